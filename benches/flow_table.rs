@@ -0,0 +1,124 @@
+// Benchmarks the flow table on synthetic traffic, and on a real capture when
+// FLUERE_BENCH_PCAP points to a pcap file:
+//
+//     FLUERE_BENCH_PCAP=large.pcap cargo bench --bench flow_table
+
+use std::{hint::black_box, thread};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use fluere::{
+    net::{FlowTable, ShardedFlowTable},
+    types::Parameters,
+};
+use pcap::{Capture, PacketHeader};
+
+const SYNTHETIC_FLOWS: u32 = 100_000;
+const PACKETS_PER_FLOW: u32 = 10;
+
+struct Trace {
+    packets: Vec<(PacketHeader, Vec<u8>)>,
+}
+
+impl Trace {
+    // UDP packets of many concurrent flows, interleaved and 1 ms apart
+    fn synthetic() -> Self {
+        let mut packets = Vec::new();
+        for round in 0..PACKETS_PER_FLOW {
+            for flow in 0..SYNTHETIC_FLOWS {
+                let time = (round * SYNTHETIC_FLOWS + flow) as i64 * 1_000;
+                packets.push((header(time), udp_packet(flow)));
+            }
+        }
+        Self { packets }
+    }
+
+    fn from_file(path: &str) -> Self {
+        let mut capture = Capture::from_file(path).expect("Failed to open the benchmark pcap");
+        let mut packets = Vec::new();
+        while let Ok(packet) = capture.next_packet() {
+            packets.push((*packet.header, packet.data.to_vec()));
+        }
+        Self { packets }
+    }
+
+    fn run(&self, table: &mut FlowTable) -> usize {
+        let mut records = 0;
+        for (header, data) in self.packets.iter() {
+            records += table.ingest(&pcap::Packet::new(header, data)).len();
+        }
+        records + table.flush().len()
+    }
+
+    fn run_sharded(&self, workers: usize) -> usize {
+        let parameters = Parameters::new(Some(false), Some(60_000), None, None, None)
+            .with_workers(Some(workers));
+        let (mut table, mut finished) =
+            ShardedFlowTable::from_parameters(&parameters).expect("Failed to start the workers");
+        let collector = thread::spawn(move || {
+            let mut records = 0;
+            while let Some(batch) = finished.blocking_recv() {
+                records += batch.len();
+            }
+            records
+        });
+
+        for (header, data) in self.packets.iter() {
+            table.dispatch(&pcap::Packet::new(header, data));
+        }
+        table.finish();
+        collector.join().expect("Record collector panicked")
+    }
+}
+
+fn header(time_us: i64) -> PacketHeader {
+    PacketHeader {
+        ts: libc::timeval {
+            tv_sec: (time_us / 1_000_000) as libc::time_t,
+            tv_usec: (time_us % 1_000_000) as libc::suseconds_t,
+        },
+        caplen: 42,
+        len: 42,
+    }
+}
+
+fn udp_packet(flow: u32) -> Vec<u8> {
+    let [_, a, b, c] = flow.to_be_bytes();
+    let mut data = vec![
+        // Ethernet: dst mac, src mac, EtherType IPv4
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+        // IPv4: Version=4, IHL=5, Total Length=28, TTL=64, Protocol=UDP
+        0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+    ];
+    data.extend_from_slice(&[10, a, b, c, 192, 168, 0, 1]);
+    data.extend_from_slice(&[0xc0, 0x00, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+    data
+}
+
+fn bench_trace(c: &mut Criterion, name: &str, trace: &Trace) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(trace.packets.len() as u64));
+    group.bench_function("ingest", |b| {
+        b.iter(|| {
+            let mut table = FlowTable::new(60_000, false);
+            black_box(trace.run(&mut table))
+        })
+    });
+    for workers in [1, 4] {
+        group.bench_function(format!("sharded_{}", workers), |b| {
+            b.iter(|| black_box(trace.run_sharded(workers)))
+        });
+    }
+    group.finish();
+}
+
+fn flow_table(c: &mut Criterion) {
+    bench_trace(c, "synthetic", &Trace::synthetic());
+
+    if let Ok(path) = std::env::var("FLUERE_BENCH_PCAP") {
+        bench_trace(c, "pcap", &Trace::from_file(&path));
+    }
+}
+
+criterion_group!(benches, flow_table);
+criterion_main!(benches);
@@ -4,3 +4,5 @@ mod types;
 pub use types::Config;
 pub use types::Plugin;
 pub use types::Plugins;
+pub use types::Sink;
+pub use types::Sinks;
@@ -4,6 +4,7 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 
 pub type Plugins = BTreeMap<String, Plugin>;
+pub type Sinks = Vec<Sink>;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Plugin {
@@ -12,7 +13,95 @@ pub struct Plugin {
     pub extra_arguments: Option<HashMap<String, String>>,
 }
 
+/// Destination flows are exported to, next to the ones given on the command line.
+///
+/// ```toml
+/// [[sinks]]
+/// type = "csv"
+/// directory = "/var/lib/fluere"
+/// name = "flows"
+/// columns = ["source", "destination", "prot_name", "first", "duration"]
+/// timestamps = "rfc3339"
+///
+/// [[sinks]]
+/// type = "jsonl"
+/// name = "flows"
+/// fields = ["source=src", "destination=dst", "prot", "first", "last"]
+/// timestamps = "rfc3339"
+/// protocol = "name"
+///
+/// [[sinks]]
+/// type = "ipfix"
+/// destination = "tcp://collector.example:4739"
+///
+/// [[sinks]]
+/// type = "stream"
+/// destination = "unix:///run/fluere/flows.sock"
+/// format = "binary"
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Sink {
+    /// CSV files, rotated at every export interval
+    Csv {
+        directory: Option<String>,
+        name: String,
+        /// Columns written, derived ones included, each optionally renamed with `column=key`;
+        /// all record columns by default
+        columns: Option<Vec<String>>,
+        /// `micros` (default), `seconds`, `rfc3339` or `local`
+        timestamps: Option<String>,
+        /// `number` (default) or `name`
+        protocol: Option<String>,
+        /// Single character, or `tab`; a comma by default
+        delimiter: Option<String>,
+        /// Whether files start with a header row, true by default
+        header: Option<bool>,
+    },
+    /// JSON Lines files, rotated at every export interval
+    Jsonl {
+        directory: Option<String>,
+        name: String,
+        /// Fields written, each optionally renamed with `field=key`; all by default
+        fields: Option<Vec<String>>,
+        /// `micros` (default), `seconds`, `rfc3339` or `local`
+        timestamps: Option<String>,
+        /// `number` (default) or `name`
+        protocol: Option<String>,
+    },
+    /// Parquet files, rotated at every export interval
+    Parquet {
+        directory: Option<String>,
+        name: String,
+        /// `string` (default) or `binary`
+        addresses: Option<String>,
+    },
+    /// SQLite database, with the flows in its `flows` table
+    Sqlite {
+        path: String,
+        /// Hours flows are kept for, all are kept by default
+        retention_hours: Option<u64>,
+    },
+    /// NetFlow v5 or v9 collector, as `host:port`
+    Netflow {
+        collector: String,
+        version: Option<u8>,
+    },
+    /// IPFIX collector (`udp://host:port`, `tcp://host:port`) or file path
+    Ipfix { destination: String },
+    /// Consumer socket (`tcp://host:port`, `unix:///path`) receiving every finished flow
+    Stream {
+        destination: String,
+        /// `json` (default) or `binary`
+        format: Option<String>,
+        /// Flows held while the consumer is down, 10000 by default
+        buffer: Option<usize>,
+    },
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Config {
     pub plugins: Plugins,
+    #[serde(default)]
+    pub sinks: Sinks,
 }
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 
 mod downloader;
@@ -17,18 +17,62 @@ use tokio::sync::{mpsc, Mutex};
 #[cfg(feature = "log")]
 use log::{debug, error, info, warn};
 
+/// Values of the enterprise elements collected along with a record and unknown to Fluere, in
+/// hexadecimal by `<enterprise>.<element>`. Plugins find them under the `extensions` key.
+pub type Extensions = BTreeMap<String, String>;
+
+// Keys of the Lua table handed to the plugins, in the order of `FluereRecord::to_vec()`
+const RECORD_KEYS: [&str; 36] = [
+    "source",
+    "destination",
+    "d_pkts",
+    "d_octets",
+    "first",
+    "last",
+    "src_port",
+    "dst_port",
+    "min_pkt",
+    "max_pkt",
+    "min_ttl",
+    "max_ttl",
+    "in_pkts",
+    "out_pkts",
+    "in_bytes",
+    "out_bytes",
+    "fin_cnt",
+    "syn_cnt",
+    "rst_cnt",
+    "psh_cnt",
+    "ack_cnt",
+    "urg_cnt",
+    "ece_cnt",
+    "cwr_cnt",
+    "ns_cnt",
+    "prot",
+    "tos",
+    "flow_label",
+    "outer_vlan",
+    "inner_vlan",
+    "tunnel_type",
+    "tunnel_src",
+    "tunnel_dst",
+    "tunnel_id",
+    "end_reason",
+    "exporter",
+];
+
 #[derive(Debug, Clone)]
 pub struct PluginManager {
     lua: Arc<Mutex<Lua>>,
-    sender: mpsc::Sender<FluereRecord>,
-    receiver: Arc<Mutex<mpsc::Receiver<FluereRecord>>>,
+    sender: mpsc::Sender<(FluereRecord, Extensions)>,
+    receiver: Arc<Mutex<mpsc::Receiver<(FluereRecord, Extensions)>>>,
     plugins: Arc<Mutex<HashSet<Cow<'static, str>>>>,
 }
 
 impl PluginManager {
     pub fn new() -> Result<Self> {
         let lua = Arc::new(Mutex::new(Lua::new()));
-        let (sender, receiver) = mpsc::channel::<FluereRecord>(100); // 100 is the channel capacity
+        let (sender, receiver) = mpsc::channel::<(FluereRecord, Extensions)>(100); // 100 is the channel capacity
         let plugins = Arc::new(Mutex::new(HashSet::new()));
 
         Ok(PluginManager {
@@ -218,54 +262,16 @@ impl PluginManager {
 
         Arc::new(Mutex::new(tokio::spawn(async move {
             let mut receiver_guard = receiver_clone.lock().await;
-            while let Some(data) = receiver_guard.recv().await {
+            while let Some((data, extensions)) = receiver_guard.recv().await {
                 let lua_clone = lua_clone.clone();
                 let plugins_clone = plugins_clone.clone();
                 tokio::task::spawn(async move {
                     let lua_guard = lua_clone.lock().await;
                     let plugins = plugins_clone.lock().await;
 
-                    let record_vec = data.to_vec();
                     let lua = &*lua_guard;
-                    let lua_table = lua.create_table().expect("Failed to create Lua table");
-
-                    // Set the values in the Lua table
-                    for (index, key) in [
-                        "source",
-                        "destination",
-                        "d_pkts",
-                        "d_octets",
-                        "first",
-                        "last",
-                        "src_port",
-                        "dst_port",
-                        "min_pkt",
-                        "max_pkt",
-                        "min_ttl",
-                        "max_ttl",
-                        "in_pkts",
-                        "out_pkts",
-                        "in_bytes",
-                        "out_bytes",
-                        "fin_cnt",
-                        "syn_cnt",
-                        "rst_cnt",
-                        "psh_cnt",
-                        "ack_cnt",
-                        "urg_cnt",
-                        "ece_cnt",
-                        "cwr_cnt",
-                        "ns_cnt",
-                        "prot",
-                        "tos",
-                    ]
-                    .iter()
-                    .enumerate()
-                    {
-                        lua_table
-                            .set(*key, record_vec[index].clone())
-                            .unwrap_or_else(|_| panic!("Failed to set key: {}", key));
-                    }
+                    let lua_table =
+                        record_table(lua, &data, &extensions).expect("Failed to create Lua table");
 
                     for plugin_name in plugins.iter() {
                         let plugin_table: mlua::Table = lua
@@ -298,7 +304,16 @@ impl PluginManager {
     }
 
     pub async fn process_flow_data(&self, data: FluereRecord) -> Result<()> {
-        self.sender.send(data).await.unwrap();
+        self.process_flow_data_with_extensions(data, Extensions::new())
+            .await
+    }
+
+    pub async fn process_flow_data_with_extensions(
+        &self,
+        data: FluereRecord,
+        extensions: Extensions,
+    ) -> Result<()> {
+        self.sender.send((data, extensions)).await.unwrap();
         Ok(())
     }
 
@@ -336,9 +351,53 @@ impl PluginManager {
     }
 }
 
+// Lua table of a record, with the values set under their field names and the extensions in
+// a table of their own
+fn record_table<'lua>(
+    lua: &'lua Lua,
+    record: &FluereRecord,
+    extensions: &Extensions,
+) -> Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    for (key, value) in RECORD_KEYS.iter().zip(record.to_vec()) {
+        table.set(*key, value)?;
+    }
+    table.set("extensions", lua.create_table_from(extensions.clone())?)?;
+    Ok(table)
+}
+
 impl Drop for PluginManager {
     fn drop(&mut self) {
         drop(self.plugins.lock());
         drop(self.lua.lock());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_every_record_value_has_a_key() {
+        assert_eq!(RECORD_KEYS.len(), FluereRecord::default().to_vec().len());
+    }
+
+    #[test]
+    fn test_record_table_has_the_exporter_and_extensions() {
+        let lua = Lua::new();
+        let record = FluereRecord {
+            d_pkts: 3,
+            exporter: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            ..FluereRecord::default()
+        };
+        let extensions = Extensions::from([("2959.1".to_string(), "6677".to_string())]);
+        let table = record_table(&lua, &record, &extensions).unwrap();
+
+        assert_eq!(table.get::<_, String>("d_pkts").unwrap(), "3");
+        assert_eq!(table.get::<_, String>("exporter").unwrap(), "192.0.2.1");
+        let extensions: mlua::Table = table.get("extensions").unwrap();
+        assert_eq!(extensions.get::<_, String>("2959.1").unwrap(), "6677");
+    }
+}
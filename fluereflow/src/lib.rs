@@ -11,3 +11,9 @@ pub use types::FluereHeader;
 // The FluereRecord data structure represents a single record in a NetFlow flow.
 // It includes fields for the source and destination IP addresses, the source and destination ports, the protocol, and other information about the record.
 pub use types::FluereRecord;
+// The FlowEndReason enum describes why a FluereRecord was exported.
+// It distinguishes timeouts, TCP connection teardown and flows that were still active when the capture ended.
+pub use types::FlowEndReason;
+// The TunnelType enum describes the encapsulation a FluereRecord was carried in.
+// The outer tunnel endpoints and tunnel ID are kept on the record next to it.
+pub use types::TunnelType;
@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// Why a flow record was exported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FlowEndReason {
+    /// The flow is still active, or the reason is not known.
+    #[default]
+    Unknown,
+    /// No packet was seen for longer than the inactive timeout.
+    IdleTimeout,
+    /// The flow was active for longer than the active timeout and will continue in a new record.
+    ActiveTimeout,
+    /// The TCP connection was closed by a FIN handshake.
+    TcpFin,
+    /// The TCP connection was aborted by a RST.
+    TcpReset,
+    /// The flow was still active when the capture ended.
+    ForcedEnd,
+    /// The flow was exported early to make room in a full flow table.
+    LackOfResources,
+}
+
+impl FlowEndReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlowEndReason::Unknown => "unknown",
+            FlowEndReason::IdleTimeout => "idle_timeout",
+            FlowEndReason::ActiveTimeout => "active_timeout",
+            FlowEndReason::TcpFin => "tcp_fin",
+            FlowEndReason::TcpReset => "tcp_rst",
+            FlowEndReason::ForcedEnd => "forced_end",
+            FlowEndReason::LackOfResources => "lack_of_resources",
+        }
+    }
+}
+
+impl fmt::Display for FlowEndReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -1,4 +1,6 @@
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
+
+use super::{FlowEndReason, TunnelType};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FluereFlow {
@@ -18,9 +20,9 @@ pub struct FluereHeader {
     pub unix_secs: u32,
 }
 impl FluereHeader {
-    pub fn new(_version: u16, count: u16, sys_uptime: u32, unix_secs: u32) -> FluereHeader {
+    pub fn new(version: u16, count: u16, sys_uptime: u32, unix_secs: u32) -> FluereHeader {
         FluereHeader {
-            version: 1,
+            version,
             count,
             sys_uptime,
             unix_secs,
@@ -57,6 +59,16 @@ pub struct FluereRecord {
     pub ns_cnt: u32,
     pub prot: u8,
     pub tos: u8,
+    pub flow_label: u32,
+    pub outer_vlan: u16,
+    pub inner_vlan: u16,
+    pub tunnel_type: TunnelType,
+    pub tunnel_src: IpAddr,
+    pub tunnel_dst: IpAddr,
+    pub tunnel_id: u32,
+    pub end_reason: FlowEndReason,
+    // Device the flow was collected from, unspecified for captured flows
+    pub exporter: IpAddr,
 }
 
 impl FluereRecord {
@@ -117,6 +129,15 @@ impl FluereRecord {
             ns_cnt,
             prot,
             tos,
+            flow_label: 0,
+            outer_vlan: 0,
+            inner_vlan: 0,
+            tunnel_type: TunnelType::default(),
+            tunnel_src: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tunnel_dst: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            tunnel_id: 0,
+            end_reason: FlowEndReason::default(),
+            exporter: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
         }
     }
     pub fn to_vec(&self) -> Vec<String> {
@@ -148,6 +169,51 @@ impl FluereRecord {
             self.ns_cnt.to_string(),
             self.prot.to_string(),
             self.tos.to_string(),
+            self.flow_label.to_string(),
+            self.outer_vlan.to_string(),
+            self.inner_vlan.to_string(),
+            self.tunnel_type.to_string(),
+            self.tunnel_src.to_string(),
+            self.tunnel_dst.to_string(),
+            self.tunnel_id.to_string(),
+            self.end_reason.to_string(),
+            self.exporter.to_string(),
         ]
     }
 }
+
+/// Record with every counter at zero, and unspecified IPv4 addresses.
+impl Default for FluereRecord {
+    fn default() -> Self {
+        let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        FluereRecord::new(
+            unspecified,
+            unspecified,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        )
+    }
+}
@@ -1,5 +1,9 @@
+mod end_reason;
 mod fluereflow;
+mod tunnel;
 
+pub use self::end_reason::FlowEndReason;
 pub use self::fluereflow::FluereFlow;
 pub use self::fluereflow::FluereHeader;
 pub use self::fluereflow::FluereRecord;
+pub use self::tunnel::TunnelType;
@@ -0,0 +1,44 @@
+use std::fmt;
+
+/// Encapsulation a flow was carried in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TunnelType {
+    /// The flow was not tunnelled.
+    #[default]
+    None,
+    /// VXLAN, RFC 7348.
+    Vxlan,
+    /// GENEVE, RFC 8926.
+    Geneve,
+    /// GRE, RFC 2784 and RFC 2890.
+    Gre,
+    /// NVGRE, Ethernet over GRE with a virtual subnet ID, RFC 7637.
+    Nvgre,
+    /// IPv4 carried directly in IP (protocol 4).
+    IpInIp,
+    /// IPv6 carried directly in IP (protocol 41), such as 6in4.
+    Ipv6InIp,
+    /// MPLS label stack.
+    Mpls,
+}
+
+impl TunnelType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TunnelType::None => "none",
+            TunnelType::Vxlan => "vxlan",
+            TunnelType::Geneve => "geneve",
+            TunnelType::Gre => "gre",
+            TunnelType::Nvgre => "nvgre",
+            TunnelType::IpInIp => "ipip",
+            TunnelType::Ipv6InIp => "6in4",
+            TunnelType::Mpls => "mpls",
+        }
+    }
+}
+
+impl fmt::Display for TunnelType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
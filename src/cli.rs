@@ -2,7 +2,10 @@ use std::process::exit;
 
 use crate::{
     FluereError,
-    types::{Args, Files, Parameters},
+    types::{
+        AddressFormat, Args, CompressionFormat, EvictionPolicy, ExportFormat, Files, Parameters,
+        ProtocolFormat, StreamFormat, TimestampFormat,
+    },
 };
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
@@ -54,6 +57,13 @@ pub fn cli_template() -> Command {
                         .short('t')
                         .long("timeout"),
                 )
+                .arg(
+                    Arg::new("active_timeout")
+                        .help("Set flow active timeout, in milliseconds (0: infinite)")
+                        .default_value("0")
+                        .short('a')
+                        .long("active-timeout"),
+                )
                 .arg(
                     Arg::new("useMACaddress")
                         .help("Set use MAC address on Key value [default: false]")
@@ -61,13 +71,198 @@ pub fn cli_template() -> Command {
                         .long("useMAC")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("midstream")
+                        .help("Track TCP connections first seen without a SYN [default: false]")
+                        .short('m')
+                        .long("midstream")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("use_ipv6")
-                        .help("support ipv6 [default: false]")
+                        .help("Create flows for IPv6 packets [default: false]")
                         .short('6')
                         .long("ipv6")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("tunnel_depth")
+                        .help("Set how many tunnel layers (VXLAN, GENEVE, GRE, IP-in-IP, MPLS) to decapsulate (0: off)")
+                        .default_value("1")
+                        .short('T')
+                        .long("tunnel-depth"),
+                )
+                .arg(
+                    Arg::new("max_flows")
+                        .help("Set the maximum number of active flows (0: unlimited)")
+                        .default_value("0")
+                        .long("max-flows"),
+                )
+                .arg(
+                    Arg::new("max_memory")
+                        .help("Set the memory budget of active flows, in megabytes (0: unlimited)")
+                        .default_value("0")
+                        .long("max-memory"),
+                )
+                .arg(
+                    Arg::new("eviction")
+                        .help("Set which flows are exported early when the flow table is full")
+                        .default_value("oldest")
+                        .value_parser(["oldest", "idle", "least-active"])
+                        .long("eviction"),
+                )
+                .arg(
+                    Arg::new("workers")
+                        .help("Set the number of flow table worker threads")
+                        .default_value("1")
+                        .short('w')
+                        .long("workers"),
+                )
+                .arg(
+                    Arg::new("netflow")
+                        .help("Send flows to a NetFlow collector, given as host:port")
+                        .long("netflow"),
+                )
+                .arg(
+                    Arg::new("netflow_version")
+                        .help("Set the NetFlow version sent to the collector")
+                        .default_value("5")
+                        .value_parser(["5", "9"])
+                        .long("netflow-version"),
+                )
+                .arg(
+                    Arg::new("ipfix")
+                        .help("Send flows to an IPFIX collector (udp://host:port, tcp://host:port) or file")
+                        .long("ipfix"),
+                )
+                .arg(
+                    Arg::new("ipfix_enterprise")
+                        .help("Private enterprise number of the Fluere IPFIX elements (default: the RFC 5612 documentation number, a placeholder)")
+                        .default_value("32473")
+                        .long("ipfix-enterprise"),
+                )
+                .arg(
+                    Arg::new("sqlite")
+                        .help("Store flows in a SQLite database file")
+                        .long("sqlite"),
+                )
+                .arg(
+                    Arg::new("sqlite_retention")
+                        .help("Set how long the SQLite database keeps flows, in hours (0: forever)")
+                        .default_value("0")
+                        .long("sqlite-retention"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Set the format of the exported flow records")
+                        .default_value("csv")
+                        .value_parser(["csv", "jsonl", "parquet"])
+                        .long("format"),
+                )
+                .arg(
+                    Arg::new("fields")
+                        .help("Select the CSV columns or JSON Lines fields, each optionally renamed with field=key, derived duration, prot_name and bytes_per_packet included (e.g. source=src,destination=dst,prot)")
+                        .long("fields"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .help("Set the CSV delimiter, a single character or tab")
+                        .default_value(",")
+                        .long("delimiter"),
+                )
+                .arg(
+                    Arg::new("no_header")
+                        .help("Write CSV files without a header row")
+                        .action(ArgAction::SetTrue)
+                        .long("no-header"),
+                )
+                .arg(
+                    Arg::new("timestamp_format")
+                        .help("Set how flow times are written: epoch microseconds or seconds, ISO 8601 in UTC (rfc3339) or local time")
+                        .default_value("micros")
+                        .value_parser(["micros", "seconds", "rfc3339", "local"])
+                        .long("timestamp-format"),
+                )
+                .arg(
+                    Arg::new("protocol_format")
+                        .help("Set how the protocol is written in CSV and JSON Lines records")
+                        .default_value("number")
+                        .value_parser(["number", "name"])
+                        .long("protocol-format"),
+                )
+                .arg(
+                    Arg::new("address_format")
+                        .help("Set how Parquet exports store IP addresses")
+                        .default_value("string")
+                        .value_parser(["string", "binary"])
+                        .long("address-format"),
+                )
+                .arg(
+                    Arg::new("stream")
+                        .help("Stream flows to a consumer socket (tcp://host:port, unix:///path)")
+                        .long("stream"),
+                )
+                .arg(
+                    Arg::new("stream_format")
+                        .help("Set the encoding of the streamed flows")
+                        .default_value("json")
+                        .value_parser(["json", "binary"])
+                        .long("stream-format"),
+                )
+                .arg(
+                    Arg::new("stream_buffer")
+                        .help("Set how many flows are held while the stream consumer is down")
+                        .default_value("10000")
+                        .long("stream-buffer"),
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .help("Set the directory flow record files are written to")
+                        .default_value("./output")
+                        .long("output-dir"),
+                )
+                .arg(
+                    Arg::new("file_template")
+                        .help("Set the strftime pattern of rotated file names, with {name}, {hostname} and {interface} (e.g. {hostname}_{interface}_%Y%m%d_%H%M%S)")
+                        .long("file-template"),
+                )
+                .arg(
+                    Arg::new("rotate_size")
+                        .help("Start a new file past this size, in megabytes (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-size"),
+                )
+                .arg(
+                    Arg::new("rotate_records")
+                        .help("Start a new file past this number of flows (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-records"),
+                )
+                .arg(
+                    Arg::new("compress")
+                        .help("Compress finished files")
+                        .default_value("none")
+                        .value_parser(["none", "gzip", "zstd"])
+                        .long("compress"),
+                )
+                .arg(
+                    Arg::new("max_files")
+                        .help("Set how many finished files are kept, the oldest deleted first (0: all)")
+                        .default_value("0")
+                        .long("max-files"),
+                )
+                .arg(
+                    Arg::new("max_age")
+                        .help("Delete finished files older than this, in hours (0: never)")
+                        .default_value("0")
+                        .long("max-age"),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .help("Write the flow records to the standard output instead of files, logging to the standard error")
+                        .action(ArgAction::SetTrue)
+                        .long("stdout"),
+                )
                 .arg(
                     Arg::new("interval")
                         .help("Set export interval, in milliseconds")
@@ -95,17 +290,24 @@ pub fn cli_template() -> Command {
                 .about("Convet pcap files to netflow")
                 .arg(
                     Arg::new("file")
-                        .help("Name of the input pcap file [Required]")
+                        .help("Input pcap or pcapng files, directories or glob patterns, merged in time order, - for stdin; gzip, zstd and xz are decompressed [Required]")
                         .short('f')
                         .long("file")
+                        .num_args(1..)
+                        .action(ArgAction::Append)
                         .required(true),
                 )
+                .arg(
+                    Arg::new("window")
+                        .help("Split the results into windows of packet time, in milliseconds (0: one result)")
+                        .default_value("0")
+                        .long("window"),
+                )
                 .arg(
                     Arg::new("csv")
-                        .help("Title of the exported csv file")
+                        .help("Title of the exported file (default: <capture>_converted)")
                         .short('c')
-                        .long("csv")
-                        .default_value("output"),
+                        .long("csv"),
                 )
                 .arg(
                     Arg::new("timeout")
@@ -114,6 +316,13 @@ pub fn cli_template() -> Command {
                         .short('t')
                         .long("timeout"),
                 )
+                .arg(
+                    Arg::new("active_timeout")
+                        .help("Set flow active timeout, in milliseconds (0: infinite)")
+                        .default_value("0")
+                        .short('a')
+                        .long("active-timeout"),
+                )
                 .arg(
                     Arg::new("useMACaddress")
                         .help("Set use MAC address on Key value [default: false]")
@@ -121,13 +330,198 @@ pub fn cli_template() -> Command {
                         .long("useMAC")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("midstream")
+                        .help("Track TCP connections first seen without a SYN [default: false]")
+                        .short('m')
+                        .long("midstream")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("use_ipv6")
-                        .help("support ipv6 [default: false]")
+                        .help("Create flows for IPv6 packets [default: false]")
                         .short('6')
                         .long("ipv6")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("tunnel_depth")
+                        .help("Set how many tunnel layers (VXLAN, GENEVE, GRE, IP-in-IP, MPLS) to decapsulate (0: off)")
+                        .default_value("1")
+                        .short('T')
+                        .long("tunnel-depth"),
+                )
+                .arg(
+                    Arg::new("max_flows")
+                        .help("Set the maximum number of active flows (0: unlimited)")
+                        .default_value("0")
+                        .long("max-flows"),
+                )
+                .arg(
+                    Arg::new("max_memory")
+                        .help("Set the memory budget of active flows, in megabytes (0: unlimited)")
+                        .default_value("0")
+                        .long("max-memory"),
+                )
+                .arg(
+                    Arg::new("eviction")
+                        .help("Set which flows are exported early when the flow table is full")
+                        .default_value("oldest")
+                        .value_parser(["oldest", "idle", "least-active"])
+                        .long("eviction"),
+                )
+                .arg(
+                    Arg::new("workers")
+                        .help("Set the number of flow table worker threads")
+                        .default_value("1")
+                        .short('w')
+                        .long("workers"),
+                )
+                .arg(
+                    Arg::new("netflow")
+                        .help("Send flows to a NetFlow collector, given as host:port")
+                        .long("netflow"),
+                )
+                .arg(
+                    Arg::new("netflow_version")
+                        .help("Set the NetFlow version sent to the collector")
+                        .default_value("5")
+                        .value_parser(["5", "9"])
+                        .long("netflow-version"),
+                )
+                .arg(
+                    Arg::new("ipfix")
+                        .help("Send flows to an IPFIX collector (udp://host:port, tcp://host:port) or file")
+                        .long("ipfix"),
+                )
+                .arg(
+                    Arg::new("ipfix_enterprise")
+                        .help("Private enterprise number of the Fluere IPFIX elements (default: the RFC 5612 documentation number, a placeholder)")
+                        .default_value("32473")
+                        .long("ipfix-enterprise"),
+                )
+                .arg(
+                    Arg::new("sqlite")
+                        .help("Store flows in a SQLite database file")
+                        .long("sqlite"),
+                )
+                .arg(
+                    Arg::new("sqlite_retention")
+                        .help("Set how long the SQLite database keeps flows, in hours (0: forever)")
+                        .default_value("0")
+                        .long("sqlite-retention"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Set the format of the exported flow records")
+                        .default_value("csv")
+                        .value_parser(["csv", "jsonl", "parquet"])
+                        .long("format"),
+                )
+                .arg(
+                    Arg::new("fields")
+                        .help("Select the CSV columns or JSON Lines fields, each optionally renamed with field=key, derived duration, prot_name and bytes_per_packet included (e.g. source=src,destination=dst,prot)")
+                        .long("fields"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .help("Set the CSV delimiter, a single character or tab")
+                        .default_value(",")
+                        .long("delimiter"),
+                )
+                .arg(
+                    Arg::new("no_header")
+                        .help("Write CSV files without a header row")
+                        .action(ArgAction::SetTrue)
+                        .long("no-header"),
+                )
+                .arg(
+                    Arg::new("timestamp_format")
+                        .help("Set how flow times are written: epoch microseconds or seconds, ISO 8601 in UTC (rfc3339) or local time")
+                        .default_value("micros")
+                        .value_parser(["micros", "seconds", "rfc3339", "local"])
+                        .long("timestamp-format"),
+                )
+                .arg(
+                    Arg::new("protocol_format")
+                        .help("Set how the protocol is written in CSV and JSON Lines records")
+                        .default_value("number")
+                        .value_parser(["number", "name"])
+                        .long("protocol-format"),
+                )
+                .arg(
+                    Arg::new("address_format")
+                        .help("Set how Parquet exports store IP addresses")
+                        .default_value("string")
+                        .value_parser(["string", "binary"])
+                        .long("address-format"),
+                )
+                .arg(
+                    Arg::new("stream")
+                        .help("Stream flows to a consumer socket (tcp://host:port, unix:///path)")
+                        .long("stream"),
+                )
+                .arg(
+                    Arg::new("stream_format")
+                        .help("Set the encoding of the streamed flows")
+                        .default_value("json")
+                        .value_parser(["json", "binary"])
+                        .long("stream-format"),
+                )
+                .arg(
+                    Arg::new("stream_buffer")
+                        .help("Set how many flows are held while the stream consumer is down")
+                        .default_value("10000")
+                        .long("stream-buffer"),
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .help("Set the directory flow record files are written to")
+                        .default_value("./output")
+                        .long("output-dir"),
+                )
+                .arg(
+                    Arg::new("file_template")
+                        .help("Set the strftime pattern of rotated file names, with {name}, {hostname} and {interface} (e.g. {hostname}_{interface}_%Y%m%d_%H%M%S)")
+                        .long("file-template"),
+                )
+                .arg(
+                    Arg::new("rotate_size")
+                        .help("Start a new file past this size, in megabytes (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-size"),
+                )
+                .arg(
+                    Arg::new("rotate_records")
+                        .help("Start a new file past this number of flows (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-records"),
+                )
+                .arg(
+                    Arg::new("compress")
+                        .help("Compress finished files")
+                        .default_value("none")
+                        .value_parser(["none", "gzip", "zstd"])
+                        .long("compress"),
+                )
+                .arg(
+                    Arg::new("max_files")
+                        .help("Set how many finished files are kept, the oldest deleted first (0: all)")
+                        .default_value("0")
+                        .long("max-files"),
+                )
+                .arg(
+                    Arg::new("max_age")
+                        .help("Delete finished files older than this, in hours (0: never)")
+                        .default_value("0")
+                        .long("max-age"),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .help("Write the flow records to the standard output instead of files, logging to the standard error")
+                        .action(ArgAction::SetTrue)
+                        .long("stdout"),
+                )
                 .arg(
                     Arg::new("verbose")
                         .help("Set verbosity level")
@@ -174,6 +568,13 @@ pub fn cli_template() -> Command {
                         .short('t')
                         .long("timeout"),
                 )
+                .arg(
+                    Arg::new("active_timeout")
+                        .help("Set flow active timeout, in milliseconds (0: infinite)")
+                        .default_value("0")
+                        .short('a')
+                        .long("active-timeout"),
+                )
                 .arg(
                     Arg::new("useMACaddress")
                         .help("Set use MAC address on Key value [default: false]")
@@ -181,13 +582,185 @@ pub fn cli_template() -> Command {
                         .long("useMAC")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("midstream")
+                        .help("Track TCP connections first seen without a SYN [default: false]")
+                        .short('m')
+                        .long("midstream")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("use_ipv6")
-                        .help("support ipv6 [default: false]")
+                        .help("Create flows for IPv6 packets [default: false]")
                         .short('6')
                         .long("ipv6")
                         .action(ArgAction::SetTrue),
                 )
+                .arg(
+                    Arg::new("tunnel_depth")
+                        .help("Set how many tunnel layers (VXLAN, GENEVE, GRE, IP-in-IP, MPLS) to decapsulate (0: off)")
+                        .default_value("1")
+                        .short('T')
+                        .long("tunnel-depth"),
+                )
+                .arg(
+                    Arg::new("max_flows")
+                        .help("Set the maximum number of active flows (0: unlimited)")
+                        .default_value("0")
+                        .long("max-flows"),
+                )
+                .arg(
+                    Arg::new("max_memory")
+                        .help("Set the memory budget of active flows, in megabytes (0: unlimited)")
+                        .default_value("0")
+                        .long("max-memory"),
+                )
+                .arg(
+                    Arg::new("eviction")
+                        .help("Set which flows are exported early when the flow table is full")
+                        .default_value("oldest")
+                        .value_parser(["oldest", "idle", "least-active"])
+                        .long("eviction"),
+                )
+                .arg(
+                    Arg::new("netflow")
+                        .help("Send flows to a NetFlow collector, given as host:port")
+                        .long("netflow"),
+                )
+                .arg(
+                    Arg::new("netflow_version")
+                        .help("Set the NetFlow version sent to the collector")
+                        .default_value("5")
+                        .value_parser(["5", "9"])
+                        .long("netflow-version"),
+                )
+                .arg(
+                    Arg::new("ipfix")
+                        .help("Send flows to an IPFIX collector (udp://host:port, tcp://host:port) or file")
+                        .long("ipfix"),
+                )
+                .arg(
+                    Arg::new("ipfix_enterprise")
+                        .help("Private enterprise number of the Fluere IPFIX elements (default: the RFC 5612 documentation number, a placeholder)")
+                        .default_value("32473")
+                        .long("ipfix-enterprise"),
+                )
+                .arg(
+                    Arg::new("sqlite")
+                        .help("Store flows in a SQLite database file")
+                        .long("sqlite"),
+                )
+                .arg(
+                    Arg::new("sqlite_retention")
+                        .help("Set how long the SQLite database keeps flows, in hours (0: forever)")
+                        .default_value("0")
+                        .long("sqlite-retention"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Set the format of the exported flow records")
+                        .default_value("csv")
+                        .value_parser(["csv", "jsonl", "parquet"])
+                        .long("format"),
+                )
+                .arg(
+                    Arg::new("fields")
+                        .help("Select the CSV columns or JSON Lines fields, each optionally renamed with field=key, derived duration, prot_name and bytes_per_packet included (e.g. source=src,destination=dst,prot)")
+                        .long("fields"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .help("Set the CSV delimiter, a single character or tab")
+                        .default_value(",")
+                        .long("delimiter"),
+                )
+                .arg(
+                    Arg::new("no_header")
+                        .help("Write CSV files without a header row")
+                        .action(ArgAction::SetTrue)
+                        .long("no-header"),
+                )
+                .arg(
+                    Arg::new("timestamp_format")
+                        .help("Set how flow times are written: epoch microseconds or seconds, ISO 8601 in UTC (rfc3339) or local time")
+                        .default_value("micros")
+                        .value_parser(["micros", "seconds", "rfc3339", "local"])
+                        .long("timestamp-format"),
+                )
+                .arg(
+                    Arg::new("protocol_format")
+                        .help("Set how the protocol is written in CSV and JSON Lines records")
+                        .default_value("number")
+                        .value_parser(["number", "name"])
+                        .long("protocol-format"),
+                )
+                .arg(
+                    Arg::new("address_format")
+                        .help("Set how Parquet exports store IP addresses")
+                        .default_value("string")
+                        .value_parser(["string", "binary"])
+                        .long("address-format"),
+                )
+                .arg(
+                    Arg::new("stream")
+                        .help("Stream flows to a consumer socket (tcp://host:port, unix:///path)")
+                        .long("stream"),
+                )
+                .arg(
+                    Arg::new("stream_format")
+                        .help("Set the encoding of the streamed flows")
+                        .default_value("json")
+                        .value_parser(["json", "binary"])
+                        .long("stream-format"),
+                )
+                .arg(
+                    Arg::new("stream_buffer")
+                        .help("Set how many flows are held while the stream consumer is down")
+                        .default_value("10000")
+                        .long("stream-buffer"),
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .help("Set the directory flow record files are written to")
+                        .default_value("./output")
+                        .long("output-dir"),
+                )
+                .arg(
+                    Arg::new("file_template")
+                        .help("Set the strftime pattern of rotated file names, with {name}, {hostname} and {interface} (e.g. {hostname}_{interface}_%Y%m%d_%H%M%S)")
+                        .long("file-template"),
+                )
+                .arg(
+                    Arg::new("rotate_size")
+                        .help("Start a new file past this size, in megabytes (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-size"),
+                )
+                .arg(
+                    Arg::new("rotate_records")
+                        .help("Start a new file past this number of flows (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-records"),
+                )
+                .arg(
+                    Arg::new("compress")
+                        .help("Compress finished files")
+                        .default_value("none")
+                        .value_parser(["none", "gzip", "zstd"])
+                        .long("compress"),
+                )
+                .arg(
+                    Arg::new("max_files")
+                        .help("Set how many finished files are kept, the oldest deleted first (0: all)")
+                        .default_value("0")
+                        .long("max-files"),
+                )
+                .arg(
+                    Arg::new("max_age")
+                        .help("Delete finished files older than this, in hours (0: never)")
+                        .default_value("0")
+                        .long("max-age"),
+                )
                 .arg(
                     Arg::new("interval")
                         .help("Set export interval, in milliseconds")
@@ -263,6 +836,249 @@ pub fn cli_template() -> Command {
                         .long("verbose"), // 0: Error, 1: Warn, 2: Info, 3: Debug, 4: Trace
                 ),
         )
+        .subcommand(
+            Command::new("collect")
+                .about("Collect flows exported by NetFlow v5, v9, IPFIX and sFlow devices")
+                .arg(
+                    Arg::new("csv")
+                        .help("Title of the exported csv file")
+                        .short('c')
+                        .long("csv")
+                        .default_value("output"),
+                )
+                .arg(
+                    Arg::new("listen")
+                        .help("Set the address exported flows are received on, as host:port")
+                        .default_value("0.0.0.0:2055")
+                        .short('L')
+                        .long("listen"),
+                )
+                .arg(
+                    Arg::new("listen_tcp")
+                        .help("Also accept IPFIX over TCP on this address, as host:port")
+                        .long("listen-tcp"),
+                )
+                .arg(
+                    Arg::new("duration")
+                        .help("Set collection duration, in milliseconds (0: infinite)")
+                        .default_value("0")
+                        .short('d')
+                        .long("duration"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .help("Set the timeout of flows built from sFlow samples, in milliseconds (0: infinite)")
+                        .default_value("600000")
+                        .short('t')
+                        .long("timeout"),
+                )
+                .arg(
+                    Arg::new("active_timeout")
+                        .help("Set flow active timeout, in milliseconds (0: infinite)")
+                        .default_value("0")
+                        .short('a')
+                        .long("active-timeout"),
+                )
+                .arg(
+                    Arg::new("useMACaddress")
+                        .help("Set use MAC address on Key value [default: false]")
+                        .short('M')
+                        .long("useMAC")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("use_ipv6")
+                        .help("Create flows for sampled IPv6 packets [default: false]")
+                        .short('6')
+                        .long("ipv6")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("tunnel_depth")
+                        .help("Set how many tunnel layers (VXLAN, GENEVE, GRE, IP-in-IP, MPLS) to decapsulate (0: off)")
+                        .default_value("1")
+                        .short('T')
+                        .long("tunnel-depth"),
+                )
+                .arg(
+                    Arg::new("max_flows")
+                        .help("Set the maximum number of active flows (0: unlimited)")
+                        .default_value("0")
+                        .long("max-flows"),
+                )
+                .arg(
+                    Arg::new("max_memory")
+                        .help("Set the memory budget of active flows, in megabytes (0: unlimited)")
+                        .default_value("0")
+                        .long("max-memory"),
+                )
+                .arg(
+                    Arg::new("eviction")
+                        .help("Set which flows are exported early when the flow table is full")
+                        .default_value("oldest")
+                        .value_parser(["oldest", "idle", "least-active"])
+                        .long("eviction"),
+                )
+                .arg(
+                    Arg::new("netflow")
+                        .help("Send flows to a NetFlow collector, given as host:port")
+                        .long("netflow"),
+                )
+                .arg(
+                    Arg::new("netflow_version")
+                        .help("Set the NetFlow version sent to the collector")
+                        .default_value("5")
+                        .value_parser(["5", "9"])
+                        .long("netflow-version"),
+                )
+                .arg(
+                    Arg::new("ipfix")
+                        .help("Send flows to an IPFIX collector (udp://host:port, tcp://host:port) or file")
+                        .long("ipfix"),
+                )
+                .arg(
+                    Arg::new("ipfix_enterprise")
+                        .help("Private enterprise number of the Fluere IPFIX elements (default: the RFC 5612 documentation number, a placeholder)")
+                        .default_value("32473")
+                        .long("ipfix-enterprise"),
+                )
+                .arg(
+                    Arg::new("sqlite")
+                        .help("Store flows in a SQLite database file")
+                        .long("sqlite"),
+                )
+                .arg(
+                    Arg::new("sqlite_retention")
+                        .help("Set how long the SQLite database keeps flows, in hours (0: forever)")
+                        .default_value("0")
+                        .long("sqlite-retention"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .help("Set the format of the exported flow records")
+                        .default_value("csv")
+                        .value_parser(["csv", "jsonl", "parquet"])
+                        .long("format"),
+                )
+                .arg(
+                    Arg::new("fields")
+                        .help("Select the CSV columns or JSON Lines fields, each optionally renamed with field=key, derived duration, prot_name and bytes_per_packet included (e.g. source=src,destination=dst,prot)")
+                        .long("fields"),
+                )
+                .arg(
+                    Arg::new("delimiter")
+                        .help("Set the CSV delimiter, a single character or tab")
+                        .default_value(",")
+                        .long("delimiter"),
+                )
+                .arg(
+                    Arg::new("no_header")
+                        .help("Write CSV files without a header row")
+                        .action(ArgAction::SetTrue)
+                        .long("no-header"),
+                )
+                .arg(
+                    Arg::new("timestamp_format")
+                        .help("Set how flow times are written: epoch microseconds or seconds, ISO 8601 in UTC (rfc3339) or local time")
+                        .default_value("micros")
+                        .value_parser(["micros", "seconds", "rfc3339", "local"])
+                        .long("timestamp-format"),
+                )
+                .arg(
+                    Arg::new("protocol_format")
+                        .help("Set how the protocol is written in CSV and JSON Lines records")
+                        .default_value("number")
+                        .value_parser(["number", "name"])
+                        .long("protocol-format"),
+                )
+                .arg(
+                    Arg::new("address_format")
+                        .help("Set how Parquet exports store IP addresses")
+                        .default_value("string")
+                        .value_parser(["string", "binary"])
+                        .long("address-format"),
+                )
+                .arg(
+                    Arg::new("stream")
+                        .help("Stream flows to a consumer socket (tcp://host:port, unix:///path)")
+                        .long("stream"),
+                )
+                .arg(
+                    Arg::new("stream_format")
+                        .help("Set the encoding of the streamed flows")
+                        .default_value("json")
+                        .value_parser(["json", "binary"])
+                        .long("stream-format"),
+                )
+                .arg(
+                    Arg::new("stream_buffer")
+                        .help("Set how many flows are held while the stream consumer is down")
+                        .default_value("10000")
+                        .long("stream-buffer"),
+                )
+                .arg(
+                    Arg::new("output_dir")
+                        .help("Set the directory flow record files are written to")
+                        .default_value("./output")
+                        .long("output-dir"),
+                )
+                .arg(
+                    Arg::new("file_template")
+                        .help("Set the strftime pattern of rotated file names, with {name}, {hostname} and {interface} (e.g. {hostname}_{interface}_%Y%m%d_%H%M%S)")
+                        .long("file-template"),
+                )
+                .arg(
+                    Arg::new("rotate_size")
+                        .help("Start a new file past this size, in megabytes (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-size"),
+                )
+                .arg(
+                    Arg::new("rotate_records")
+                        .help("Start a new file past this number of flows (0: no limit)")
+                        .default_value("0")
+                        .long("rotate-records"),
+                )
+                .arg(
+                    Arg::new("compress")
+                        .help("Compress finished files")
+                        .default_value("none")
+                        .value_parser(["none", "gzip", "zstd"])
+                        .long("compress"),
+                )
+                .arg(
+                    Arg::new("max_files")
+                        .help("Set how many finished files are kept, the oldest deleted first (0: all)")
+                        .default_value("0")
+                        .long("max-files"),
+                )
+                .arg(
+                    Arg::new("max_age")
+                        .help("Delete finished files older than this, in hours (0: never)")
+                        .default_value("0")
+                        .long("max-age"),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .help("Write the flow records to the standard output instead of files, logging to the standard error")
+                        .action(ArgAction::SetTrue)
+                        .long("stdout"),
+                )
+                .arg(
+                    Arg::new("interval")
+                        .help("Set export interval, in milliseconds")
+                        .default_value("1800000")
+                        .short('I')
+                        .long("interval"),
+                )
+                .arg(
+                    Arg::new("verbose")
+                        .help("Set verbosity level") 
+                        .default_value("2")
+                        .short('v')
+                        .long("verbose"), // 0: Error, 1: Warning, 2: Info, 3: Debug 4: Trace
+                ),
+        )
 }
 
 pub async fn handle_mode(mode: &str, args: &ArgMatches) -> Result<(Args, u8), FluereError> {
@@ -270,7 +1086,7 @@ pub async fn handle_mode(mode: &str, args: &ArgMatches) -> Result<(Args, u8), Fl
         .get_one::<String>("verbose")
         .map_or(0, |v| v.parse::<u8>().unwrap_or(0));
 
-    if mode != "offline" && args.get_flag("list") {
+    if matches!(mode, "online" | "live" | "pcap") && args.get_flag("list") {
         println!("List of network interfaces");
         println!("--------------------------");
         let devices = Device::list().expect("Failed to list network devices");
@@ -289,6 +1105,7 @@ pub async fn handle_mode(mode: &str, args: &ArgMatches) -> Result<(Args, u8), Fl
         "online" | "live" => parse_online_live_args(args, mode),
         "offline" => parse_offline_args(args),
         "pcap" => parse_pcap_args(args),
+        "collect" => parse_collect_args(args),
         _ => unreachable!(),
     };
 
@@ -297,6 +1114,8 @@ pub async fn handle_mode(mode: &str, args: &ArgMatches) -> Result<(Args, u8), Fl
 
 fn parse_online_live_args(args: &clap::ArgMatches, _mode: &str) -> Args {
     let use_mac = args.get_flag("useMACaddress");
+    let midstream = args.get_flag("midstream");
+    let use_ipv6 = args.get_flag("use_ipv6");
     let csv = args
         .get_one::<String>("csv")
         .expect("CSV file not specified")
@@ -305,11 +1124,134 @@ fn parse_online_live_args(args: &clap::ArgMatches, _mode: &str) -> Args {
         .get_one::<String>("interface")
         .expect("Network interface not specified")
         .to_string();
+    let netflow = args.get_one::<String>("netflow").cloned();
+    let ipfix = args.get_one::<String>("ipfix").cloned();
+    let sqlite = args.get_one::<String>("sqlite").cloned();
+    let sqlite_retention = args
+        .get_one::<String>("sqlite_retention")
+        .expect("SQLite retention argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse SQLite retention value");
+    let format = ExportFormat::try_from(
+        args.get_one::<String>("format")
+            .expect("Format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse export format");
+    let fields = args.get_one::<String>("fields").cloned();
+    let delimiter = args.get_one::<String>("delimiter").cloned();
+    let header = !args.get_flag("no_header");
+    let timestamp_format = TimestampFormat::try_from(
+        args.get_one::<String>("timestamp_format")
+            .expect("Timestamp format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse timestamp format");
+    let protocol_format = ProtocolFormat::try_from(
+        args.get_one::<String>("protocol_format")
+            .expect("Protocol format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse protocol format");
+    let address_format = AddressFormat::try_from(
+        args.get_one::<String>("address_format")
+            .expect("Address format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse address format");
+    let stream = args.get_one::<String>("stream").cloned();
+    let stream_format = StreamFormat::try_from(
+        args.get_one::<String>("stream_format")
+            .expect("Stream format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse stream format");
+    let stream_buffer = args
+        .get_one::<String>("stream_buffer")
+        .expect("Stream buffer argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse stream buffer value");
+    let output_dir = args.get_one::<String>("output_dir").cloned();
+    let file_template = args.get_one::<String>("file_template").cloned();
+    let rotate_size = args
+        .get_one::<String>("rotate_size")
+        .expect("Rotate size argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate size value");
+    let rotate_records = args
+        .get_one::<String>("rotate_records")
+        .expect("Rotate records argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate records value");
+    let compression = CompressionFormat::try_from(
+        args.get_one::<String>("compress")
+            .expect("Compress argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse compression format");
+    let max_files = args
+        .get_one::<String>("max_files")
+        .expect("Max files argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max files value");
+    let max_age = args
+        .get_one::<String>("max_age")
+        .expect("Max age argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse max age value");
+    let netflow_version = args
+        .get_one::<String>("netflow_version")
+        .expect("NetFlow version argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse NetFlow version value");
+    let ipfix_enterprise = args
+        .get_one::<String>("ipfix_enterprise")
+        .expect("IPFIX enterprise number argument missing")
+        .parse::<u32>()
+        .expect("Failed to parse IPFIX enterprise number value");
     let timeout = args
         .get_one::<String>("timeout")
         .expect("Timeout argument missing")
         .parse::<u64>()
         .expect("Failed to parse timeout value");
+    let active_timeout = args
+        .get_one::<String>("active_timeout")
+        .expect("Active timeout argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse active timeout value");
+    let tunnel_depth = args
+        .get_one::<String>("tunnel_depth")
+        .expect("Tunnel depth argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse tunnel depth value");
+    let max_flows = args
+        .get_one::<String>("max_flows")
+        .expect("Max flows argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max flows value");
+    let max_memory = args
+        .get_one::<String>("max_memory")
+        .expect("Max memory argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max memory value");
+    let eviction_policy = EvictionPolicy::try_from(
+        args.get_one::<String>("eviction")
+            .expect("Eviction argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse eviction policy");
+    // Live mode draws its UI on the standard output
+    let stdout = args.try_get_one::<bool>("stdout").ok().flatten().copied();
+    // Live mode keeps a single flow table, which its UI reads from
+    let workers = args
+        .try_get_one::<String>("workers")
+        .ok()
+        .flatten()
+        .map(|workers| {
+            workers
+                .parse::<usize>()
+                .expect("Failed to parse workers value")
+        });
     let duration = args
         .get_one::<String>("duration")
         .expect("Duration argument missing")
@@ -333,30 +1275,186 @@ fn parse_online_live_args(args: &clap::ArgMatches, _mode: &str) -> Args {
 
     Args::new(
         Some(interface),
-        Files::new(Some(csv), None, None),
+        Files::new(Some(csv), None, None)
+            .with_netflow(netflow)
+            .with_ipfix(ipfix)
+            .with_sqlite(sqlite)
+            .with_format(Some(format))
+            .with_stdout(stdout)
+            .with_stream(stream)
+            .with_output_dir(output_dir),
         Parameters::new(
             Some(use_mac),
             Some(timeout),
             Some(duration),
             Some(interval),
             Some(sleep_windows),
-        ),
+        )
+        .with_active_timeout(Some(active_timeout))
+        .with_midstream(Some(midstream))
+        .with_use_ipv6(Some(use_ipv6))
+        .with_tunnel_depth(Some(tunnel_depth))
+        .with_max_flows(Some(max_flows))
+        .with_max_memory(Some(max_memory))
+        .with_eviction_policy(Some(eviction_policy))
+        .with_workers(workers)
+        .with_netflow_version(Some(netflow_version))
+        .with_ipfix_enterprise(Some(ipfix_enterprise))
+        .with_sqlite_retention(Some(sqlite_retention))
+        .with_fields(fields)
+        .with_delimiter(delimiter)
+        .with_header(Some(header))
+        .with_timestamp_format(Some(timestamp_format))
+        .with_protocol_format(Some(protocol_format))
+        .with_address_format(Some(address_format))
+        .with_stream_format(Some(stream_format))
+        .with_stream_buffer(Some(stream_buffer))
+        .with_file_template(file_template)
+        .with_rotate_size(Some(rotate_size))
+        .with_rotate_records(Some(rotate_records))
+        .with_compression(Some(compression))
+        .with_max_files(Some(max_files))
+        .with_max_age(Some(max_age)),
         // Some(verbose),
     )
 }
 fn parse_offline_args(args: &clap::ArgMatches) -> Args {
     let use_mac = args.get_flag("useMACaddress");
-    let _use_ipv6 = args.get_flag("use_ipv6");
-    let file = args
-        .get_one::<String>("file")
+    let midstream = args.get_flag("midstream");
+    let use_ipv6 = args.get_flag("use_ipv6");
+    let inputs: Vec<String> = args
+        .get_many::<String>("file")
         .expect("File not specified")
-        .to_string();
-    let csv = args.get_one::<String>("csv").unwrap().to_string();
+        .cloned()
+        .collect();
+    let file = inputs.first().cloned();
+    let window = args
+        .get_one::<String>("window")
+        .expect("Window argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse window value");
+    let csv = args.get_one::<String>("csv").cloned();
+    let netflow = args.get_one::<String>("netflow").cloned();
+    let ipfix = args.get_one::<String>("ipfix").cloned();
+    let sqlite = args.get_one::<String>("sqlite").cloned();
+    let sqlite_retention = args
+        .get_one::<String>("sqlite_retention")
+        .expect("SQLite retention argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse SQLite retention value");
+    let format = ExportFormat::try_from(
+        args.get_one::<String>("format")
+            .expect("Format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse export format");
+    let fields = args.get_one::<String>("fields").cloned();
+    let delimiter = args.get_one::<String>("delimiter").cloned();
+    let header = !args.get_flag("no_header");
+    let timestamp_format = TimestampFormat::try_from(
+        args.get_one::<String>("timestamp_format")
+            .expect("Timestamp format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse timestamp format");
+    let protocol_format = ProtocolFormat::try_from(
+        args.get_one::<String>("protocol_format")
+            .expect("Protocol format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse protocol format");
+    let address_format = AddressFormat::try_from(
+        args.get_one::<String>("address_format")
+            .expect("Address format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse address format");
+    let stream = args.get_one::<String>("stream").cloned();
+    let stream_format = StreamFormat::try_from(
+        args.get_one::<String>("stream_format")
+            .expect("Stream format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse stream format");
+    let stream_buffer = args
+        .get_one::<String>("stream_buffer")
+        .expect("Stream buffer argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse stream buffer value");
+    let output_dir = args.get_one::<String>("output_dir").cloned();
+    let file_template = args.get_one::<String>("file_template").cloned();
+    let rotate_size = args
+        .get_one::<String>("rotate_size")
+        .expect("Rotate size argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate size value");
+    let rotate_records = args
+        .get_one::<String>("rotate_records")
+        .expect("Rotate records argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate records value");
+    let compression = CompressionFormat::try_from(
+        args.get_one::<String>("compress")
+            .expect("Compress argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse compression format");
+    let max_files = args
+        .get_one::<String>("max_files")
+        .expect("Max files argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max files value");
+    let max_age = args
+        .get_one::<String>("max_age")
+        .expect("Max age argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse max age value");
+    let netflow_version = args
+        .get_one::<String>("netflow_version")
+        .expect("NetFlow version argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse NetFlow version value");
+    let ipfix_enterprise = args
+        .get_one::<String>("ipfix_enterprise")
+        .expect("IPFIX enterprise number argument missing")
+        .parse::<u32>()
+        .expect("Failed to parse IPFIX enterprise number value");
     let timeout = args
         .get_one::<String>("timeout")
         .expect("Timeout argument missing")
         .parse::<u64>()
         .expect("Failed to parse timeout value");
+    let active_timeout = args
+        .get_one::<String>("active_timeout")
+        .expect("Active timeout argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse active timeout value");
+    let tunnel_depth = args
+        .get_one::<String>("tunnel_depth")
+        .expect("Tunnel depth argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse tunnel depth value");
+    let max_flows = args
+        .get_one::<String>("max_flows")
+        .expect("Max flows argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max flows value");
+    let max_memory = args
+        .get_one::<String>("max_memory")
+        .expect("Max memory argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max memory value");
+    let eviction_policy = EvictionPolicy::try_from(
+        args.get_one::<String>("eviction")
+            .expect("Eviction argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse eviction policy");
+    let workers = args
+        .get_one::<String>("workers")
+        .expect("Workers argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse workers value");
     // let verbose = args
     // .get_one::<String>("verbose")
     // .unwrap()
@@ -365,8 +1463,42 @@ fn parse_offline_args(args: &clap::ArgMatches) -> Args {
 
     Args::new(
         None,
-        Files::new(Some(csv), Some(file), None),
-        Parameters::new(Some(use_mac), Some(timeout), None, None, None),
+        Files::new(csv, file, None)
+            .with_netflow(netflow)
+            .with_ipfix(ipfix)
+            .with_sqlite(sqlite)
+            .with_format(Some(format))
+            .with_stdout(Some(args.get_flag("stdout")))
+            .with_stream(stream)
+            .with_output_dir(output_dir)
+            .with_inputs(inputs),
+        Parameters::new(Some(use_mac), Some(timeout), None, None, None)
+            .with_active_timeout(Some(active_timeout))
+            .with_midstream(Some(midstream))
+            .with_use_ipv6(Some(use_ipv6))
+            .with_tunnel_depth(Some(tunnel_depth))
+            .with_max_flows(Some(max_flows))
+            .with_max_memory(Some(max_memory))
+            .with_eviction_policy(Some(eviction_policy))
+            .with_workers(Some(workers))
+            .with_netflow_version(Some(netflow_version))
+            .with_ipfix_enterprise(Some(ipfix_enterprise))
+            .with_sqlite_retention(Some(sqlite_retention))
+            .with_fields(fields)
+            .with_delimiter(delimiter)
+            .with_header(Some(header))
+            .with_timestamp_format(Some(timestamp_format))
+            .with_protocol_format(Some(protocol_format))
+            .with_address_format(Some(address_format))
+            .with_stream_format(Some(stream_format))
+            .with_stream_buffer(Some(stream_buffer))
+            .with_file_template(file_template)
+            .with_rotate_size(Some(rotate_size))
+            .with_rotate_records(Some(rotate_records))
+            .with_compression(Some(compression))
+            .with_max_files(Some(max_files))
+            .with_max_age(Some(max_age))
+            .with_window(Some(window)),
         // Some(verbose),
     )
 }
@@ -413,3 +1545,191 @@ fn parse_pcap_args(args: &clap::ArgMatches) -> Args {
         // Some(verbose),
     )
 }
+
+fn parse_collect_args(args: &clap::ArgMatches) -> Args {
+    let csv = args
+        .get_one::<String>("csv")
+        .expect("CSV file not specified")
+        .to_string();
+    let listen = args
+        .get_one::<String>("listen")
+        .expect("Listen address not specified")
+        .to_string();
+    let listen_tcp = args.get_one::<String>("listen_tcp").cloned();
+    let use_mac = args.get_flag("useMACaddress");
+    let use_ipv6 = args.get_flag("use_ipv6");
+    // Sampled packets rarely include the SYN of a connection
+    let midstream = true;
+    let timeout = args
+        .get_one::<String>("timeout")
+        .expect("Timeout argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse timeout value");
+    let active_timeout = args
+        .get_one::<String>("active_timeout")
+        .expect("Active timeout argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse active timeout value");
+    let tunnel_depth = args
+        .get_one::<String>("tunnel_depth")
+        .expect("Tunnel depth argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse tunnel depth value");
+    let max_flows = args
+        .get_one::<String>("max_flows")
+        .expect("Max flows argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max flows value");
+    let max_memory = args
+        .get_one::<String>("max_memory")
+        .expect("Max memory argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max memory value");
+    let eviction_policy = EvictionPolicy::try_from(
+        args.get_one::<String>("eviction")
+            .expect("Eviction argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse eviction policy");
+    let netflow = args.get_one::<String>("netflow").cloned();
+    let ipfix = args.get_one::<String>("ipfix").cloned();
+    let sqlite = args.get_one::<String>("sqlite").cloned();
+    let sqlite_retention = args
+        .get_one::<String>("sqlite_retention")
+        .expect("SQLite retention argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse SQLite retention value");
+    let format = ExportFormat::try_from(
+        args.get_one::<String>("format")
+            .expect("Format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse export format");
+    let fields = args.get_one::<String>("fields").cloned();
+    let delimiter = args.get_one::<String>("delimiter").cloned();
+    let header = !args.get_flag("no_header");
+    let timestamp_format = TimestampFormat::try_from(
+        args.get_one::<String>("timestamp_format")
+            .expect("Timestamp format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse timestamp format");
+    let protocol_format = ProtocolFormat::try_from(
+        args.get_one::<String>("protocol_format")
+            .expect("Protocol format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse protocol format");
+    let address_format = AddressFormat::try_from(
+        args.get_one::<String>("address_format")
+            .expect("Address format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse address format");
+    let stream = args.get_one::<String>("stream").cloned();
+    let stream_format = StreamFormat::try_from(
+        args.get_one::<String>("stream_format")
+            .expect("Stream format argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse stream format");
+    let stream_buffer = args
+        .get_one::<String>("stream_buffer")
+        .expect("Stream buffer argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse stream buffer value");
+    let output_dir = args.get_one::<String>("output_dir").cloned();
+    let file_template = args.get_one::<String>("file_template").cloned();
+    let rotate_size = args
+        .get_one::<String>("rotate_size")
+        .expect("Rotate size argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate size value");
+    let rotate_records = args
+        .get_one::<String>("rotate_records")
+        .expect("Rotate records argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse rotate records value");
+    let compression = CompressionFormat::try_from(
+        args.get_one::<String>("compress")
+            .expect("Compress argument missing")
+            .as_str(),
+    )
+    .expect("Failed to parse compression format");
+    let max_files = args
+        .get_one::<String>("max_files")
+        .expect("Max files argument missing")
+        .parse::<usize>()
+        .expect("Failed to parse max files value");
+    let max_age = args
+        .get_one::<String>("max_age")
+        .expect("Max age argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse max age value");
+    let netflow_version = args
+        .get_one::<String>("netflow_version")
+        .expect("NetFlow version argument missing")
+        .parse::<u8>()
+        .expect("Failed to parse NetFlow version value");
+    let ipfix_enterprise = args
+        .get_one::<String>("ipfix_enterprise")
+        .expect("IPFIX enterprise number argument missing")
+        .parse::<u32>()
+        .expect("Failed to parse IPFIX enterprise number value");
+    let stdout = args.get_flag("stdout");
+    let duration = args
+        .get_one::<String>("duration")
+        .expect("Duration argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse duration value");
+    let interval = args
+        .get_one::<String>("interval")
+        .expect("Interval argument missing")
+        .parse::<u64>()
+        .expect("Failed to parse interval value");
+
+    Args::new(
+        None,
+        Files::new(Some(csv), None, None)
+            .with_netflow(netflow)
+            .with_ipfix(ipfix)
+            .with_sqlite(sqlite)
+            .with_format(Some(format))
+            .with_stdout(Some(stdout))
+            .with_stream(stream)
+            .with_output_dir(output_dir)
+            .with_listen(Some(listen))
+            .with_listen_tcp(listen_tcp),
+        Parameters::new(
+            Some(use_mac),
+            Some(timeout),
+            Some(duration),
+            Some(interval),
+            None,
+        )
+        .with_active_timeout(Some(active_timeout))
+        .with_midstream(Some(midstream))
+        .with_use_ipv6(Some(use_ipv6))
+        .with_tunnel_depth(Some(tunnel_depth))
+        .with_max_flows(Some(max_flows))
+        .with_max_memory(Some(max_memory))
+        .with_eviction_policy(Some(eviction_policy))
+        .with_netflow_version(Some(netflow_version))
+        .with_ipfix_enterprise(Some(ipfix_enterprise))
+        .with_sqlite_retention(Some(sqlite_retention))
+        .with_fields(fields)
+        .with_delimiter(delimiter)
+        .with_header(Some(header))
+        .with_timestamp_format(Some(timestamp_format))
+        .with_protocol_format(Some(protocol_format))
+        .with_address_format(Some(address_format))
+        .with_stream_format(Some(stream_format))
+        .with_stream_buffer(Some(stream_buffer))
+        .with_file_template(file_template)
+        .with_rotate_size(Some(rotate_size))
+        .with_rotate_records(Some(rotate_records))
+        .with_compression(Some(compression))
+        .with_max_files(Some(max_files))
+        .with_max_age(Some(max_age)),
+    )
+}
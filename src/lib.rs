@@ -15,6 +15,7 @@ pub enum Mode {
     Online,
     Live,
     Pcap,
+    Collect,
 }
 
 impl TryFrom<&str> for Mode {
@@ -26,6 +27,7 @@ impl TryFrom<&str> for Mode {
             "online" => Ok(Mode::Online),
             "live" => Ok(Mode::Live),
             "pcap" => Ok(Mode::Pcap),
+            "collect" => Ok(Mode::Collect),
             _ => Err(FluereError::ConfigError(format!("Invalid mode: {}", s))),
         }
     }
@@ -38,6 +40,7 @@ impl std::fmt::Display for Mode {
             Mode::Online => write!(f, "Online"),
             Mode::Live => write!(f, "Live"),
             Mode::Pcap => write!(f, "Pcap"),
+            Mode::Collect => write!(f, "Collect"),
         }
     }
 }
@@ -61,18 +64,19 @@ pub async fn execute_mode(mode: Mode, args: types::Args) -> Result<(), FluereErr
         Mode::Offline => net::fluereflow_fileparse(args).await?,
         Mode::Live => net::live_fluereflow::packet_capture(args).await?,
         Mode::Pcap => net::pcap_capture(args).await,
+        Mode::Collect => net::collect_fluereflow::collect(args).await?,
     }
     Ok(())
 }
 
 // Add a function to setup logging
-pub fn setup_logging(verbose: u8) -> Result<(), FluereError> {
-    let logger = logger::Logger::new(
-        None,
-        Some(Level::Trace),
-        Some(logger::Logstdout::Stdout),
-        false,
-    );
+// Logs go to the standard error when the flow records are written to the standard output
+pub fn setup_logging(verbose: u8, records_on_stdout: bool) -> Result<(), FluereError> {
+    let write_to_std = match records_on_stdout {
+        true => logger::Logstdout::StdErr,
+        false => logger::Logstdout::Stdout,
+    };
+    let logger = logger::Logger::new(None, Some(Level::Trace), Some(write_to_std), false);
     let filter = get_log_level(verbose);
 
     log::set_boxed_logger(Box::new(logger))
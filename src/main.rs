@@ -22,7 +22,7 @@ async fn main() -> Result<(), FluereError> {
         };
 
         // Setup logging using library function
-        fluere::setup_logging(verbosity)?;
+        fluere::setup_logging(verbosity, params.files.stdout.unwrap_or(false))?;
         debug!("Fluere started");
 
         // Execute the selected mode using library function
@@ -0,0 +1,352 @@
+// This file contains the inputs of the offline mode. Files, directories and glob patterns are
+// expanded into capture files, whose packets are merged in timestamp order, so that a flow
+// spanning captures rotated by tcpdump is tracked as one flow.
+// libpcap only reads plain captures, so the standard input, named pipes and compressed files
+// are fed to it through a pipe by a thread, which decompresses gzip, zstd and xz on the way.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::{FluereError, net::parser::parse_microseconds};
+
+use flate2::read::MultiGzDecoder;
+use log::{debug, warn};
+use pcap::{Capture, Offline, PacketHeader};
+use xz2::read::XzDecoder;
+
+/// Input naming the standard input.
+pub const STDIN_INPUT: &str = "-";
+// Files of a directory taken as captures, compressed or not
+const CAPTURE_EXTENSIONS: [&str; 3] = ["pcap", "pcapng", "cap"];
+const COMPRESSED_EXTENSIONS: [&str; 3] = ["gz", "zst", "xz"];
+// Longest magic number of the compression formats
+const MAGIC_LENGTH: usize = 6;
+
+// Compression of a capture, recognised by its magic number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    fn detect(magic: &[u8]) -> Option<Self> {
+        match magic {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+}
+
+/// Packet copied out of its capture.
+pub type OwnedPacket = (PacketHeader, Vec<u8>);
+
+/// Capture files named by the inputs, in order and without duplicates.
+///
+/// A directory stands for the `.pcap`, `.pcapng` and `.cap` files in it, compressed ones
+/// included, and an input with `*`, `?` or `[` for the files matching it, both sorted by name.
+/// `-` and named pipes are kept as they are.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, FluereError> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        let mut matched = match path.is_dir() {
+            true => directory_captures(path)?,
+            false if input.contains(['*', '?', '[']) => glob_files(input)?,
+            false if input == STDIN_INPUT || path.exists() => vec![path.to_path_buf()],
+            false => {
+                return Err(FluereError::ConfigError(format!(
+                    "Capture file not found: {}",
+                    input
+                )));
+            }
+        };
+        if matched.is_empty() {
+            return Err(FluereError::ConfigError(format!(
+                "No capture files found for {}",
+                input
+            )));
+        }
+        matched.sort();
+        debug!("{} expanded to {} files", input, matched.len());
+        for file in matched {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+fn directory_captures(directory: &Path) -> Result<Vec<PathBuf>, FluereError> {
+    Ok(fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_capture(path))
+        .collect())
+}
+
+// Whether the file name ends with a capture extension, followed by a compression one or not
+fn is_capture(path: &Path) -> bool {
+    let extension = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_string)
+    };
+    match extension(path) {
+        Some(compressed) if COMPRESSED_EXTENSIONS.contains(&compressed.as_str()) => {
+            extension(&path.with_extension(""))
+                .is_some_and(|inner| CAPTURE_EXTENSIONS.contains(&inner.as_str()))
+        }
+        Some(extension) => CAPTURE_EXTENSIONS.contains(&extension.as_str()),
+        None => false,
+    }
+}
+
+fn glob_files(pattern: &str) -> Result<Vec<PathBuf>, FluereError> {
+    let paths = glob::glob(pattern).map_err(|e| {
+        FluereError::ConfigError(format!("Invalid file pattern {}: {}", pattern, e))
+    })?;
+    Ok(paths
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect())
+}
+
+/// Packets of a capture file, until its end or the first error reading it.
+pub struct CapturePackets {
+    capture: Capture<Offline>,
+    path: String,
+}
+
+impl CapturePackets {
+    /// Opens a pcap or pcapng file, `-` being the standard input.
+    ///
+    /// Plain files are read by libpcap itself. Compressed files, the standard input and named
+    /// pipes are read through a pipe, decompressed if their magic number is known.
+    pub fn open(path: &Path) -> Result<Self, FluereError> {
+        let name = path.display().to_string();
+        let capture = match path.to_str() == Some(STDIN_INPUT) {
+            true => pipe_capture(io::stdin(), "stdin")?,
+            false if path.is_file() && !is_compressed(path)? => Capture::from_file(path)?,
+            false => pipe_capture(File::open(path)?, &name)?,
+        };
+
+        Ok(Self {
+            capture,
+            path: name,
+        })
+    }
+}
+
+fn is_compressed(path: &Path) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(MAGIC_LENGTH);
+    File::open(path)?
+        .take(MAGIC_LENGTH as u64)
+        .read_to_end(&mut magic)?;
+    Ok(Compression::detect(&magic).is_some())
+}
+
+// Opens a capture on a pipe, which a thread fills with the decompressed input
+#[cfg(not(windows))]
+fn pipe_capture(
+    input: impl Read + Send + 'static,
+    name: &str,
+) -> Result<Capture<Offline>, FluereError> {
+    use std::{os::fd::IntoRawFd, thread};
+
+    let (reader, mut writer) = io::pipe()?;
+    let name = name.to_string();
+    thread::Builder::new()
+        .name("fluere-decompress".to_string())
+        .spawn(move || {
+            let copied =
+                decompressed(input).and_then(|mut input| io::copy(&mut input, &mut writer));
+            // A broken pipe means the capture was closed before the end of the input
+            if let Err(e) = copied
+                && e.kind() != io::ErrorKind::BrokenPipe
+            {
+                warn!("Failed to read {}: {}", name, e);
+            }
+        })?;
+
+    // SAFETY: the descriptor is taken out of the reader, leaving the capture its only owner
+    Ok(unsafe { Capture::from_raw_fd(reader.into_raw_fd()) }?)
+}
+
+#[cfg(windows)]
+fn pipe_capture(
+    _input: impl Read + Send + 'static,
+    name: &str,
+) -> Result<Capture<Offline>, FluereError> {
+    Err(FluereError::ConfigError(format!(
+        "Reading pipes and compressed captures is not supported on Windows: {}",
+        name
+    )))
+}
+
+// The input decompressed by its magic number, or as it is if not compressed
+fn decompressed(mut input: impl Read + Send + 'static) -> io::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(MAGIC_LENGTH);
+    (&mut input)
+        .take(MAGIC_LENGTH as u64)
+        .read_to_end(&mut magic)?;
+    let compression = Compression::detect(&magic);
+    debug!("Capture compression: {:?}", compression);
+
+    let input = io::Cursor::new(magic).chain(input);
+    Ok(match compression {
+        Some(Compression::Gzip) => Box::new(MultiGzDecoder::new(input)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::new(input)?),
+        Some(Compression::Xz) => Box::new(XzDecoder::new_multi_decoder(input)),
+        None => Box::new(input),
+    })
+}
+
+impl Iterator for CapturePackets {
+    type Item = OwnedPacket;
+
+    fn next(&mut self) -> Option<OwnedPacket> {
+        match self.capture.next_packet() {
+            Ok(packet) => Some((*packet.header, packet.data.to_vec())),
+            Err(pcap::Error::NoMorePackets) => None,
+            Err(e) => {
+                warn!("Stopped reading {}: {}", self.path, e);
+                None
+            }
+        }
+    }
+}
+
+/// Packets of several sources merged by timestamp.
+///
+/// Each source is expected in timestamp order, as captures are. Packets with the same
+/// timestamp are taken from the sources in the order they were given.
+pub struct MergedPackets<I> {
+    sources: Vec<I>,
+    // Next packet of every source that has one left
+    heads: Vec<Option<OwnedPacket>>,
+    // Timestamp and source of every head, earliest first
+    order: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl<I: Iterator<Item = OwnedPacket>> MergedPackets<I> {
+    pub fn new(sources: Vec<I>) -> Self {
+        let mut merged = Self {
+            heads: sources.iter().map(|_| None).collect(),
+            sources,
+            order: BinaryHeap::new(),
+        };
+        for index in 0..merged.sources.len() {
+            merged.advance(index);
+        }
+        merged
+    }
+
+    // Reads the next packet of a source into its head
+    fn advance(&mut self, index: usize) {
+        self.heads[index] = self.sources[index].next();
+        if let Some((header, _)) = &self.heads[index] {
+            let time = parse_microseconds(header.ts.tv_sec as u64, header.ts.tv_usec as u64);
+            self.order.push(Reverse((time, index)));
+        }
+    }
+}
+
+impl<I: Iterator<Item = OwnedPacket>> Iterator for MergedPackets<I> {
+    type Item = OwnedPacket;
+
+    fn next(&mut self) -> Option<OwnedPacket> {
+        let Reverse((_, index)) = self.order.pop()?;
+        let packet = self.heads[index].take();
+        self.advance(index);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sec: i64, byte: u8) -> OwnedPacket {
+        let header = PacketHeader {
+            ts: libc::timeval {
+                tv_sec: sec as libc::time_t,
+                tv_usec: 0,
+            },
+            caplen: 1,
+            len: 1,
+        };
+        (header, vec![byte])
+    }
+
+    #[test]
+    fn test_packets_are_merged_by_timestamp() {
+        let first = vec![packet(1, 1), packet(3, 3), packet(5, 5)];
+        let second = vec![packet(2, 2), packet(3, 4), packet(6, 6)];
+        let merged: Vec<u8> = MergedPackets::new(vec![first.into_iter(), second.into_iter()])
+            .map(|(_, data)| data[0])
+            .collect();
+        assert_eq!(merged, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_directories_and_patterns_are_expanded() {
+        let directory = std::env::temp_dir().join(format!("fluere-inputs-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for file in ["b.pcap", "a.pcapng", "c.pcap.gz", "notes.txt", "notes.gz"] {
+            fs::write(directory.join(file), []).unwrap();
+        }
+        let name = |path: &PathBuf| path.file_name().unwrap().to_string_lossy().to_string();
+
+        let inputs = [directory.to_string_lossy().to_string()];
+        let files: Vec<String> = expand_inputs(&inputs).unwrap().iter().map(name).collect();
+        assert_eq!(files, vec!["a.pcapng", "b.pcap", "c.pcap.gz"]);
+
+        let inputs = [
+            directory.join("b.*").to_string_lossy().to_string(),
+            directory.join("*.pcap*").to_string_lossy().to_string(),
+        ];
+        let files: Vec<String> = expand_inputs(&inputs).unwrap().iter().map(name).collect();
+        assert_eq!(files, vec!["b.pcap", "a.pcapng", "c.pcap.gz"]);
+
+        let inputs = [directory.join("*.cap").to_string_lossy().to_string()];
+        assert!(expand_inputs(&inputs).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_inputs_are_decompressed() {
+        use std::io::Write;
+
+        let capture = b"\xd4\xc3\xb2\xa1 plain capture".to_vec();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&capture).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&capture).unwrap();
+        let inputs = [
+            capture.clone(),
+            gzip.finish().unwrap(),
+            zstd::encode_all(&capture[..], 0).unwrap(),
+            xz.finish().unwrap(),
+        ];
+
+        for input in inputs {
+            let mut output = Vec::new();
+            decompressed(io::Cursor::new(input))
+                .unwrap()
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(output, capture);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+// This file contains the collector mode, which receives flows exported by routers and firewalls
+// instead of capturing packets. NetFlow v5, v9 and IPFIX datagrams, and IPFIX over TCP, are
+// decoded into flow records, which go through the same plugins and exporters as the captured
+// flows. Packet headers sampled by sFlow switches are turned into flows by a flow table, and
+// their interface counters are written to their own files, as are the IPFIX enterprise
+// elements Fluere does not know, which the plugins also get with their flows.
+
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    FluereError,
+    error::OptionExt,
+    net::FlowTable,
+    types::Args,
+    utils::{
+        CollectedRecord, CsvRowsExporter, DEFAULT_IPFIX_ENTERPRISE, EnterpriseElement, Exporters,
+        InterfaceCounters, IpfixDecoder, NetflowDecoder, SflowDatagram, csv_rows_output,
+        format_exporter,
+    },
+};
+
+use fluere_config::Config;
+use fluere_plugin::PluginManager;
+use fluereflow::FluereRecord;
+use log::{debug, info, warn};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{Mutex, mpsc},
+    task::JoinHandle,
+    time::timeout,
+};
+
+// Largest UDP payload
+const MAX_DATAGRAM: usize = 65_535;
+// How long to wait for a message before checking the duration and interval again
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+// Messages received but not decoded yet
+const MESSAGE_QUEUE: usize = 1_024;
+const IPFIX_VERSION: u16 = 10;
+const SFLOW_VERSION: u32 = 5;
+const IPFIX_HEADER_LENGTH: usize = 16;
+
+// Export messages, and the end of the IPFIX sessions over TCP
+enum Message {
+    Export(SocketAddr, Vec<u8>),
+    Closed(SocketAddr),
+}
+
+// Flows and interface counters decoded from a message
+struct Decoded {
+    flows: Vec<CollectedRecord>,
+    counters: Vec<InterfaceCounters>,
+}
+
+// Decoders of the export protocols, picked by the version a message starts with
+struct Decoders {
+    netflow: NetflowDecoder,
+    ipfix: IpfixDecoder,
+    // Flows built from the packet headers sampled by sFlow agents
+    sflow: FlowTable,
+}
+
+impl Decoders {
+    fn new(sflow: FlowTable, enterprise_number: u32) -> Self {
+        Self {
+            netflow: NetflowDecoder::new(),
+            ipfix: IpfixDecoder::new(enterprise_number),
+            sflow,
+        }
+    }
+
+    fn decode(&mut self, exporter: SocketAddr, message: &[u8]) -> Result<Decoded, FluereError> {
+        if message.starts_with(&SFLOW_VERSION.to_be_bytes()) {
+            return self.decode_sflow(message);
+        }
+        let flows = match message.starts_with(&IPFIX_VERSION.to_be_bytes()) {
+            true => self.ipfix.decode(exporter, message)?,
+            false => collected(self.netflow.decode(exporter.ip(), message)?),
+        };
+
+        Ok(Decoded {
+            flows,
+            counters: Vec::new(),
+        })
+    }
+
+    // Agents only send their uptime, samples are timed when they are received
+    fn decode_sflow(&mut self, message: &[u8]) -> Result<Decoded, FluereError> {
+        let time = now();
+        let datagram = SflowDatagram::decode(message, time)?;
+        let mut flows = Vec::new();
+        for packet in datagram.packets.iter() {
+            flows.extend(self.sflow.ingest_sampled(
+                &packet.header,
+                time,
+                packet.rate,
+                datagram.agent,
+            ));
+        }
+
+        Ok(Decoded {
+            flows: collected(flows),
+            counters: datagram.counters,
+        })
+    }
+
+    fn missing_templates(&self) -> u64 {
+        self.netflow.missing_templates() + self.ipfix.missing_templates()
+    }
+}
+
+// Where the collected flows and the other collected data go
+struct Outputs {
+    exporters: Exporters,
+    counters: CsvRowsExporter<InterfaceCounters>,
+    elements: CsvRowsExporter<EnterpriseElement>,
+    plugin_manager: PluginManager,
+}
+
+impl Outputs {
+    // Writes the flows to the exporters and their enterprise elements to their files, then hands
+    // them to the plugins
+    async fn export(&mut self, flows: Vec<CollectedRecord>) -> Result<(), FluereError> {
+        if flows.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<FluereRecord> = flows.iter().map(|flow| flow.record).collect();
+        self.exporters.write_batch(&records);
+        let elements: Vec<EnterpriseElement> = flows
+            .iter()
+            .flat_map(CollectedRecord::enterprise_elements)
+            .collect();
+        if let Err(e) = self.elements.write_batch(&elements) {
+            warn!("Failed to write enterprise elements: {}", e);
+        }
+
+        for flow in flows {
+            let extensions = flow.extension_values();
+            self.plugin_manager
+                .process_flow_data_with_extensions(flow.record, extensions)
+                .await
+                .map_err(|e| FluereError::PluginError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn write_counters(&mut self, counters: &[InterfaceCounters]) {
+        if let Err(e) = self.counters.write_batch(counters) {
+            warn!("Failed to write interface counters: {}", e);
+        }
+    }
+
+    // Starts new files
+    fn rotate(&mut self) {
+        self.exporters.rotate();
+        self.counters
+            .rotate()
+            .unwrap_or_else(|e| warn!("Failed to rotate the interface counters: {}", e));
+        self.elements
+            .rotate()
+            .unwrap_or_else(|e| warn!("Failed to rotate the enterprise elements: {}", e));
+    }
+
+    // Finishes the files, then waits for the plugins
+    async fn close(mut self, plugin_worker: Arc<Mutex<JoinHandle<()>>>) {
+        self.exporters.close();
+        if let Err(e) = self.counters.close() {
+            warn!("Failed to finish the interface counters: {}", e);
+        }
+        if let Err(e) = self.elements.close() {
+            warn!("Failed to finish the enterprise elements: {}", e);
+        }
+        self.plugin_manager.await_completion(plugin_worker).await;
+    }
+}
+
+// This function listens for exported flows until the duration has been reached.
+// Every message is decoded by its version, and its records are exported and handed to the plugins.
+pub async fn collect(arg: Args) -> Result<(), FluereError> {
+    let csv_file = arg
+        .files
+        .csv
+        .clone()
+        .required("this should be defaulted to `output` on construction")?;
+    let listen = arg
+        .files
+        .listen
+        .clone()
+        .required("this should be defaulted to `0.0.0.0:2055` on construction")?;
+    let duration = arg
+        .parameters
+        .duration
+        .required("this should be defaulted to `0(infinite)` on construction")?;
+    let interval = arg
+        .parameters
+        .interval
+        .required("this should be defaulted to `30 minutes` on construction")?;
+    let config = Config::new();
+    let plugin_manager = PluginManager::new().expect("Failed to create plugin manager");
+    let plugin_worker = plugin_manager.start_worker();
+
+    plugin_manager
+        .load_plugins(&config)
+        .await
+        .expect("Failed to load plugins");
+
+    let (sender, mut messages) = mpsc::channel(MESSAGE_QUEUE);
+    let receivers = spawn_receivers(&listen, arg.files.listen_tcp.as_deref(), sender).await?;
+
+    let records = format_exporter(&arg.files, &arg.parameters, None, &csv_file, true)?;
+    let exporters = Exporters::from_args(records, &arg.files, &arg.parameters, &config.sinks)?;
+    info!("Exporting flows to {} sinks", exporters.len());
+    let side_output = |suffix: &str| {
+        csv_rows_output(
+            &arg.files,
+            &arg.parameters,
+            &format!("{}_{}", csv_file, suffix),
+        )
+    };
+    let mut outputs = Outputs {
+        exporters,
+        counters: CsvRowsExporter::new("Interface counters", side_output("interfaces")?),
+        elements: CsvRowsExporter::new("Enterprise elements", side_output("extensions")?),
+        plugin_manager,
+    };
+
+    let start = Instant::now();
+    let mut decoders = Decoders::new(
+        FlowTable::from_parameters(&arg.parameters)?,
+        arg.parameters
+            .ipfix_enterprise
+            .unwrap_or(DEFAULT_IPFIX_ENTERPRISE),
+    );
+    let flow_cnt = receive_flows(
+        &mut messages,
+        &mut decoders,
+        &mut outputs,
+        duration,
+        interval,
+    )
+    .await?;
+    receivers.iter().for_each(JoinHandle::abort);
+
+    info!(
+        "Collected {} flows, {} FlowSets had no template",
+        flow_cnt,
+        decoders.missing_templates()
+    );
+    debug!("Collected in {:?}", start.elapsed());
+    outputs.close(plugin_worker).await;
+
+    Ok(())
+}
+
+// Decodes and exports the received messages until the duration has been reached, and
+// returns how many flows were collected.
+async fn receive_flows(
+    messages: &mut mpsc::Receiver<Message>,
+    decoders: &mut Decoders,
+    outputs: &mut Outputs,
+    duration: u64,
+    interval: u64,
+) -> Result<usize, FluereError> {
+    let start = Instant::now();
+    let mut last_export = Instant::now();
+    let mut flow_cnt = 0;
+
+    while duration == 0 || start.elapsed() < Duration::from_millis(duration) {
+        // Nothing arrived before the timeout, only check the timeouts and interval
+        match timeout(RECEIVE_TIMEOUT, messages.recv()).await {
+            Ok(Some(Message::Export(exporter, message))) => {
+                flow_cnt += collect_message(exporter, &message, decoders, outputs).await?
+            }
+            Ok(Some(Message::Closed(exporter))) => decoders.ipfix.close_session(exporter),
+            Ok(None) => break,
+            Err(_) => {}
+        }
+
+        // Flows built from sFlow samples end once idle, even without new samples
+        let expired = decoders.sflow.expire(now());
+        flow_cnt += expired.len();
+        outputs.export(collected(expired)).await?;
+
+        // Start new export files if the interval has been reached
+        if last_export.elapsed() >= Duration::from_millis(interval) && interval != 0 {
+            outputs.rotate();
+            last_export = Instant::now();
+        }
+    }
+
+    let remaining = decoders.sflow.flush();
+    flow_cnt += remaining.len();
+    outputs.export(collected(remaining)).await?;
+
+    Ok(flow_cnt)
+}
+
+// Decodes a message, exports its flows and writes its interface counters, returning how many
+// flows it held. Messages that cannot be decoded are dropped.
+async fn collect_message(
+    exporter: SocketAddr,
+    message: &[u8],
+    decoders: &mut Decoders,
+    outputs: &mut Outputs,
+) -> Result<usize, FluereError> {
+    let decoded = match decoders.decode(exporter, message) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            warn!("Dropping message from {}: {}", exporter, e);
+            return Ok(0);
+        }
+    };
+
+    let flow_cnt = decoded.flows.len();
+    outputs.export(decoded.flows).await?;
+    outputs.write_counters(&decoded.counters);
+    Ok(flow_cnt)
+}
+
+// Flows that come without enterprise elements
+fn collected(flows: Vec<FluereRecord>) -> Vec<CollectedRecord> {
+    flows.into_iter().map(CollectedRecord::from).collect()
+}
+
+// Microseconds since the epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("SystemTime before UNIX EPOCH")
+        .as_micros() as u64
+}
+
+// Starts receiving datagrams, and IPFIX sessions if a TCP address is given
+async fn spawn_receivers(
+    listen: &str,
+    listen_tcp: Option<&str>,
+    sender: mpsc::Sender<Message>,
+) -> Result<Vec<JoinHandle<()>>, FluereError> {
+    let socket = UdpSocket::bind(listen).await?;
+    info!("Collecting flows on udp://{}", socket.local_addr()?);
+    let mut receivers = vec![tokio::spawn(receive_datagrams(socket, sender.clone()))];
+    if let Some(listen_tcp) = listen_tcp {
+        let listener = TcpListener::bind(listen_tcp).await?;
+        info!("Collecting IPFIX on tcp://{}", listener.local_addr()?);
+        receivers.push(tokio::spawn(accept_sessions(listener, sender)));
+    }
+
+    Ok(receivers)
+}
+
+async fn receive_datagrams(socket: UdpSocket, sender: mpsc::Sender<Message>) {
+    let mut buffer = vec![0; MAX_DATAGRAM];
+    loop {
+        match socket.recv_from(&mut buffer).await {
+            Ok((length, exporter)) => {
+                let message = Message::Export(exporter, buffer[..length].to_vec());
+                if sender.send(message).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("Failed to receive a datagram: {}", e),
+        }
+    }
+}
+
+async fn accept_sessions(listener: TcpListener, sender: mpsc::Sender<Message>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, exporter)) => {
+                debug!("IPFIX session from {} opened", exporter);
+                tokio::spawn(receive_session(stream, exporter, sender.clone()));
+            }
+            Err(e) => warn!("Failed to accept an IPFIX session: {}", e),
+        }
+    }
+}
+
+// Splits the stream into messages by the length in their header, until the exporter closes it
+async fn receive_session(
+    mut stream: TcpStream,
+    exporter: SocketAddr,
+    sender: mpsc::Sender<Message>,
+) {
+    let mut header = [0; IPFIX_HEADER_LENGTH];
+    while stream.read_exact(&mut header).await.is_ok() {
+        let version = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if version != IPFIX_VERSION || length < IPFIX_HEADER_LENGTH {
+            warn!("Closing the session from {}, not an IPFIX stream", exporter);
+            break;
+        }
+
+        let mut message = header.to_vec();
+        message.resize(length, 0);
+        if stream
+            .read_exact(&mut message[IPFIX_HEADER_LENGTH..])
+            .await
+            .is_err()
+            || sender
+                .send(Message::Export(exporter, message))
+                .await
+                .is_err()
+        {
+            break;
+        }
+    }
+
+    debug!("IPFIX session from {} closed", exporter);
+    // The collector may be gone already
+    let _ = sender.send(Message::Closed(exporter)).await;
+}
@@ -0,0 +1,799 @@
+// This file contains the flow table shared by the online, offline and live modes.
+// It owns the active flows, the expiration index and the rules that decide when a flow ends,
+// so every mode (and library users) only has to feed packets in and collect finished records.
+
+use std::{collections::HashMap, mem, net::IpAddr};
+
+use crate::{
+    FluereError,
+    error::OptionExt,
+    net::{
+        flows::{restart_flow, update_flow},
+        parser::{decapsulate, parse_fluereflow, parse_keys, parse_microseconds},
+        timer_wheel::TimerWheel,
+        types::{Key, TcpFlags, TcpTracker},
+    },
+    types::{EvictionPolicy, Parameters, UDFlowKey},
+};
+
+use fluereflow::{FlowEndReason, FluereRecord};
+use log::{debug, trace, warn};
+
+// Tunnel layers decapsulated unless configured otherwise
+pub(crate) const DEFAULT_TUNNEL_DEPTH: u8 = 1;
+
+// Rough memory cost of one flow: the table entry plus hash table slack and expiration entries
+const FLOW_MEMORY_ESTIMATE: usize = 2 * std::mem::size_of::<(Key, ActiveFlow)>();
+
+// A full table evicts this fraction of its flows at once, so that the scan for eviction
+// candidates is amortised over many new flows
+const EVICTION_BATCH_DIVISOR: usize = 64;
+
+// A flow being tracked, stored under its canonical key.
+// The record is oriented from the initiator to the responder, so `out_*` counts
+// client-to-server packets and `in_*` counts server-to-client packets.
+#[derive(Debug, Clone, Copy)]
+struct ActiveFlow {
+    record: FluereRecord,
+    tcp: Option<TcpTracker>,
+    // Whether the initiator is the destination side of the canonical key
+    initiator_swapped: bool,
+    // Deadline of the flow's pending idle timer (0: none)
+    timer_deadline: u64,
+}
+
+/// Counters of the times the flow table was full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OverloadStats {
+    /// Times a new flow found the table full
+    pub overload_events: u64,
+    /// Flows exported early to make room
+    pub evicted_flows: u64,
+}
+
+/// Active flow table driven packet by packet.
+///
+/// Packets are handed to [`FlowTable::ingest`], which returns every flow that finished or
+/// expired because of that packet. [`FlowTable::flush`] drains the flows that are still
+/// active, e.g. when a capture ends.
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    active_flow: HashMap<Key, ActiveFlow>,
+    // Idle timers, one per flow, keyed by the deadline they were scheduled for
+    timers: TimerWheel<(Key, u64)>,
+    // Inactive timeout in milliseconds (0: infinite)
+    flow_timeout: u64,
+    // Active timeout in milliseconds (0: infinite)
+    active_timeout: u64,
+    use_mac: bool,
+    // Track TCP connections first seen without a SYN
+    midstream: bool,
+    use_ipv6: bool,
+    // Maximum number of tunnel layers to decapsulate
+    tunnel_depth: u8,
+    // Maximum number of active flows (0: unlimited)
+    max_flows: usize,
+    // Memory budget in bytes (0: unlimited)
+    memory_budget: usize,
+    eviction_policy: EvictionPolicy,
+    overload: OverloadStats,
+    established: Option<Key>,
+}
+
+impl FlowTable {
+    pub fn new(flow_timeout: u64, use_mac: bool) -> Self {
+        Self {
+            flow_timeout,
+            use_mac,
+            tunnel_depth: DEFAULT_TUNNEL_DEPTH,
+            ..Default::default()
+        }
+    }
+
+    /// Splits flows that stay active longer than `active_timeout` milliseconds (0: infinite).
+    ///
+    /// When a packet arrives after the active timeout, the flow is exported as a partial
+    /// record and its counters restart with that packet, like NetFlow/IPFIX exporters do.
+    pub fn with_active_timeout(mut self, active_timeout: u64) -> Self {
+        self.active_timeout = active_timeout;
+        self
+    }
+
+    /// Picks up TCP connections that were already open when first seen.
+    ///
+    /// By default a TCP flow only starts on a SYN and packets of unknown connections are skipped.
+    pub fn with_midstream(mut self, midstream: bool) -> Self {
+        self.midstream = midstream;
+        self
+    }
+
+    /// Creates flows for IPv6 packets, which are skipped by default.
+    pub fn with_ipv6(mut self, use_ipv6: bool) -> Self {
+        self.use_ipv6 = use_ipv6;
+        self
+    }
+
+    /// Decapsulates up to `tunnel_depth` layers of tunnels, so flows are built from the inner
+    /// headers. The outermost tunnel is kept on the record (0: flows of the outer headers).
+    pub fn with_tunnel_depth(mut self, tunnel_depth: u8) -> Self {
+        self.tunnel_depth = tunnel_depth;
+        self
+    }
+
+    /// Limits the number of active flows (0: unlimited).
+    ///
+    /// When a new flow finds the table full, flows chosen by the eviction policy are exported
+    /// early with [`FlowEndReason::LackOfResources`].
+    pub fn with_max_flows(mut self, max_flows: usize) -> Self {
+        self.max_flows = max_flows;
+        self
+    }
+
+    /// Limits the memory used by active flows to roughly `memory_budget` bytes (0: unlimited).
+    pub fn with_memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// Scales the flow count and memory limits down to one of `shards` tables sharing them.
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        let shards = shards.max(1);
+        self.max_flows = self.max_flows.div_ceil(shards);
+        self.memory_budget = self.memory_budget.div_ceil(shards);
+        self
+    }
+
+    /// Builds a flow table from the parsed command line parameters.
+    pub fn from_parameters(parameters: &Parameters) -> Result<Self, FluereError> {
+        let use_mac = parameters
+            .use_mac
+            .required("this should be defaulted to `false` on construction")?;
+        let flow_timeout = parameters
+            .timeout
+            .required("this should be defaulted to `10 minutes` on construction")?;
+
+        let active_timeout = parameters.active_timeout.unwrap_or(0);
+        let midstream = parameters.midstream.unwrap_or(false);
+        let use_ipv6 = parameters.use_ipv6.unwrap_or(false);
+        let tunnel_depth = parameters.tunnel_depth.unwrap_or(DEFAULT_TUNNEL_DEPTH);
+        let max_flows = parameters.max_flows.unwrap_or(0);
+        // Convert megabytes to bytes
+        let memory_budget = parameters.max_memory.unwrap_or(0) * 1024 * 1024;
+        let eviction_policy = parameters.eviction_policy.unwrap_or_default();
+
+        Ok(Self::new(flow_timeout, use_mac)
+            .with_active_timeout(active_timeout)
+            .with_midstream(midstream)
+            .with_ipv6(use_ipv6)
+            .with_tunnel_depth(tunnel_depth)
+            .with_max_flows(max_flows)
+            .with_memory_budget(memory_budget)
+            .with_eviction_policy(eviction_policy))
+    }
+
+    /// Number of flows currently active.
+    pub fn len(&self) -> usize {
+        self.active_flow.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active_flow.is_empty()
+    }
+
+    /// How often the table was full and how many flows were evicted because of it.
+    pub fn overload_stats(&self) -> OverloadStats {
+        self.overload
+    }
+
+    /// Key of the flow created by the last call to [`FlowTable::ingest`], if any,
+    /// oriented from the initiator to the responder.
+    pub fn established(&self) -> Option<&Key> {
+        self.established.as_ref()
+    }
+
+    /// Feeds a packet into the table.
+    ///
+    /// Returns the flows that finished with this packet and the flows whose timeout expired
+    /// before its timestamp. Packets that cannot be parsed are skipped.
+    pub fn ingest(&mut self, packet: &pcap::Packet) -> Vec<FluereRecord> {
+        let packet_time = parse_microseconds(
+            packet.header.ts.tv_sec as u64,
+            packet.header.ts.tv_usec as u64,
+        );
+        self.ingest_frame(packet.data, packet_time, 1, None)
+    }
+
+    /// Feeds a packet header sampled by a switch into the table.
+    ///
+    /// The header stands for `rate` packets, so the packet and byte counters of its flow are
+    /// scaled by the sampling rate. New flows are tagged with the `exporter` that sampled them.
+    /// Returns the flows that finished with this sample and the flows whose timeout expired
+    /// before `time` (in microseconds).
+    pub fn ingest_sampled(
+        &mut self,
+        data: &[u8],
+        time: u64,
+        rate: u32,
+        exporter: IpAddr,
+    ) -> Vec<FluereRecord> {
+        self.ingest_frame(data, time, rate.max(1), Some(exporter))
+    }
+
+    // Parses a frame and accounts it as `packets` packets to its flow
+    fn ingest_frame(
+        &mut self,
+        data: &[u8],
+        packet_time: u64,
+        packets: u32,
+        exporter: Option<IpAddr>,
+    ) -> Vec<FluereRecord> {
+        self.established = None;
+
+        let frame = match decapsulate(data, self.tunnel_depth) {
+            Ok(frame) => frame,
+            Err(e) => {
+                debug!("Error on decapsulate: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut key_value = match parse_keys(&frame) {
+            Ok(key) => key,
+            Err(e) => {
+                debug!("Error on parse_keys: {}", e);
+                return Vec::new();
+            }
+        };
+        if !self.use_ipv6 && key_value.src_ip.is_ipv6() {
+            trace!("Skipping IPv6 packet");
+            return Vec::new();
+        }
+        if !self.use_mac {
+            key_value.mac_defaultate();
+        }
+
+        let (doctets, raw_flags, mut flowdata) = match parse_fluereflow(&frame, packet_time) {
+            Ok(result) => result,
+            Err(e) => {
+                debug!("Error on parse_fluereflow: {}", e);
+                return Vec::new();
+            }
+        };
+        if let Some(exporter) = exporter {
+            flowdata.exporter = exporter;
+        }
+
+        let flags = TcpFlags::new(raw_flags);
+
+        let mut finished = Vec::new();
+        if let Some((flow_key, is_reverse)) =
+            self.lookup_or_insert(key_value, flowdata, flags, &mut finished)
+        {
+            let update_key = UDFlowKey {
+                doctets: doctets * packets as usize,
+                packets,
+                pkt: flowdata.min_pkt,
+                ttl: flowdata.min_ttl,
+                flags,
+                time: packet_time,
+            };
+            finished.extend(self.update(flow_key, is_reverse, update_key));
+        }
+
+        finished.extend(self.expire(packet_time));
+        finished
+    }
+
+    /// Removes and returns every flow whose inactive timeout passed before `now`
+    /// (in microseconds).
+    pub fn expire(&mut self, now: u64) -> Vec<FluereRecord> {
+        self.timers
+            .advance(now)
+            .into_iter()
+            .filter_map(|(key, deadline)| self.fire_timer(key, deadline, now))
+            .collect()
+    }
+
+    /// Removes and returns every active flow, ordered by their first packet.
+    ///
+    /// Also reports how often the table overflowed since the last flush, and counts again.
+    pub fn flush(&mut self) -> Vec<FluereRecord> {
+        let overload = mem::take(&mut self.overload);
+        if overload.overload_events > 0 {
+            warn!(
+                "Flow table was full {} times, {} flows were exported early",
+                overload.overload_events, overload.evicted_flows
+            );
+        }
+        self.timers.clear();
+        let mut flows: Vec<FluereRecord> = self
+            .active_flow
+            .drain()
+            .map(|(_, flow)| end_record(flow.record, FlowEndReason::ForcedEnd))
+            .collect();
+        flows.sort_by_key(|flow| flow.first);
+
+        flows
+    }
+
+    // Finds the flow the packet belongs to, creating it when the packet may start a flow.
+    // Returns the canonical key of the flow and whether the packet was sent by the responder.
+    // Flows evicted to make room for a new one are pushed to `evicted`.
+    fn lookup_or_insert(
+        &mut self,
+        key_value: Key,
+        flowdata: FluereRecord,
+        flags: TcpFlags,
+        evicted: &mut Vec<FluereRecord>,
+    ) -> Option<(Key, bool)> {
+        let (flow_key, swapped) = key_value.canonical();
+        if let Some(flow) = self.active_flow.get(&flow_key) {
+            return Some((flow_key, swapped != flow.initiator_swapped));
+        }
+
+        // If the protocol is TCP, only a SYN packet establishes a new flow,
+        // unless connections already in progress are picked up
+        let tcp = match flowdata.prot {
+            6 if flags.syn > 0 => Some(TcpTracker::new()),
+            6 if self.midstream => Some(TcpTracker::midstream()),
+            6 => return None,
+            _ => None,
+        };
+
+        evicted.extend(self.evict());
+
+        let is_initiator = is_initiator(&flowdata, flags);
+        let (record, initiator_key) = if is_initiator {
+            (flowdata, key_value)
+        } else {
+            (swap_endpoints(flowdata), key_value.reversed())
+        };
+        self.active_flow.insert(
+            flow_key,
+            ActiveFlow {
+                record,
+                tcp,
+                initiator_swapped: swapped == is_initiator,
+                timer_deadline: 0,
+            },
+        );
+        self.established = Some(initiator_key);
+        trace!("flow established");
+
+        Some((flow_key, !is_initiator))
+    }
+
+    // Maximum number of active flows allowed by the flow count and memory limits
+    fn flow_limit(&self) -> Option<usize> {
+        let count_limit = (self.max_flows > 0).then_some(self.max_flows);
+        let memory_limit =
+            (self.memory_budget > 0).then(|| (self.memory_budget / FLOW_MEMORY_ESTIMATE).max(1));
+
+        match (count_limit, memory_limit) {
+            (Some(count_limit), Some(memory_limit)) => Some(count_limit.min(memory_limit)),
+            (count_limit, memory_limit) => count_limit.or(memory_limit),
+        }
+    }
+
+    // Exports a batch of flows chosen by the eviction policy when the table is full.
+    fn evict(&mut self) -> Vec<FluereRecord> {
+        let Some(limit) = self.flow_limit() else {
+            return Vec::new();
+        };
+        let len = self.active_flow.len();
+        if len < limit {
+            return Vec::new();
+        }
+
+        self.overload.overload_events += 1;
+        if self.overload.overload_events == 1 {
+            warn!(
+                "Flow table is full ({} flows), exporting {} flows early",
+                limit, self.eviction_policy
+            );
+        }
+
+        let batch = (limit / EVICTION_BATCH_DIVISOR).max(len + 1 - limit);
+        let policy = self.eviction_policy;
+        let mut candidates: Vec<(u64, Key)> = self
+            .active_flow
+            .iter()
+            .map(|(key, flow)| (eviction_score(policy, &flow.record), *key))
+            .collect();
+        candidates.select_nth_unstable_by_key(batch - 1, |&(score, _)| score);
+        candidates.truncate(batch);
+
+        let evicted: Vec<FluereRecord> = candidates
+            .into_iter()
+            .filter_map(|(_, key)| self.active_flow.remove(&key))
+            .map(|flow| end_record(flow.record, FlowEndReason::LackOfResources))
+            .collect();
+        debug!("evicted {} flows", evicted.len());
+        self.overload.evicted_flows += evicted.len() as u64;
+
+        evicted
+    }
+
+    // Updates the flow with the packet and returns the records it produced: the partial record
+    // when the active timeout was reached, and the flow itself when the packet finished it.
+    fn update(
+        &mut self,
+        flow_key: Key,
+        is_reverse: bool,
+        update_key: UDFlowKey,
+    ) -> Vec<FluereRecord> {
+        let mut records = Vec::new();
+        let active_timeout = self.active_timeout;
+        let expiration_time = self.expiration_time(update_key.time);
+        let Some(flow) = self.active_flow.get_mut(&flow_key) else {
+            return records;
+        };
+
+        if active_timeout > 0 && update_key.time >= flow.record.first + (active_timeout * 1_000) {
+            trace!("flow reached active timeout");
+            records.push(end_record(flow.record, FlowEndReason::ActiveTimeout));
+            restart_flow(&mut flow.record, update_key);
+        }
+
+        update_flow(&mut flow.record, is_reverse, update_key);
+        trace!(
+            "{} flow updated",
+            if is_reverse { "reverse" } else { "forward" }
+        );
+        trace!("flow key detail: {:?}", flow_key);
+
+        let end_reason = flow.tcp.as_mut().and_then(|tcp| {
+            let state = tcp.update(update_key.flags, is_reverse);
+            trace!("tcp state: {}", state.as_str());
+            tcp.end_reason()
+        });
+        if let Some(end_reason) = end_reason {
+            trace!("flow finished");
+            trace!("flow data: {:?}", flow.record);
+            let flow = self.active_flow.remove(&flow_key);
+            records.extend(flow.map(|flow| end_record(flow.record, end_reason)));
+            return records;
+        }
+
+        // A flow keeps a single timer, which is only moved when it fires
+        if self.flow_timeout > 0 && flow.timer_deadline == 0 {
+            flow.timer_deadline = expiration_time;
+            self.timers.schedule(
+                update_key.time,
+                expiration_time,
+                (flow_key, expiration_time),
+            );
+        }
+
+        records
+    }
+
+    // Handles a fired idle timer: expires the flow if it has really been idle long enough,
+    // otherwise moves the timer to the flow's current deadline.
+    fn fire_timer(&mut self, key: Key, deadline: u64, now: u64) -> Option<FluereRecord> {
+        let idle_deadline = self.expiration_time(self.active_flow.get(&key)?.record.last);
+        let flow = self.active_flow.get_mut(&key)?;
+        // Left over from an earlier flow with the same key
+        if flow.timer_deadline != deadline {
+            return None;
+        }
+        if idle_deadline > now {
+            flow.timer_deadline = idle_deadline;
+            self.timers
+                .schedule(now, idle_deadline, (key, idle_deadline));
+            return None;
+        }
+
+        trace!("flow expired");
+        let flow = self.active_flow.remove(&key)?;
+        Some(end_record(flow.record, FlowEndReason::IdleTimeout))
+    }
+
+    #[inline]
+    fn expiration_time(&self, last: u64) -> u64 {
+        // Convert milliseconds to microseconds
+        last + (self.flow_timeout * 1_000)
+    }
+}
+
+// Flows with the lowest score are evicted first.
+fn eviction_score(policy: EvictionPolicy, record: &FluereRecord) -> u64 {
+    match policy {
+        EvictionPolicy::Oldest => record.first,
+        EvictionPolicy::Idle => record.last,
+        EvictionPolicy::LeastActive => record.d_pkts as u64,
+    }
+}
+
+// Whether the packet that creates a flow was sent by the side that opened the connection.
+// A SYN/ACK comes from the responder; without a handshake, a packet sent from a well-known
+// port to an ephemeral one is taken as a server reply.
+fn is_initiator(flowdata: &FluereRecord, flags: TcpFlags) -> bool {
+    if flowdata.prot == 6 && flags.syn > 0 {
+        return flags.ack == 0;
+    }
+
+    !(flowdata.src_port < 1024 && flowdata.dst_port >= 1024)
+}
+
+fn swap_endpoints(mut record: FluereRecord) -> FluereRecord {
+    std::mem::swap(&mut record.source, &mut record.destination);
+    std::mem::swap(&mut record.src_port, &mut record.dst_port);
+    record
+}
+
+fn end_record(mut record: FluereRecord, end_reason: FlowEndReason) -> FluereRecord {
+    record.end_reason = end_reason;
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp_packet(src_port: u16, dst_port: u16, flags: u8) -> Vec<u8> {
+        let mut data = vec![
+            // Ethernet: dst mac, src mac, EtherType IPv4
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            // IPv4: Version=4, IHL=5, Total Length=40, TTL=64, Protocol=TCP
+            0x45, 0x00, 0x00, 0x28, 0x00, 0x00, 0x40, 0x00, 0x40, 0x06, 0x00, 0x00,
+        ];
+        if src_port < dst_port {
+            data.extend_from_slice(&[10, 0, 0, 2, 10, 0, 0, 1]);
+        } else {
+            data.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        }
+        data.extend_from_slice(&src_port.to_be_bytes());
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&[
+            0x00, 0x00, 0x00, 0x01, // Sequence number
+            0x00, 0x00, 0x00, 0x00, // Acknowledgment number
+            0x50, flags, 0xff, 0xff, // Data offset=5, flags, window
+            0x00, 0x00, 0x00, 0x00, // Checksum, urgent pointer
+        ]);
+        data
+    }
+
+    fn udp_ipv6_packet() -> Vec<u8> {
+        let mut data = vec![
+            // Ethernet: dst mac, src mac, EtherType IPv6
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x86, 0xdd,
+            // IPv6: Version=6, Flow Label=0xbeef, Payload Length=16, Next Header=Hop-by-Hop
+            0x60, 0x00, 0xbe, 0xef, 0x00, 0x10, 0x00, 0x40,
+        ];
+        data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        data.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        data.extend_from_slice(&[
+            0x11, 0x00, 0x01, 0x04, 0x00, 0x00, 0x00, 0x00, // Hop-by-Hop, next header UDP
+            0xd4, 0x31, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, // UDP 54321 -> 53
+        ]);
+        data
+    }
+
+    fn vlan_tagged(mut data: Vec<u8>, vlan_id: u16) -> Vec<u8> {
+        let mut tag = vec![0x81, 0x00];
+        tag.extend_from_slice(&vlan_id.to_be_bytes());
+        data.splice(12..12, tag);
+        data
+    }
+
+    fn header(sec: i64, len: usize) -> pcap::PacketHeader {
+        pcap::PacketHeader {
+            ts: libc::timeval {
+                tv_sec: sec as libc::time_t,
+                tv_usec: 0,
+            },
+            caplen: len as u32,
+            len: len as u32,
+        }
+    }
+
+    fn ingest(table: &mut FlowTable, sec: i64, data: &[u8]) -> Vec<FluereRecord> {
+        let header = header(sec, data.len());
+        table.ingest(&pcap::Packet::new(&header, data))
+    }
+
+    #[test]
+    fn test_tcp_flow_lifecycle() {
+        let mut table = FlowTable::new(600_000, false);
+
+        assert!(ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02)).is_empty());
+        assert!(table.established().is_some());
+        assert!(ingest(&mut table, 1, &tcp_packet(80, 40000, 0x12)).is_empty());
+        assert!(table.established().is_none());
+        assert!(ingest(&mut table, 1, &tcp_packet(40000, 80, 0x10)).is_empty());
+        assert_eq!(table.len(), 1);
+
+        // The half-close and the peer's FIN stay in the same flow
+        assert!(ingest(&mut table, 2, &tcp_packet(40000, 80, 0x11)).is_empty());
+        assert!(ingest(&mut table, 2, &tcp_packet(80, 40000, 0x10)).is_empty());
+        assert!(ingest(&mut table, 3, &tcp_packet(80, 40000, 0x11)).is_empty());
+
+        let finished = ingest(&mut table, 3, &tcp_packet(40000, 80, 0x10));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].d_pkts, 7);
+        assert_eq!(finished[0].in_pkts, 3);
+        assert_eq!(finished[0].out_pkts, 4);
+        assert_eq!(finished[0].end_reason, FlowEndReason::TcpFin);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_ipv6_flows_need_flag() {
+        let mut table = FlowTable::new(600_000, false);
+        ingest(&mut table, 1, &udp_ipv6_packet());
+        assert!(table.is_empty());
+
+        let mut table = FlowTable::new(600_000, false).with_ipv6(true);
+        ingest(&mut table, 1, &udp_ipv6_packet());
+        let flows = table.flush();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].prot, 17);
+        assert_eq!((flows[0].src_port, flows[0].dst_port), (54321, 53));
+        assert_eq!(flows[0].flow_label, 0xbeef);
+    }
+
+    #[test]
+    fn test_vlans_split_flows() {
+        let mut table = FlowTable::new(600_000, false);
+        ingest(&mut table, 1, &vlan_tagged(tcp_packet(40000, 80, 0x02), 10));
+        ingest(&mut table, 1, &vlan_tagged(tcp_packet(40000, 80, 0x02), 20));
+        assert_eq!(table.len(), 2);
+
+        let flows = table.flush();
+        let mut vlans: Vec<u16> = flows.iter().map(|flow| flow.outer_vlan).collect();
+        vlans.sort();
+        assert_eq!(vlans, vec![10, 20]);
+        assert!(flows.iter().all(|flow| flow.dst_port == 80));
+    }
+
+    #[test]
+    fn test_initiator_from_syn_ack() {
+        let mut table = FlowTable::new(600_000, false);
+
+        // The SYN was missed, the flow starts with the server's SYN/ACK
+        ingest(&mut table, 1, &tcp_packet(80, 40000, 0x12));
+        let established = table.established().copied().unwrap();
+        assert_eq!(established.src_port, 40000);
+        assert_eq!(established.dst_port, 80);
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x10));
+
+        let flushed = table.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].src_port, 40000);
+        assert_eq!(flushed[0].dst_port, 80);
+        assert_eq!(flushed[0].out_pkts, 1);
+        assert_eq!(flushed[0].in_pkts, 1);
+    }
+
+    #[test]
+    fn test_tcp_reset_ends_flow() {
+        let mut table = FlowTable::new(600_000, false);
+
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        let finished = ingest(&mut table, 1, &tcp_packet(80, 40000, 0x14));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].end_reason, FlowEndReason::TcpReset);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_tcp_without_syn_is_skipped() {
+        let mut table = FlowTable::new(600_000, false);
+
+        assert!(ingest(&mut table, 1, &tcp_packet(40000, 80, 0x10)).is_empty());
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_tcp_midstream_pickup() {
+        let mut table = FlowTable::new(600_000, false).with_midstream(true);
+
+        assert!(ingest(&mut table, 1, &tcp_packet(40000, 80, 0x10)).is_empty());
+        assert_eq!(table.len(), 1);
+        let finished = ingest(&mut table, 2, &tcp_packet(80, 40000, 0x04));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].d_pkts, 2);
+    }
+
+    #[test]
+    fn test_sampled_packets_are_scaled() {
+        let mut table = FlowTable::new(600_000, false).with_midstream(true);
+        let agent = IpAddr::from([192, 168, 0, 1]);
+
+        table.ingest_sampled(&tcp_packet(40000, 80, 0x10), 1_000_000, 512, agent);
+        table.ingest_sampled(&tcp_packet(80, 40000, 0x10), 2_000_000, 512, agent);
+        let flushed = table.flush();
+        assert_eq!(flushed.len(), 1);
+        assert_eq!(flushed[0].d_pkts, 1024);
+        assert_eq!(flushed[0].d_octets, 40 * 1024);
+        assert_eq!((flushed[0].out_pkts, flushed[0].in_pkts), (512, 512));
+        assert_eq!(flushed[0].ack_cnt, 1024);
+        assert_eq!(flushed[0].exporter, agent);
+    }
+
+    #[test]
+    fn test_idle_flow_expires() {
+        let mut table = FlowTable::new(1_000, false);
+
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        ingest(&mut table, 2, &tcp_packet(80, 40000, 0x12));
+        // Still active, refreshed one second after the SYN
+        assert!(table.expire(2_500_000).is_empty());
+
+        let expired = table.expire(3_000_000);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].end_reason, FlowEndReason::IdleTimeout);
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn test_active_timeout_splits_flow() {
+        let mut table = FlowTable::new(600_000, false).with_active_timeout(10_000);
+
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        ingest(&mut table, 5, &tcp_packet(80, 40000, 0x12));
+        let partial = ingest(&mut table, 11, &tcp_packet(40000, 80, 0x10));
+        assert_eq!(partial.len(), 1);
+        assert_eq!(partial[0].d_pkts, 2);
+        assert_eq!(partial[0].last, 5_000_000);
+        assert_eq!(partial[0].end_reason, FlowEndReason::ActiveTimeout);
+        assert_eq!(table.len(), 1);
+
+        let finished = ingest(&mut table, 12, &tcp_packet(40000, 80, 0x04));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].d_pkts, 2);
+        assert_eq!(finished[0].first, 11_000_000);
+    }
+
+    #[test]
+    fn test_full_table_evicts_oldest_flow() {
+        let mut table = FlowTable::new(600_000, false).with_max_flows(2);
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        ingest(&mut table, 2, &tcp_packet(40001, 80, 0x02));
+        assert_eq!(table.overload_stats(), OverloadStats::default());
+
+        let evicted = ingest(&mut table, 3, &tcp_packet(40002, 80, 0x02));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].src_port, 40000);
+        assert_eq!(evicted[0].end_reason, FlowEndReason::LackOfResources);
+        assert_eq!(table.len(), 2);
+
+        let overload = table.overload_stats();
+        assert_eq!(overload.overload_events, 1);
+        assert_eq!(overload.evicted_flows, 1);
+    }
+
+    #[test]
+    fn test_full_table_evicts_least_active_flow() {
+        let mut table = FlowTable::new(600_000, false)
+            .with_max_flows(2)
+            .with_eviction_policy(EvictionPolicy::LeastActive);
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        ingest(&mut table, 1, &tcp_packet(80, 40000, 0x12));
+        ingest(&mut table, 2, &tcp_packet(40001, 80, 0x02));
+
+        let evicted = ingest(&mut table, 3, &tcp_packet(40002, 80, 0x02));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].src_port, 40001);
+    }
+
+    #[test]
+    fn test_flush_drains_active_flows() {
+        let mut table = FlowTable::new(0, false);
+
+        ingest(&mut table, 1, &tcp_packet(40000, 80, 0x02));
+        ingest(&mut table, 2, &tcp_packet(40001, 80, 0x02));
+        assert!(table.expire(u64::MAX).is_empty());
+
+        let flushed = table.flush();
+        assert_eq!(flushed.len(), 2);
+        assert!(flushed[0].first <= flushed[1].first);
+        assert_eq!(flushed[0].end_reason, FlowEndReason::ForcedEnd);
+        assert!(table.is_empty());
+    }
+}
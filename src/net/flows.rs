@@ -14,29 +14,59 @@ pub fn update_flow(flow: &mut FluereRecord, is_reverse: bool, update_key: UDFlow
     let ttl = update_key.ttl;
     let flags = update_key.flags;
     let time = update_key.time;
+    let packets = update_key.packets;
 
-    flow.d_pkts += 1;
+    flow.d_pkts += packets;
     flow.d_octets += doctets;
     flow.max_pkt = flow.max_pkt.max(pkt);
     flow.min_pkt = flow.min_pkt.min(pkt);
     flow.max_ttl = flow.max_ttl.max(ttl);
     flow.min_ttl = flow.min_ttl.min(ttl);
-    flow.fin_cnt += flags.fin as u32;
-    flow.syn_cnt += flags.syn as u32;
-    flow.rst_cnt += flags.rst as u32;
-    flow.psh_cnt += flags.psh as u32;
-    flow.ack_cnt += flags.ack as u32;
-    flow.urg_cnt += flags.urg as u32;
-    flow.ece_cnt += flags.ece as u32;
-    flow.cwr_cnt += flags.cwr as u32;
-    flow.ns_cnt += flags.ns as u32;
+    flow.fin_cnt += flags.fin as u32 * packets;
+    flow.syn_cnt += flags.syn as u32 * packets;
+    flow.rst_cnt += flags.rst as u32 * packets;
+    flow.psh_cnt += flags.psh as u32 * packets;
+    flow.ack_cnt += flags.ack as u32 * packets;
+    flow.urg_cnt += flags.urg as u32 * packets;
+    flow.ece_cnt += flags.ece as u32 * packets;
+    flow.cwr_cnt += flags.cwr as u32 * packets;
+    flow.ns_cnt += flags.ns as u32 * packets;
     flow.last = time;
 
     if is_reverse {
-        flow.in_pkts += 1;
+        flow.in_pkts += packets;
         flow.in_bytes += doctets;
     } else {
-        flow.out_pkts += 1;
+        flow.out_pkts += packets;
         flow.out_bytes += doctets;
     }
 }
+
+/// Restarts a flow after it has been exported on active timeout
+/// # Arguments
+/// * `flow` - The flow to restart
+/// * `update_key` - The update key of the packet that starts the new segment
+///
+pub fn restart_flow(flow: &mut FluereRecord, update_key: UDFlowKey) {
+    flow.d_pkts = 0;
+    flow.d_octets = 0;
+    flow.first = update_key.time;
+    flow.last = update_key.time;
+    flow.min_pkt = update_key.pkt;
+    flow.max_pkt = update_key.pkt;
+    flow.min_ttl = update_key.ttl;
+    flow.max_ttl = update_key.ttl;
+    flow.in_pkts = 0;
+    flow.out_pkts = 0;
+    flow.in_bytes = 0;
+    flow.out_bytes = 0;
+    flow.fin_cnt = 0;
+    flow.syn_cnt = 0;
+    flow.rst_cnt = 0;
+    flow.psh_cnt = 0;
+    flow.ack_cnt = 0;
+    flow.urg_cnt = 0;
+    flow.ece_cnt = 0;
+    flow.cwr_cnt = 0;
+    flow.ns_cnt = 0;
+}
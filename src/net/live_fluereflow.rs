@@ -1,29 +1,25 @@
 // This file contains the implementation of the live packet capture functionality.
 // It uses the pcap library to capture packets from a network interface and the fluereflow library to convert the packets into NetFlow data.
 // The data is then displayed in a terminal user interface using the ratatui library.
+// Unlike the online mode, packets are not sharded across worker threads: the UI reads the active
+// flow count and the newly established flows from a single flow table, so this mode is meant for
+// watching an interface rather than for line-rate capture, which the online mode handles.
 use crate::{
     FluereError,
     error::OptionExt,
-    net::{
-        CaptureDevice, find_device,
-        flows::update_flow,
-        parser::{microseconds_to_timestamp, parse_fluereflow, parse_keys, parse_microseconds},
-        types::TcpFlags,
-    },
-    types::{Args, UDFlowKey},
-    utils::{cur_time_file, fluere_exporter},
+    net::{CaptureDevice, FlowTable, find_device, parser::microseconds_to_timestamp},
+    types::Args,
+    utils::{Exporters, format_exporter},
 };
 use std::{
     borrow::Cow,
-    collections::HashMap,
-    fs, io,
+    io,
     sync::Arc,
     time::{Duration, Instant, SystemTime},
 };
 
 use fluere_config::Config;
 use fluere_plugin::PluginManager;
-use fluereflow::FluereRecord;
 
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent},
@@ -38,7 +34,7 @@ use ratatui::{
     style::{Color, Style},
     widgets::{Block, Borders, Gauge, List, ListItem, Paragraph},
 };
-use tokio::{sync::Mutex, task};
+use tokio::sync::Mutex;
 
 const MAX_RECENT_FLOWS: usize = 50;
 
@@ -68,12 +64,9 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
     let csv_file = arg
         .files
         .csv
+        .clone()
         .required("this should be defaulted to `output` on construction")?;
     //let enable_ipv6
-    let use_mac = arg
-        .parameters
-        .use_mac
-        .required("this should be defaulted to `false` on construction")?;
     let interface_name = arg.interface.required("interface should be provided")?;
     let duration = arg
         .parameters
@@ -83,10 +76,6 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
         .parameters
         .interval
         .required("this should be defaulted to `30 minutes` on construction")?;
-    let flow_timeout = arg
-        .parameters
-        .timeout
-        .required("this should be defaulted to `10 minutes` on construction")?;
     let _sleep_windows = arg
         .parameters
         .sleep_windows
@@ -104,9 +93,6 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
     let mut cap_device = CaptureDevice::new(interface.clone()).unwrap();
     let cap = &mut cap_device.capture;
 
-    let file_dir = "./output";
-    fs::create_dir_all(file_dir)?;
-
     let start = Instant::now();
     let last_export_unix_time = Arc::new(Mutex::new(
         SystemTime::now()
@@ -115,12 +101,17 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
             .as_secs(),
     ));
     let last_export = Arc::new(Mutex::new(Instant::now()));
-    let mut file_path = cur_time_file(csv_file.as_str(), file_dir, ".csv");
-    let mut file = fs::File::create(file_path.as_ref())?;
+    let records = format_exporter(
+        &arg.files,
+        &arg.parameters,
+        Some(&interface_name),
+        &csv_file,
+        true,
+    )?;
+    let mut exporters = Exporters::from_args(records, &arg.files, &arg.parameters, &config.sinks)?;
 
-    let mut records: Vec<FluereRecord> = Vec::new();
     let recent_flows: Arc<Mutex<Vec<FlowSummary>>> = Arc::new(Mutex::new(Vec::new()));
-    let active_flow = Arc::new(Mutex::new(HashMap::new()));
+    let active_flow = Arc::new(Mutex::new(FlowTable::from_parameters(&arg.parameters)?));
 
     match enable_raw_mode() {
         Ok(_) => debug!("Raw mode enabled"),
@@ -190,9 +181,6 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
 
     tokio::spawn(listen_for_exit_keys());
 
-    let mut tasks = vec![];
-    let mut export_tasks = vec![];
-
     loop {
         match cap.next_packet() {
             Err(_) => {
@@ -201,155 +189,37 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
             Ok(packet) => {
                 trace!("received packet");
 
-                let (mut key_value, mut reverse_key) = match parse_keys(packet.clone()) {
-                    Ok(keys) => keys,
-                    Err(_) => continue,
-                };
-                if !use_mac {
-                    key_value.mac_defaultate();
-                    reverse_key.mac_defaultate();
-                }
-
-                let (doctets, raw_flags, flowdata) = match parse_fluereflow(packet.clone()) {
-                    Ok(result) => result,
-                    Err(e) => {
-                        debug!("{}", e);
-                        continue;
-                    }
-                };
-
-                let flags = TcpFlags::new(raw_flags);
-                //pushing packet in to active_flows if it is not present
-                //let mut active_flow: HashMap<Key, FluereRecord> = HashMap::new();
                 let mut active_flow_guard = active_flow.lock().await;
-
-                let is_reverse = match active_flow_guard.get(&key_value) {
-                    None => match active_flow_guard.get(&reverse_key) {
-                        None => {
-                            // if the protocol is TCP, check if is a syn packet
-                            if flowdata.prot == 6 {
-                                if flags.syn > 0 {
-                                    active_flow_guard.insert(key_value, flowdata);
-                                    trace!("flow established");
-                                    let mut recent_flows_guard = recent_flows.lock().await;
-                                    recent_flows_guard.push(FlowSummary {
-                                        src: Cow::from(key_value.src_ip.to_string()),
-                                        dst: Cow::from(key_value.dst_ip.to_string()),
-                                        src_port: Cow::from(key_value.src_port.to_string()),
-                                        dst_port: Cow::from(key_value.dst_port.to_string()),
-                                        protocol: Cow::from(key_value.protocol.to_string()), //flow_data: format!("{:?}", flowdata),
-                                    });
-                                    if recent_flows_guard.len() > MAX_RECENT_FLOWS {
-                                        recent_flows_guard.remove(0);
-                                    }
-                                } else {
-                                    continue;
-                                }
-                            } else {
-                                active_flow_guard.insert(key_value, flowdata);
-                                trace!("flow established");
-                                let mut recent_flows_guard = recent_flows.lock().await;
-                                recent_flows_guard.push(FlowSummary {
-                                    src: Cow::from(key_value.src_ip.to_string()),
-                                    dst: Cow::from(key_value.dst_ip.to_string()),
-                                    src_port: Cow::from(key_value.src_port.to_string()),
-                                    dst_port: Cow::from(key_value.dst_port.to_string()),
-                                    protocol: Cow::from(key_value.protocol.to_string()), //flow_data: format!("{:?}", flowdata),
-                                });
-                                if recent_flows_guard.len() > MAX_RECENT_FLOWS {
-                                    recent_flows_guard.remove(0);
-                                }
-                            }
-
-                            false
-                        }
-                        Some(_) => true,
-                    },
-                    Some(_) => false,
-                };
-
-                let time = parse_microseconds(
-                    packet.header.ts.tv_sec as u64,
-                    packet.header.ts.tv_usec as u64,
-                );
-                //println!("time: {:?}", time);
-                let pkt = flowdata.min_pkt;
-                let ttl = flowdata.min_ttl;
-                //println!("current inputed flow{:?}", active_flow.get(&key_value).unwrap());
-                let flow_key = if is_reverse { &reverse_key } else { &key_value };
-                if let Some(flow) = active_flow_guard.get_mut(flow_key) {
-                    let update_key = UDFlowKey {
-                        doctets,
-                        pkt,
-                        ttl,
-                        flags,
-                        time,
-                    };
-                    update_flow(flow, is_reverse, update_key);
-
-                    trace!(
-                        "{} flow updated",
-                        if is_reverse { "reverse" } else { "forward" }
-                    );
-
-                    if flags.is_finished() {
-                        trace!("flow finished");
-                        plugin_manager.process_flow_data(*flow).await.unwrap();
-                        records.push(*flow);
-                        active_flow_guard.remove(flow_key);
+                let finished = active_flow_guard.ingest(&packet);
+
+                if let Some(key_value) = active_flow_guard.established() {
+                    let mut recent_flows_guard = recent_flows.lock().await;
+                    recent_flows_guard.push(FlowSummary {
+                        src: Cow::from(key_value.src_ip.to_string()),
+                        dst: Cow::from(key_value.dst_ip.to_string()),
+                        src_port: Cow::from(key_value.src_port.to_string()),
+                        dst_port: Cow::from(key_value.dst_port.to_string()),
+                        protocol: Cow::from(key_value.protocol.to_string()), //flow_data: format!("{:?}", flowdata),
+                    });
+                    if recent_flows_guard.len() > MAX_RECENT_FLOWS {
+                        recent_flows_guard.remove(0);
                     }
                 }
+                drop(active_flow_guard);
+
+                exporters.write_batch(&finished);
+                for flow in finished {
+                    plugin_manager
+                        .process_flow_data(flow)
+                        .await
+                        .map_err(|e| FluereError::PluginError(e.to_string()))?;
+                }
 
-                // Export flows if the interval has been reached
+                // Start new export files if the interval has been reached
                 let mut last_export_guard = last_export.lock().await;
                 let mut last_export_unix_time_guard = last_export_unix_time.lock().await;
                 if last_export_guard.elapsed() >= Duration::from_millis(interval) && interval != 0 {
-                    let mut expired_flows = vec![];
-                    let mut expired_flow_data: Vec<FluereRecord> = vec![];
-
-                    debug!("Calculating timeout start");
-                    for (key, flow) in active_flow_guard.iter() {
-                        if flow_timeout > 0 && flow.last < (time - (flow_timeout * 1000)) {
-                            trace!("flow expired");
-
-                            //plugin_manager.process_flow_data(*flow).await.unwrap();
-                            records.push(*flow);
-                            expired_flows.push(*key);
-                            expired_flow_data.push(*flow);
-                        }
-                    }
-
-                    let cloned_plugin_manager = plugin_manager.clone();
-                    tasks.push(task::spawn(async move {
-                        for flow in &expired_flow_data {
-                            cloned_plugin_manager
-                                .process_flow_data(*flow)
-                                .await
-                                .unwrap();
-                        }
-                        debug!(
-                            "Sending {} expired flows to plugins done",
-                            expired_flow_data.len()
-                        );
-                    }));
-
-                    active_flow_guard.retain(|key, _| !expired_flows.contains(key));
-                    let cloned_records = records.clone();
-                    records.clear();
-                    //let tasks = task::spawn(async {
-                    //fluere_exporter(cloned_records, file).await;
-                    //});
-                    let file_path_clone = file_path.clone();
-                    export_tasks.push(task::spawn(async move {
-                        let _ = fluere_exporter(cloned_records, file).await;
-                        debug!("Export {} Finished", file_path_clone);
-                    }));
-
-                    /*if verbose >= 1 {
-                    println!("Export {} result: {:?}", file_path, result);
-                    }*/
-                    file_path = cur_time_file(csv_file.as_str(), file_dir, ".csv");
-                    file = fs::File::create(file_path.as_ref()).map_err(FluereError::IoError)?;
+                    exporters.rotate();
                     *last_export_guard = Instant::now();
                     *last_export_unix_time_guard = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
@@ -359,42 +229,24 @@ pub async fn online_packet_capture(arg: Args) -> Result<(), FluereError> {
 
                 // Check if the duration has been reached
                 if start.elapsed() >= Duration::from_millis(duration) && duration != 0 {
-                    let mut expired_flows = vec![];
-                    for (key, flow) in active_flow_guard.iter() {
-                        if flow.last < (time - (flow_timeout * 1000)) {
-                            trace!("flow expired");
-                            plugin_manager.process_flow_data(*flow).await.unwrap();
-                            records.push(*flow);
-                            expired_flows.push(*key);
-                        }
-                    }
-                    active_flow_guard.retain(|key, _| !expired_flows.contains(key));
                     break;
                 }
             }
         }
     }
     debug!("Captured in {:?}", start.elapsed());
-    let active_flow_guard = active_flow.lock().await;
-
-    for (_key, flow) in active_flow_guard.iter() {
-        plugin_manager.process_flow_data(*flow).await.unwrap();
-        records.push(*flow);
+    let remaining_flows = active_flow.lock().await.flush();
+    exporters.write_batch(&remaining_flows);
+    exporters.close();
+
+    for flow in remaining_flows {
+        plugin_manager
+            .process_flow_data(flow)
+            .await
+            .map_err(|e| FluereError::PluginError(e.to_string()))?;
     }
-
-    for task in tasks {
-        let _ = task.await;
-    }
-
-    let cloned_records = records.clone();
-    export_tasks.push(task::spawn(async {
-        let _ = fluere_exporter(cloned_records, file).await;
-    }));
     plugin_manager.await_completion(plugin_worker).await;
     drop(plugin_manager);
-    for task in export_tasks {
-        let _ = task.await;
-    }
 
     let _ = draw_task.await;
     match disable_raw_mode() {
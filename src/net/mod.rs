@@ -1,6 +1,9 @@
 //mod fluereflow
 mod capture;
+mod capture_files;
+pub mod collect_fluereflow;
 // pub mod errors;
+mod flow_table;
 mod flows;
 // mod interface;
 pub mod live_fluereflow;
@@ -8,16 +11,21 @@ mod offline_fluereflows;
 pub mod online_fluereflow;
 mod packet_pcap;
 pub mod parser;
+mod pipeline;
+mod timer_wheel;
 pub mod types;
 
 //pub use flows::packet_capture;
 pub use capture::CaptureDevice;
 pub use capture::DeviceError;
 pub use capture::find_device;
+pub use flow_table::FlowTable;
+pub use flow_table::OverloadStats;
 // pub use interface::list_interface_names;
 // pub use interface::list_interfaces;
 pub use offline_fluereflows::fluereflow_fileparse;
 pub use packet_pcap::pcap_capture;
+pub use pipeline::ShardedFlowTable;
 //pub use types::FluereRecord;
 
 use std::fmt::{Display, Formatter, Result as FmtResult};
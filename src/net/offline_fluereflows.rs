@@ -1,196 +1,235 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    fs,
-    path::Path,
-    time::Instant,
-};
+use std::{path::Path, thread, time::Instant};
 
 use crate::{
     FluereError,
     error::OptionExt,
     net::{
-        flows::update_flow,
-        parser::{parse_fluereflow, parse_keys, parse_microseconds},
-        types::{Key, TcpFlags},
+        ShardedFlowTable,
+        capture_files::{CapturePackets, MergedPackets, OwnedPacket, STDIN_INPUT, expand_inputs},
+        parser::parse_microseconds,
     },
-    types::{Args, UDFlowKey},
-    utils::fluere_exporter,
+    types::{Args, Files, Parameters},
+    utils::{Exporters, FlowExporter, format_exporter},
 };
 
+use chrono::{DateTime, Local};
+use fluere_config::Config;
+use fluere_plugin::PluginManager;
 use fluereflow::FluereRecord;
-use indicatif::ProgressBar;
-use log::{debug, info, trace};
-use pcap::Capture;
-use tokio::task;
+use log::{info, trace};
+use pcap::{Packet, PacketHeader};
+use tokio::sync::mpsc;
+
+// Records of a window, and the packet time it starts at
+type Window = (u64, mpsc::Receiver<Vec<FluereRecord>>);
 
 pub async fn fluereflow_fileparse(arg: Args) -> Result<(), FluereError> {
-    let _csv_file = arg
-        .files
-        .csv
-        .required("this should be defaulted to `output` on construction")?;
     let file_name = arg
         .files
         .file
+        .clone()
         .required("pcap file path should be provided")?;
-    let use_mac = arg
-        .parameters
-        .use_mac
-        .required("this should be defaulted to `false` on construction")?;
-    let flow_timeout = arg
-        .parameters
-        .timeout
-        .required("this should be defaulted to `10 minutes` on construction")?;
-
-    let mut cap = Capture::from_file(file_name.clone())?;
-
-    let file_dir = "./output";
-    fs::create_dir_all(file_dir)?;
+    let inputs = match arg.files.inputs.is_empty() {
+        true => vec![file_name],
+        false => arg.files.inputs.clone(),
+    };
+    let files = expand_inputs(&inputs)?;
+    let captures = files
+        .iter()
+        .map(|file| CapturePackets::open(file))
+        .collect::<Result<Vec<_>, FluereError>>()?;
+    // Packet time covered by each result, in microseconds
+    let window = arg.parameters.window.unwrap_or(0) * 1_000;
 
     let start = Instant::now();
-    let file_noext = format!(
-        "{}_converted.csv",
-        Path::new(&file_name)
-            .file_stem()
-            .and_then(|stem| stem.to_str())
-            .unwrap_or("output")
+    // Named after the first capture unless a title is given
+    let file_stem = arg.files.csv.clone().unwrap_or_else(|| {
+        format!(
+            "{}_converted",
+            files
+                .first()
+                .map(|file| match file.to_str() == Some(STDIN_INPUT) {
+                    true => "stdin",
+                    false => capture_stem(file),
+                })
+                .unwrap_or("output")
+        )
+    });
+    let mut packets = MergedPackets::new(captures).peekable();
+    // Windows are named after the packet time they start at
+    let first_window = packets
+        .peek()
+        .filter(|_| window > 0)
+        .map(|(header, _)| packet_time(header) / window * window);
+    let records = match first_window {
+        Some(start) => window_exporter(&arg.files, &arg.parameters, &file_stem, start, window)?,
+        None => format_exporter(&arg.files, &arg.parameters, None, &file_stem, false)?,
+    };
+    let config = Config::new();
+    let plugin_manager = PluginManager::new().expect("Failed to create plugin manager");
+    let plugin_worker = plugin_manager.start_worker();
+
+    plugin_manager
+        .load_plugins(&config)
+        .await
+        .expect("Failed to load plugins");
+
+    let mut exporters = Exporters::from_args(records, &arg.files, &arg.parameters, &config.sinks)?;
+
+    let mut flow_cnt = 0;
+    let (flow_table, mut finished) = ShardedFlowTable::from_parameters(&arg.parameters)?;
+
+    info!(
+        "Converting {} files on {} workers",
+        files.len(),
+        flow_table.workers()
     );
-    let output_file_path = format!("{}/{}", file_dir, file_noext);
-    let file = fs::File::create(&output_file_path)?;
-
-    let mut records: Vec<FluereRecord> = Vec::new();
-    let mut active_flow: HashMap<Key, FluereRecord> = HashMap::new();
-    let mut flow_expirations: BTreeMap<u64, Vec<Key>> = BTreeMap::new();
-
-    info!("Converting file: {}", file_name);
+    for file in files.iter() {
+        info!("Converting file: {}", file.display());
+    }
 
-    let bar = ProgressBar::new_spinner();
+    // The records of every window after the first arrive on their own receiver
+    let (windows, mut next_windows) = mpsc::channel(1);
+    let reader = thread::Builder::new()
+        .name("fluere-reader".to_string())
+        .spawn(move || read_packets(packets, flow_table, window, windows))?;
+    let mut window_cnt = 1;
+    loop {
+        while let Some(flows) = finished.recv().await {
+            flow_cnt += flows.len();
+            export_flows(flows, &mut exporters, &plugin_manager).await?;
+        }
+        // Every window starts new files
+        let Some((window_start, next)) = next_windows.recv().await else {
+            break;
+        };
+        finished = next;
+        exporters.replace_records(window_exporter(
+            &arg.files,
+            &arg.parameters,
+            &file_stem,
+            window_start,
+            window,
+        )?);
+        window_cnt += 1;
+    }
+    let ac_flow_cnt = reader
+        .join()
+        .map_err(|_| FluereError::ParseError("Packet reader panicked".to_string()))?;
+    info!("Converted in {:?}", start.elapsed());
+    let ended_flow_cnt = flow_cnt.saturating_sub(ac_flow_cnt);
+    exporters.close();
+    plugin_manager.await_completion(plugin_worker).await;
+    drop(plugin_manager);
 
-    while let Ok(packet) = cap.next_packet() {
-        trace!("Parsing packet");
+    if window > 0 {
+        info!("Windows: {:?}", window_cnt);
+    }
+    info!("Active flows: {:?}", ac_flow_cnt);
+    info!("Ended flows: {:?}", ended_flow_cnt);
+    Ok(())
+}
 
-        let (mut key_value, mut reverse_key) = match parse_keys(packet.clone()) {
-            Ok(keys) => keys,
-            Err(_) => continue,
-        };
-        trace!("Parsed keys");
-        if !use_mac {
-            key_value.mac_defaultate();
-            reverse_key.mac_defaultate();
-        }
+// Writes the flows to the exporters, then hands them to the plugins
+async fn export_flows(
+    flows: Vec<FluereRecord>,
+    exporters: &mut Exporters,
+    plugin_manager: &PluginManager,
+) -> Result<(), FluereError> {
+    exporters.write_batch(&flows);
+    for flow in flows {
+        plugin_manager
+            .process_flow_data(flow)
+            .await
+            .map_err(|e| FluereError::PluginError(e.to_string()))?;
+    }
+    Ok(())
+}
 
-        let (doctets, raw_flags, flowdata) = match parse_fluereflow(packet.clone()) {
-            Ok(result) => result,
-            Err(e) => {
-                debug!("{}", e);
-                continue;
-            }
-        };
+// File name without its capture and compression extensions, e.g. `trace` for `trace.pcap.gz`
+fn capture_stem(file: &Path) -> &str {
+    let stem = file
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("output");
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("gz" | "zst" | "xz") => Path::new(stem)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(stem),
+        _ => stem,
+    }
+}
 
-        // Define `packet_time` before any usage
-        let packet_time = parse_microseconds(
-            packet.header.ts.tv_sec as u64,
-            packet.header.ts.tv_usec as u64,
-        );
-
-        let flags = TcpFlags::new(raw_flags);
-        // Pushing packet into active_flows if it is not present
-        let is_reverse = match active_flow.get(&key_value) {
-            None => match active_flow.get(&reverse_key) {
-                None => {
-                    // If the protocol is TCP, check if it's a SYN packet
-                    if flowdata.prot == 6 {
-                        if flags.syn > 0 {
-                            let expiration_time = packet_time + (flow_timeout * 1_000); // Convert milliseconds to microseconds
-                            flow_expirations
-                                .entry(expiration_time)
-                                .or_default()
-                                .push(key_value);
-                            active_flow.insert(key_value, flowdata);
-
-                            trace!("Flow established");
-                        } else {
-                            continue;
-                        }
-                    } else {
-                        let expiration_time = packet_time + (flow_timeout * 1_000); // Convert milliseconds to microseconds
-                        flow_expirations
-                            .entry(expiration_time)
-                            .or_default()
-                            .push(key_value);
-                        active_flow.insert(key_value, flowdata);
-
-                        trace!("Flow established");
-                    }
-
-                    false
-                }
-                Some(_) => true,
-            },
-            Some(_) => false,
-        };
+// Exporter of the records of the window starting at `start`, named after its start time, with
+// milliseconds for windows not counted in seconds
+fn window_exporter(
+    files: &Files,
+    parameters: &Parameters,
+    name: &str,
+    start: u64,
+    window: u64,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    let pattern = match window % 1_000_000 {
+        0 => "%Y-%m-%d_%H-%M-%S",
+        _ => "%Y-%m-%d_%H-%M-%S%.3f",
+    };
+    let start = DateTime::from_timestamp_micros(start as i64)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+        .format(pattern);
+    format_exporter(
+        files,
+        parameters,
+        None,
+        &format!("{}_{}", name, start),
+        false,
+    )
+}
 
-        let pkt = flowdata.min_pkt;
-        let ttl = flowdata.min_ttl;
-
-        let flow_key = if is_reverse { &reverse_key } else { &key_value };
-        if let Some(flow) = active_flow.get_mut(flow_key) {
-            let update_key = UDFlowKey {
-                doctets,
-                pkt,
-                ttl,
-                flags,
-                time: packet_time, // Use the correct variable here
-            };
-
-            update_flow(flow, is_reverse, update_key);
-
-            trace!(
-                "{} flow updated",
-                if is_reverse { "reverse" } else { "forward" }
-            );
-
-            if flags.is_finished() {
-                trace!("Flow finished");
-                trace!("Flow data: {:?}", flow);
-                records.push(*flow);
-                active_flow.remove(flow_key);
-            }
-        }
+fn packet_time(header: &PacketHeader) -> u64 {
+    parse_microseconds(header.ts.tv_sec as u64, header.ts.tv_usec as u64)
+}
 
-        // Remove expired flows before processing the next packet
-        let current_time = packet_time;
-        let expired_times: Vec<u64> = flow_expirations
-            .range(..=current_time)
-            .map(|(&time, _)| time)
-            .collect();
-
-        for expiration_time in expired_times {
-            if let Some(keys) = flow_expirations.remove(&expiration_time) {
-                for key in keys {
-                    if let Some(flow) = active_flow.remove(&key) {
-                        records.push(flow);
-                    }
+// Hands every packet of the files to the flow table workers, then flushes them.
+// With a window, the flows are flushed at the end of every window, and the records of the next
+// one go to a new receiver, handed to the export stage.
+// Returns the number of flows still active at the end of the files or windows.
+fn read_packets(
+    packets: impl Iterator<Item = OwnedPacket>,
+    mut flow_table: ShardedFlowTable,
+    window: u64,
+    windows: mpsc::Sender<Window>,
+) -> usize {
+    let mut active = 0;
+    let mut window_end = None;
+    for (header, data) in packets {
+        trace!("Parsing packet");
+        let packet_time = packet_time(&header);
+        if window > 0 && window_end.is_none_or(|end| packet_time >= end) {
+            let start = packet_time / window * window;
+            if window_end.is_some() {
+                match next_window(&mut flow_table, start, &windows) {
+                    Some(count) => active += count,
+                    None => return active + flow_table.finish(),
                 }
             }
+            window_end = Some(start + window);
         }
+        flow_table.dispatch(&Packet::new(&header, &data));
     }
-    bar.finish();
-    info!("Converted in {:?}", start.elapsed());
-    let ac_flow_cnt = active_flow.len();
-    let ended_flow_cnt = records.len();
-
-    for (_key, flow) in active_flow.clone().iter() {
-        records.push(*flow);
-    }
-
-    let tasks = task::spawn(async {
-        let _ = fluere_exporter(records, file).await;
-    });
-
-    let result = tasks.await;
-    info!("Export {} result: {:?}", output_file_path, result);
+    active + flow_table.finish()
+}
 
-    info!("Active flows: {:?}", ac_flow_cnt);
-    info!("Ended flows: {:?}", ended_flow_cnt);
-    Ok(())
+// Flushes the flows of the window ending, and hands the export stage the receiver of the window
+// starting at `start`.
+// Returns the number of flows flushed, or `None` if the export stage is gone.
+fn next_window(
+    flow_table: &mut ShardedFlowTable,
+    start: u64,
+    windows: &mpsc::Sender<Window>,
+) -> Option<usize> {
+    let (active, finished) = flow_table.restart();
+    windows.blocking_send((start, finished)).ok()?;
+    Some(active)
 }
@@ -3,23 +3,16 @@
 // The data is then exported to a CSV file.
 
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs,
-    mem::take,
+    thread,
     time::{Duration, Instant},
 };
 
 use crate::{
     FluereError,
     error::OptionExt,
-    net::{
-        CaptureDevice, NetError, find_device,
-        flows::update_flow,
-        parser::{parse_fluereflow, parse_keys, parse_microseconds},
-        types::{Key, TcpFlags},
-    },
-    types::{Args, UDFlowKey},
-    utils::{cur_time_file, fluere_exporter},
+    net::{CaptureDevice, NetError, ShardedFlowTable, find_device},
+    types::Args,
+    utils::{Exporters, format_exporter},
 };
 
 use fluere_config::Config;
@@ -28,10 +21,8 @@ use fluere_config::Config;
 // | Since the plugin manager uses Lua, for edge cases that require minimal feature,
 // | setting the plugin as a feature would be beneficial.
 use fluere_plugin::PluginManager;
-use fluereflow::FluereRecord;
 
 use log::{debug, error, info, trace};
-use tokio::{task, task::JoinHandle};
 
 // This function captures packets from a network interface and converts them into NetFlow data.
 // It takes the command line arguments as input, which specify the network interface to capture from and other parameters.
@@ -40,12 +31,9 @@ pub async fn packet_capture(arg: Args) -> Result<(), FluereError> {
     let csv_file = arg
         .files
         .csv
+        .clone()
         .required("this should be defaulted to `output` on construction")?;
     //let enable_ipv6
-    let use_mac = arg
-        .parameters
-        .use_mac
-        .required("this should be defaulted to `false` on construction")?;
     let interface_name = arg.interface.required("interface should be provided")?;
     let duration = arg
         .parameters
@@ -55,10 +43,6 @@ pub async fn packet_capture(arg: Args) -> Result<(), FluereError> {
         .parameters
         .interval
         .required("this should be defaulted to `30 minutes` on construction")?;
-    let flow_timeout = arg
-        .parameters
-        .timeout
-        .required("this should be defaulted to `10 minutes` on construction")?;
     let _sleep_windows = arg
         .parameters
         .sleep_windows
@@ -73,240 +57,77 @@ pub async fn packet_capture(arg: Args) -> Result<(), FluereError> {
         .expect("Failed to load plugins");
 
     let interface = find_device(&interface_name)?;
-    let mut cap_device = CaptureDevice::new(interface.clone()).map_err(NetError::from)?;
-    let cap = &mut cap_device.capture;
-
-    let file_dir = "./output";
-    fs::create_dir_all(file_dir)
-        .unwrap_or_else(|error| panic!("Problem creating directory: {:?}", error));
+    let cap_device = CaptureDevice::new(interface.clone()).map_err(NetError::from)?;
 
     let start = Instant::now();
     let mut last_export = Instant::now();
-    let mut file_path = cur_time_file(csv_file.as_str(), file_dir, ".csv");
-    // FIX:TASK: there is a possibility of a permission error
-    // | need to check, if it is a permission error and handle it
-    let mut file = fs::File::create(file_path.as_ref())?;
+    let records = format_exporter(
+        &arg.files,
+        &arg.parameters,
+        Some(&interface_name),
+        &csv_file,
+        true,
+    )?;
+    let mut exporters = Exporters::from_args(records, &arg.files, &arg.parameters, &config.sinks)?;
+    info!("Exporting flows to {} sinks", exporters.len());
+
+    let (flow_table, mut finished) = ShardedFlowTable::from_parameters(&arg.parameters)?;
+    info!("Processing packets on {} workers", flow_table.workers());
+    let capture = thread::Builder::new()
+        .name("fluere-capture".to_string())
+        .spawn(move || capture_packets(cap_device, flow_table, duration))?;
+
+    // The channel closes once the capture ended and every shard flushed its flows
+    while let Some(flows) = finished.recv().await {
+        exporters.write_batch(&flows);
+        for flow in flows {
+            plugin_manager
+                .process_flow_data(flow)
+                .await
+                .map_err(|e| FluereError::PluginError(e.to_string()))?;
+        }
 
-    //let mut wtr = csv::Writer::from_writer(file);
+        // Start new export files if the interval has been reached
+        if last_export.elapsed() >= Duration::from_millis(interval) && interval != 0 {
+            // No need to handle expired flows here, as the flow table expires them on ingest
+            exporters.rotate();
+            last_export = Instant::now();
+        }
+    }
 
-    let mut records: Vec<FluereRecord> = Vec::new();
-    let mut active_flow: HashMap<Key, FluereRecord> = HashMap::new();
-    let tasks: Vec<JoinHandle<Result<(), FluereError>>> = vec![];
-    let mut export_tasks = vec![];
+    if capture.join().is_err() {
+        error!("Capture thread panicked");
+    }
+    debug!("Captured in {:?}", start.elapsed());
+    exporters.close();
+    plugin_manager.await_completion(plugin_worker).await;
+    drop(plugin_manager);
+    // info!("Exporting task excutation result: {:?}", result);
 
-    // Initialize flow_expirations BTreeMap
-    let mut flow_expirations: BTreeMap<u64, Vec<Key>> = BTreeMap::new();
+    Ok(())
+}
 
+// Reads packets off the interface and hands them to the flow table workers until the
+// duration has been reached, then flushes the workers.
+fn capture_packets(mut cap_device: CaptureDevice, mut flow_table: ShardedFlowTable, duration: u64) {
+    let start = Instant::now();
     loop {
-        match cap.next_packet() {
+        match cap_device.capture.next_packet() {
             Err(e) => {
                 trace!("Error capturing packet: {}", e);
-                continue;
+                // Nothing arrived before the read timeout, don't hold back batched packets
+                flow_table.flush_batches();
             }
             Ok(packet) => {
                 trace!("received packet");
-
-                let (mut key_value, mut reverse_key) = match parse_keys(packet.clone()) {
-                    Ok(keys) => keys,
-                    Err(e) => {
-                        debug!("Error on parse_keys: {}", e);
-                        continue;
-                    }
-                };
-                if !use_mac {
-                    key_value.mac_defaultate();
-                    reverse_key.mac_defaultate();
-                }
-
-                let (doctets, raw_flags, flowdata) = match parse_fluereflow(packet.clone()) {
-                    Ok(result) => result,
-                    Err(e) => {
-                        debug!("Error on parse_fluereflow: {}", e);
-                        continue;
-                    }
-                };
-
-                let flags = TcpFlags::new(raw_flags);
-                //pushing packet in to active_flows if it is not present
-                let packet_time = parse_microseconds(
-                    packet.header.ts.tv_sec as u64,
-                    packet.header.ts.tv_usec as u64,
-                );
-
-                // When a new flow is established
-                let is_reverse = match active_flow.get(&key_value) {
-                    None => match active_flow.get(&reverse_key) {
-                        None => {
-                            // If the protocol is TCP, check if it's a SYN packet
-                            if flowdata.prot == 6 {
-                                if flags.syn > 0 {
-                                    let expiration_time = packet_time + (flow_timeout * 1_000); // Convert milliseconds to microseconds
-                                    flow_expirations
-                                        .entry(expiration_time)
-                                        .or_default()
-                                        .push(key_value);
-                                    active_flow.insert(key_value, flowdata);
-                                    trace!("flow established");
-                                } else {
-                                    continue;
-                                }
-                            } else {
-                                let expiration_time = packet_time + (flow_timeout * 1_000); // Convert milliseconds to microseconds
-                                flow_expirations
-                                    .entry(expiration_time)
-                                    .or_default()
-                                    .push(key_value);
-                                active_flow.insert(key_value, flowdata);
-                                trace!("flow established");
-                            }
-                            false
-                        }
-                        Some(_) => true,
-                    },
-                    Some(_) => false,
-                };
-
-                let flow_key = if is_reverse { &reverse_key } else { &key_value };
-
-                // Update the flow
-                if let Some(flow) = active_flow.get_mut(flow_key) {
-                    let update_key = UDFlowKey {
-                        doctets,
-                        pkt: flowdata.min_pkt,
-                        ttl: flowdata.min_ttl,
-                        flags,
-                        time: packet_time,
-                    };
-                    update_flow(flow, is_reverse, update_key);
-
-                    // Update the expiration time for the flow
-                    let new_expiration_time = packet_time + (flow_timeout * 1_000);
-                    flow_expirations
-                        .entry(new_expiration_time)
-                        .or_default()
-                        .push(*flow_key);
-
-                    trace!(
-                        "{} flow updated",
-                        if is_reverse { "reverse" } else { "forward" }
-                    );
-                    trace!("flow key detail: {:?}", flow_key);
-
-                    // Check if the flow has finished
-                    if flags.is_finished() {
-                        trace!("flow finished");
-                        trace!("flow data: {:?}", flow);
-
-                        plugin_manager
-                            .process_flow_data(*flow)
-                            .await
-                            .map_err(|e| FluereError::PluginError(e.to_string()))?;
-                        records.push(*flow);
-
-                        active_flow.remove(flow_key);
-
-                        // Remove the flow from flow_expirations
-                        for (_exp_time, keys) in flow_expirations.iter_mut() {
-                            if let Some(pos) = keys.iter().position(|k| k == flow_key) {
-                                keys.swap_remove(pos);
-                                break;
-                            }
-                        }
-                    }
-                }
-
-                // Remove expired flows before processing the next packet
-                let current_time = packet_time;
-                let expired_times: Vec<u64> = flow_expirations
-                    .range(..=current_time)
-                    .map(|(&time, _)| time)
-                    .collect();
-
-                for expiration_time in expired_times {
-                    if let Some(keys) = flow_expirations.remove(&expiration_time) {
-                        for key in keys {
-                            if let Some(flow) = active_flow.remove(&key) {
-                                trace!("flow expired");
-                                plugin_manager
-                                    .process_flow_data(flow)
-                                    .await
-                                    .map_err(|e| FluereError::PluginError(e.to_string()))?;
-                                records.push(flow);
-                            }
-                        }
-                    }
-                }
-
-                // Export flows if the interval has been reached
-                if last_export.elapsed() >= Duration::from_millis(interval) && interval != 0 {
-                    // No need to handle expired flows here, as we now handle them with flow_expirations
-                    // Proceed with exporting the current records
-                    let records_to_export = take(&mut records);
-                    debug!("Calculating timeout done");
-
-                    let file_path_clone = file_path.clone();
-                    info!("Export {} Started", file_path_clone);
-                    export_tasks.push(task::spawn(async move {
-                        let exporter = fluere_exporter(records_to_export, file).await;
-                        if let Err(err) = exporter {
-                            error!("Export error: {}", err);
-                        }
-                        info!("Export {} Finished", file_path_clone);
-                    }));
-
-                    info!("running without blocking");
-                    file_path = cur_time_file(&csv_file, file_dir, ".csv");
-                    file = fs::File::create(file_path.as_ref())?;
-                    last_export = Instant::now();
-                }
-
-                // Check if the duration has been reached
-                if start.elapsed() >= Duration::from_millis(duration) && duration != 0 {
-                    break;
-                }
+                flow_table.dispatch(&packet);
             }
         }
-    }
 
-    // After the loop, handle any remaining flows
-    // Remove any flows that have not yet expired and process them
-    for (_exp_time, keys) in flow_expirations.iter() {
-        for key in keys {
-            if let Some(flow) = active_flow.remove(key) {
-                plugin_manager
-                    .process_flow_data(flow)
-                    .await
-                    .map_err(|e| FluereError::PluginError(e.to_string()))?;
-                records.push(flow);
-            }
+        // Check if the duration has been reached
+        if start.elapsed() >= Duration::from_millis(duration) && duration != 0 {
+            break;
         }
     }
-
-    debug!("Captured in {:?}", start.elapsed());
-    for (_key, flow) in active_flow.iter() {
-        plugin_manager
-            .process_flow_data(*flow)
-            .await
-            .map_err(|e| FluereError::PluginError(e.to_string()))?;
-        records.push(*flow);
-    }
-    for task in tasks {
-        let _ = task.await;
-    }
-
-    let records_to_export = take(&mut records);
-    export_tasks.push(task::spawn(async {
-        let exporter = fluere_exporter(records_to_export, file).await;
-        if let Err(err) = exporter {
-            error!("Export error: {}", err);
-        }
-    }));
-    plugin_manager.await_completion(plugin_worker).await;
-    drop(plugin_manager);
-    for task in export_tasks {
-        let _ = task.await;
-    }
-    // info!("Exporting task excutation result: {:?}", result);
-
-    Ok(())
+    flow_table.finish();
 }
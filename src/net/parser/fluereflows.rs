@@ -1,122 +1,41 @@
-use pcap;
-
 use crate::net::NetError;
 use crate::net::parser::raw::RawProtocolHeader;
-use crate::net::parser::{dscp_to_tos, parse_flags, parse_microseconds, parse_ports};
+use crate::net::parser::{
+    DecodedFrame, dscp_to_tos, parse_flags, parse_ipv6_upper_layer, parse_ports,
+};
 
 use fluereflow::FluereRecord;
 use log::trace;
 use pnet::packet::{
     Packet, PacketSize,
     arp::ArpPacket,
-    ethernet::{EtherTypes, EthernetPacket},
+    ethernet::EtherTypes,
     ip::IpNextHeaderProtocols,
     ipv4::Ipv4Packet,
     ipv6::Ipv6Packet,
     udp::UdpPacket,
 };
 
-const VXLAN_HEADER: [u8; 8] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00];
-
-fn decapsulate_vxlan(payload: &[u8]) -> Option<Vec<u8>> {
-    if payload.starts_with(&VXLAN_HEADER) {
-        //println!("Decapsulating VXLAN");
-        Some(payload[VXLAN_HEADER.len()..].to_vec())
-    } else {
-        None
-    }
-}
-
-pub fn parse_fluereflow(packet: pcap::Packet) -> Result<(usize, [u8; 9], FluereRecord), NetError> {
+/// Builds a single-packet flow record from the innermost headers of a decoded frame.
+pub fn parse_fluereflow(
+    frame: &DecodedFrame,
+    time: u64,
+) -> Result<(usize, [u8; 9], FluereRecord), NetError> {
     trace!("Parsing packet");
-    if packet.is_empty() {
+    let DecodedFrame {
+        vlan,
+        ethertype,
+        payload,
+        tunnel,
+        ..
+    } = *frame;
+    if payload.is_empty() {
         return Err(NetError::EmptyPacket);
     }
 
-    let ethernet_packet_raw = EthernetPacket::new(packet.data);
-    let ethernet_packet_unpack = match ethernet_packet_raw {
-        None => return Err(NetError::EmptyPacket),
-        Some(e) => e,
-    };
-
-    let is_udp: bool = match ethernet_packet_unpack.get_ethertype() {
-        EtherTypes::Ipv6 => {
-            let i = match Ipv6Packet::new(ethernet_packet_unpack.payload()) {
-                Some(packet) => packet,
-                None => return Err(NetError::InvalidPacket),
-            };
-            UdpPacket::new(i.payload()).is_some()
-        }
+    let (doctets, flags, mut record) = match ethertype {
         EtherTypes::Ipv4 => {
-            let i = match Ipv4Packet::new(ethernet_packet_unpack.payload()) {
-                Some(packet) => packet,
-                None => return Err(NetError::InvalidPacket),
-            };
-            UdpPacket::new(i.payload()).is_some()
-        }
-        _ => false,
-    };
-
-    let mut decapsulated_data: Option<Vec<u8>> = None;
-
-    if is_udp {
-        let udp_payload = match ethernet_packet_unpack.get_ethertype() {
-            EtherTypes::Ipv6 => {
-                let i = match Ipv6Packet::new(ethernet_packet_unpack.payload()) {
-                    Some(packet) => packet,
-                    None => return Err(NetError::InvalidPacket),
-                };
-
-                match UdpPacket::new(i.payload()) {
-                    Some(udp) => {
-                        trace!("UDP payload length: {}", udp.payload().len());
-                        udp.payload().to_vec()
-                    }
-                    None => return Err(NetError::InvalidPacket),
-                }
-            }
-            EtherTypes::Ipv4 => {
-                let i = match Ipv4Packet::new(ethernet_packet_unpack.payload()) {
-                    Some(packet) => packet,
-                    None => return Err(NetError::InvalidPacket),
-                };
-
-                match UdpPacket::new(i.payload()) {
-                    Some(udp) => {
-                        trace!("UDP payload length: {}", udp.payload().len());
-                        udp.payload().to_vec()
-                    }
-                    None => return Err(NetError::InvalidPacket),
-                }
-            }
-            _ => Vec::new(),
-        };
-
-        // Only check if UDP payload is empty when we expect it to have content
-        if udp_payload.is_empty() && is_udp {
-            trace!("Empty UDP payload detected");
-        }
-
-        decapsulated_data = decapsulate_vxlan(&udp_payload);
-    }
-
-    let ethernet_packet = if let Some(data) = &decapsulated_data {
-        match EthernetPacket::new(data) {
-            None => ethernet_packet_unpack, // Fall back to original packet if decapsulation fails
-            Some(e) => e,
-        }
-    } else {
-        ethernet_packet_unpack
-    };
-
-    let time = parse_microseconds(
-        packet.header.ts.tv_sec as u64,
-        packet.header.ts.tv_usec as u64,
-    );
-
-    let record_result = match ethernet_packet.get_ethertype() {
-        EtherTypes::Ipv4 => {
-            let i = match Ipv4Packet::new(ethernet_packet.payload()) {
+            let i = match Ipv4Packet::new(payload) {
                 Some(packet) => packet,
                 None => {
                     trace!("Failed to parse IPv4 packet");
@@ -126,7 +45,7 @@ pub fn parse_fluereflow(packet: pcap::Packet) -> Result<(usize, [u8; 9], FluereR
             ipv4_packet(time, i)
         }
         EtherTypes::Ipv6 => {
-            let i = match Ipv6Packet::new(ethernet_packet.payload()) {
+            let i = match Ipv6Packet::new(payload) {
                 Some(packet) => packet,
                 None => {
                     trace!("Failed to parse IPv6 packet");
@@ -136,7 +55,7 @@ pub fn parse_fluereflow(packet: pcap::Packet) -> Result<(usize, [u8; 9], FluereR
             ipv6_packet(time, i)
         }
         EtherTypes::Arp => {
-            let i = match ArpPacket::new(ethernet_packet.payload()) {
+            let i = match ArpPacket::new(payload) {
                 Some(packet) => packet,
                 None => {
                     trace!("Failed to parse ARP packet");
@@ -148,7 +67,7 @@ pub fn parse_fluereflow(packet: pcap::Packet) -> Result<(usize, [u8; 9], FluereR
         ethertype => {
             trace!("Attempting fallback parsing for EtherType: {}", ethertype);
             if let Some(raw_header) =
-                RawProtocolHeader::from_ethertype(ethernet_packet.packet(), ethertype.0)
+                RawProtocolHeader::from_ethertype(payload, ethertype.0)
             {
                 let flags = raw_header.flags.map_or([0; 9], |f| parse_flags(f, &[]));
                 Ok((
@@ -195,7 +114,16 @@ pub fn parse_fluereflow(packet: pcap::Packet) -> Result<(usize, [u8; 9], FluereR
         }
     }?;
 
-    Ok(record_result)
+    record.outer_vlan = vlan.outer;
+    record.inner_vlan = vlan.inner;
+    if let Some(tunnel) = tunnel {
+        record.tunnel_type = tunnel.kind;
+        record.tunnel_src = tunnel.src;
+        record.tunnel_dst = tunnel.dst;
+        record.tunnel_id = tunnel.id;
+    }
+
+    Ok((doctets, flags, record))
 }
 
 fn arp_packet(time: u64, packet: ArpPacket) -> Result<(usize, [u8; 9], FluereRecord), NetError> {
@@ -336,14 +264,16 @@ fn ipv4_packet(time: u64, packet: Ipv4Packet) -> Result<(usize, [u8; 9], FluereR
 }
 
 fn ipv6_packet(time: u64, packet: Ipv6Packet) -> Result<(usize, [u8; 9], FluereRecord), NetError> {
-    let protocol = packet.get_next_header().0;
+    // walk the extension headers down to the upper-layer protocol
+    let upper_layer = parse_ipv6_upper_layer(&packet);
+    let protocol = upper_layer.protocol;
     let src_ip = packet.get_source();
     let dst_ip = packet.get_destination();
 
     // ports parsing
-    let (src_port, dst_port) = parse_ports(protocol, packet.payload())?;
+    let (src_port, dst_port) = upper_layer.ports()?;
     // TCP flags Fin Syn Rst Psh Ack Urg Ece Cwr Ns
-    let flags = parse_flags(protocol, packet.payload());
+    let flags = parse_flags(protocol, upper_layer.payload);
 
     //	Autonomous system number of the source and destination, either origin or peer
     let doctets = packet.packet_size();
@@ -352,37 +282,36 @@ fn ipv6_packet(time: u64, packet: Ipv6Packet) -> Result<(usize, [u8; 9], FluereR
     let tos_convert_result = dscp_to_tos(dscp);
     let tos = tos_convert_result.unwrap_or_default();
 
-    Ok((
-        doctets,
-        flags,
-        FluereRecord::new(
-            std::net::IpAddr::V6(src_ip),
-            std::net::IpAddr::V6(dst_ip),
-            0,
-            0,
-            time,
-            time,
-            src_port,
-            dst_port,
-            packet.get_payload_length() as u32,
-            packet.get_payload_length() as u32,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            protocol,
-            tos,
-        ),
-    ))
+    let mut record = FluereRecord::new(
+        std::net::IpAddr::V6(src_ip),
+        std::net::IpAddr::V6(dst_ip),
+        0,
+        0,
+        time,
+        time,
+        src_port,
+        dst_port,
+        packet.get_payload_length() as u32,
+        packet.get_payload_length() as u32,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        protocol,
+        tos,
+    );
+    record.flow_label = upper_layer.flow_label;
+
+    Ok((doctets, flags, record))
 }
@@ -0,0 +1,176 @@
+use crate::net::NetError;
+use crate::net::parser::parse_ports;
+
+use pnet::packet::{Packet, ipv6::Ipv6Packet};
+
+const IPV6_HEADER_LENGTH: usize = 40;
+
+// Extension header types
+const HOP_BY_HOP: u8 = 0;
+const ROUTING: u8 = 43;
+const FRAGMENT: u8 = 44;
+const AUTHENTICATION: u8 = 51;
+const DESTINATION_OPTIONS: u8 = 60;
+const MOBILITY: u8 = 135;
+const HIP: u8 = 139;
+const SHIM6: u8 = 140;
+
+/// Upper-layer part of an IPv6 packet, found by walking its extension headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6UpperLayer<'a> {
+    /// Upper-layer protocol (TCP, UDP, ICMPv6, ...)
+    pub protocol: u8,
+    /// 20-bit flow label of the IPv6 header
+    pub flow_label: u32,
+    /// Upper-layer header and data
+    pub payload: &'a [u8],
+    /// Whether this is a non-first fragment, which carries no upper-layer header
+    pub is_later_fragment: bool,
+}
+
+impl Ipv6UpperLayer<'_> {
+    /// Source and destination ports of the upper-layer header, zero for non-first fragments.
+    pub fn ports(&self) -> Result<(u16, u16), NetError> {
+        if self.is_later_fragment {
+            return Ok((0, 0));
+        }
+        parse_ports(self.protocol, self.payload)
+    }
+}
+
+/// Walks the extension header chain of an IPv6 packet down to the upper-layer protocol.
+///
+/// Walking stops at the first header that is not an extension header, at ESP (whose content
+/// is encrypted), at a non-first fragment, or when a header is truncated.
+pub fn parse_ipv6_upper_layer<'a>(packet: &'a Ipv6Packet<'_>) -> Ipv6UpperLayer<'a> {
+    walk_extension_headers(Ipv6UpperLayer {
+        protocol: packet.get_next_header().0,
+        flow_label: packet.get_flow_label(),
+        payload: packet.payload(),
+        is_later_fragment: false,
+    })
+}
+
+/// Same as [`parse_ipv6_upper_layer`], on the raw bytes of an IPv6 packet.
+///
+/// The returned payload borrows from `data`, so it can outlive the parsed header.
+pub fn parse_ipv6_upper_layer_bytes(data: &[u8]) -> Option<Ipv6UpperLayer<'_>> {
+    let packet = Ipv6Packet::new(data)?;
+    let end = (IPV6_HEADER_LENGTH + packet.get_payload_length() as usize).min(data.len());
+
+    Some(walk_extension_headers(Ipv6UpperLayer {
+        protocol: packet.get_next_header().0,
+        flow_label: packet.get_flow_label(),
+        payload: &data[IPV6_HEADER_LENGTH..end],
+        is_later_fragment: false,
+    }))
+}
+
+fn walk_extension_headers(mut upper_layer: Ipv6UpperLayer<'_>) -> Ipv6UpperLayer<'_> {
+    while let Some((next_header, header_length)) =
+        extension_header(upper_layer.protocol, upper_layer.payload)
+    {
+        if upper_layer.protocol == FRAGMENT && fragment_offset(upper_layer.payload) != 0 {
+            upper_layer.protocol = next_header;
+            upper_layer.payload = &[];
+            upper_layer.is_later_fragment = true;
+            break;
+        }
+        upper_layer.protocol = next_header;
+        upper_layer.payload = &upper_layer.payload[header_length..];
+    }
+
+    upper_layer
+}
+
+// Returns the next header and the length of the extension header at the start of `payload`,
+// or `None` when `protocol` is not an extension header or the header is truncated.
+fn extension_header(protocol: u8, payload: &[u8]) -> Option<(u8, usize)> {
+    let next_header = *payload.first()?;
+    let length_field = *payload.get(1)? as usize;
+    let header_length = match protocol {
+        HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS | MOBILITY | HIP | SHIM6 => {
+            (length_field + 1) * 8
+        }
+        FRAGMENT => 8,
+        AUTHENTICATION => (length_field + 2) * 4,
+        _ => return None,
+    };
+
+    (payload.len() >= header_length).then_some((next_header, header_length))
+}
+
+fn fragment_offset(fragment_header: &[u8]) -> u16 {
+    match fragment_header.get(2..4) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]) >> 3,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv6_packet(next_header: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![
+            0x60, 0x01, 0x23, 0x45, // Version=6, Traffic Class=0, Flow Label=0x12345
+        ];
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(&[next_header, 64]);
+        data.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        data.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_no_extension_headers() {
+        let data = ipv6_packet(17, &[0x04, 0xd2, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00]);
+        let packet = Ipv6Packet::new(&data).unwrap();
+        let upper_layer = parse_ipv6_upper_layer(&packet);
+
+        assert_eq!(upper_layer.protocol, 17);
+        assert_eq!(upper_layer.flow_label, 0x12345);
+        assert_eq!(upper_layer.payload.len(), 8);
+        assert!(!upper_layer.is_later_fragment);
+    }
+
+    #[test]
+    fn test_hop_by_hop_and_destination_options() {
+        let payload = [
+            60, 0, 1, 4, 0, 0, 0, 0, // Hop-by-Hop, 8 bytes
+            6, 1, 1, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Destination Options, 16 bytes
+            0x9c, 0x40, 0x00, 0x50, // TCP source port 40000, destination port 80
+        ];
+        let data = ipv6_packet(HOP_BY_HOP, &payload);
+        let packet = Ipv6Packet::new(&data).unwrap();
+        let upper_layer = parse_ipv6_upper_layer(&packet);
+
+        assert_eq!(upper_layer.protocol, 6);
+        assert_eq!(upper_layer.payload, &[0x9c, 0x40, 0x00, 0x50]);
+    }
+
+    #[test]
+    fn test_fragments() {
+        let first = [
+            17, 0, 0x00, 0x01, 0, 0, 0, 1, // Fragment, offset 0, more fragments
+            0x04, 0xd2, 0x00, 0x35, // UDP source port 1234, destination port 53
+        ];
+        let data = ipv6_packet(FRAGMENT, &first);
+        let packet = Ipv6Packet::new(&data).unwrap();
+        let upper_layer = parse_ipv6_upper_layer(&packet);
+        assert_eq!(upper_layer.protocol, 17);
+        assert_eq!(upper_layer.payload, &[0x04, 0xd2, 0x00, 0x35]);
+
+        let later = [
+            17, 0, 0x00, 0xb8, 0, 0, 0, 1, // Fragment, offset 23
+            0xde, 0xad, 0xbe, 0xef,
+        ];
+        let data = ipv6_packet(FRAGMENT, &later);
+        let packet = Ipv6Packet::new(&data).unwrap();
+        let upper_layer = parse_ipv6_upper_layer(&packet);
+        assert_eq!(upper_layer.protocol, 17);
+        assert!(upper_layer.payload.is_empty());
+        assert!(upper_layer.is_later_fragment);
+    }
+}
@@ -1,10 +1,8 @@
 use std::net::{IpAddr, Ipv4Addr};
 
 use crate::net::NetError;
-use crate::net::parser::parse_ports;
-use crate::net::types::{Key, MacAddress};
-
-use pcap;
+use crate::net::parser::{DecodedFrame, parse_ipv6_upper_layer, parse_ports};
+use crate::net::types::Key;
 
 use pnet::packet::Packet;
 use pnet::packet::arp::ArpPacket;
@@ -13,24 +11,11 @@ use pnet::packet::gre::GrePacket;
 use pnet::packet::icmpv6::Icmpv6Packet;
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv6::Ipv6Packet;
-use pnet::packet::udp::UdpPacket;
-use pnet::packet::vlan::VlanPacket;
 
 use log::trace;
 
 use super::raw::RawProtocolHeader;
 
-const VXLAN_HEADER: [u8; 8] = [0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x00];
-
-fn decapsulate_vxlan(payload: &[u8]) -> Option<Vec<u8>> {
-    if payload.starts_with(&VXLAN_HEADER) {
-        //println!("Decapsulating VXLAN");
-        Some(payload[VXLAN_HEADER.len()..].to_vec())
-    } else {
-        None
-    }
-}
-
 fn parse_ethernet_packet(
     ethernet_packet: &EthernetPacket,
 ) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetError> {
@@ -53,12 +38,6 @@ fn parse_ethernet_packet(
         Err(NetError::InvalidPacket)
     };
 
-    let parse_test_vlan = if let Some(packet) = VlanPacket::new(ethernet_packet.payload()) {
-        vlan_keys(packet)
-    } else {
-        Err(NetError::InvalidPacket)
-    };
-
     // If all standard parsers fail, try raw parser as a fallback
     let parse_test_raw = if let Some(raw_header) = RawProtocolHeader::from_raw_packet(
         ethernet_packet.payload(),
@@ -83,128 +62,34 @@ fn parse_ethernet_packet(
     trace!("parse_test_ipv4: {:?}", parse_test_ipv4);
     trace!("parse_test_ipv6: {:?}", parse_test_ipv6);
     trace!("parse_test_arp: {:?}", parse_test_arp);
-    trace!("parse_test_vlan: {:?}", parse_test_vlan);
     trace!("parse_test_raw: {:?}", parse_test_raw);
 
     // Return the first successful parse, or an error if they all fail
     parse_test_ipv4
         .or(parse_test_ipv6)
         .or(parse_test_arp)
-        .or(parse_test_vlan)
         .or(parse_test_raw)
         .map_err(|_| NetError::UnknownEtherType(ethernet_packet.get_ethertype().to_string()))
 }
 
-pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
+/// Builds the flow key of a decoded frame, from its innermost headers.
+pub fn parse_keys(frame: &DecodedFrame) -> Result<Key, NetError> {
     trace!("Parsing keys");
-    if packet.is_empty() {
+    let DecodedFrame {
+        src_mac,
+        dst_mac,
+        vlan,
+        ethertype,
+        payload,
+        tunnel,
+    } = *frame;
+    if payload.is_empty() {
         return Err(NetError::EmptyPacket);
     }
-    trace!("Parsing ethernet packet");
-    let ethernet_packet = EthernetPacket::new(packet.data).ok_or(NetError::InvalidPacket)?;
-    trace!("Parsed ethernet packet");
-
-    let is_udp: bool = match ethernet_packet.get_ethertype() {
-        EtherTypes::Ipv6 => {
-            let i = Ipv6Packet::new(ethernet_packet.payload());
-            if i.is_none() {
-                return Err(NetError::EmptyPacket);
-            }
-            
-
-            UdpPacket::new(i.unwrap().payload()).is_some()
-        }
-        EtherTypes::Ipv4 => {
-            let i = Ipv4Packet::new(ethernet_packet.payload());
-            if i.is_none() {
-                return Err(NetError::EmptyPacket);
-            }
-
-            
-
-            UdpPacket::new(i.unwrap().payload()).is_some()
-        }
-        EtherTypes::Arp => {
-            let i = ArpPacket::new(ethernet_packet.payload());
-            if i.is_none() {
-                return Err(NetError::EmptyPacket);
-            }
-
-            
-
-            UdpPacket::new(i.unwrap().payload()).is_some()
-        }
-
-        _ => false,
-    };
-    trace!("Parsed if is UDP payload");
-    trace!("is_udp: {:?}", is_udp);
-    let mut decapsulated_data: Option<Vec<u8>> = None;
-
-    if is_udp {
-        let udp_payload = match ethernet_packet.get_ethertype() {
-            EtherTypes::Ipv6 => {
-                let i = Ipv6Packet::new(ethernet_packet.payload());
-                if i.is_none() {
-                    return Err(NetError::EmptyPacket);
-                }
-
-                UdpPacket::new(i.unwrap().payload())
-                    .unwrap()
-                    .payload()
-                    .to_vec()
-            }
-            EtherTypes::Ipv4 => {
-                let i = Ipv4Packet::new(ethernet_packet.payload());
-                if i.is_none() {
-                    return Err(NetError::EmptyPacket);
-                }
-
-                UdpPacket::new(i.unwrap().payload())
-                    .unwrap()
-                    .payload()
-                    .to_vec()
-            }
-            EtherTypes::Arp => {
-                let i = ArpPacket::new(ethernet_packet.payload());
-                if i.is_none() {
-                    return Err(NetError::EmptyPacket);
-                }
-
-                UdpPacket::new(i.unwrap().payload())
-                    .unwrap()
-                    .payload()
-                    .to_vec()
-            }
-            _ => Vec::new(),
-        };
-        trace!("Parsed UDP payload");
-        if udp_payload.is_empty() {
-            return Err(NetError::EmptyPacket);
-        }
-        trace!("Parsed UDP payload");
-        //UdpPacket::new(ethernet_packet_unpack.payload()).unwrap().payload().to_vec();
-        //println!("UDP payload: {:?}", udp_payload);
-        decapsulated_data = decapsulate_vxlan(&udp_payload);
-    }
-
-    let ethernet_packet_decapsulated = if let Some(data) = &decapsulated_data {
-        match EthernetPacket::new(data) {
-            None => return Err(NetError::EmptyPacket),
-            Some(e) => e,
-        }
-    } else {
-        ethernet_packet
-    };
-
-    let ethernet_packet = ethernet_packet_decapsulated;
-
-    let src_mac = MacAddress::new(ethernet_packet.get_source().into());
-    let dst_mac = MacAddress::new(ethernet_packet.get_destination().into());
-    trace!("ether type {:?}", ethernet_packet.get_ethertype());
-    let (src_ip, dst_ip, src_port, dst_port, protocol) = match ethernet_packet.get_ethertype() {
+    trace!("ether type {:?}", ethertype);
+    let (src_ip, dst_ip, src_port, dst_port, protocol) = match ethertype {
         EtherTypes::Ipv6 => {
-            let i = Ipv6Packet::new(ethernet_packet.payload());
+            let i = Ipv6Packet::new(payload);
             if i.is_none() {
                 return Err(NetError::EmptyPacket);
             }
@@ -214,7 +99,7 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
             ipv6_keys(i.unwrap())?
         }
         EtherTypes::Ipv4 => {
-            let i = Ipv4Packet::new(ethernet_packet.payload());
+            let i = Ipv4Packet::new(payload);
             if i.is_none() {
                 return Err(NetError::EmptyPacket);
             }
@@ -224,7 +109,7 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
             ipv4_keys(i.unwrap())?
         }
         EtherTypes::Arp => {
-            let i = ArpPacket::new(ethernet_packet.payload());
+            let i = ArpPacket::new(payload);
             if i.is_none() {
                 return Err(NetError::EmptyPacket);
             }
@@ -233,16 +118,8 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
 
             arp_keys(i.unwrap())?
         }
-        EtherTypes::Vlan => {
-            let i = VlanPacket::new(ethernet_packet.payload());
-            if i.is_none() {
-                return Err(NetError::EmptyPacket);
-            }
-            trace!("VLAN packet detected");
-            vlan_keys(i.unwrap())?
-        }
         EtherTypes::Rarp => {
-            let i = ArpPacket::new(ethernet_packet.payload());
+            let i = ArpPacket::new(payload);
             if i.is_none() {
                 return Err(NetError::EmptyPacket);
             }
@@ -251,34 +128,28 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
         }
         _ => {
             // Try standard parsers first
-            let parse_test_ipv4 = if let Some(packet) = Ipv4Packet::new(ethernet_packet.payload()) {
+            let parse_test_ipv4 = if let Some(packet) = Ipv4Packet::new(payload) {
                 ipv4_keys(packet)
             } else {
                 Err(NetError::InvalidPacket)
             };
 
-            let parse_test_ipv6 = if let Some(packet) = Ipv6Packet::new(ethernet_packet.payload()) {
+            let parse_test_ipv6 = if let Some(packet) = Ipv6Packet::new(payload) {
                 ipv6_keys(packet)
             } else {
                 Err(NetError::InvalidPacket)
             };
 
-            let parse_test_arp = if let Some(packet) = ArpPacket::new(ethernet_packet.payload()) {
+            let parse_test_arp = if let Some(packet) = ArpPacket::new(payload) {
                 arp_keys(packet)
             } else {
                 Err(NetError::InvalidPacket)
             };
 
-            let parse_test_vlan = if let Some(packet) = VlanPacket::new(ethernet_packet.payload()) {
-                vlan_keys(packet)
-            } else {
-                Err(NetError::InvalidPacket)
-            };
-
             // If all standard parsers fail, try raw parser as fallback
             let parse_test_raw = if let Some(raw_header) = RawProtocolHeader::from_raw_packet(
-                ethernet_packet.payload(),
-                ethernet_packet.get_ethertype().0 as u8,
+                payload,
+                ethertype.0 as u8,
             ) {
                 Ok((
                     raw_header
@@ -298,18 +169,14 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
             trace!("parse_test_ipv4: {:?}", parse_test_ipv4);
             trace!("parse_test_ipv6: {:?}", parse_test_ipv6);
             trace!("parse_test_arp: {:?}", parse_test_arp);
-            trace!("parse_test_vlan: {:?}", parse_test_vlan);
             trace!("parse_test_raw: {:?}", parse_test_raw);
 
             // Try to use the first successful parse result, including raw parser
             parse_test_ipv4
                 .or(parse_test_ipv6)
                 .or(parse_test_arp)
-                .or(parse_test_vlan)
                 .or(parse_test_raw)
-                .or(Err(NetError::UnknownEtherType(
-                    ethernet_packet.get_ethertype().to_string(),
-                )))?
+                .or(Err(NetError::UnknownEtherType(ethertype.to_string())))?
         }
     };
     trace!("Parsed keys");
@@ -320,6 +187,7 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
     trace!("protocol: {:?}", protocol);
     trace!("src_mac: {:?}", src_mac);
     trace!("dst_mac: {:?}", dst_mac);
+    trace!("vlan: {:?}", vlan);
     let key_value = Key {
         src_ip,
         src_port,
@@ -328,18 +196,12 @@ pub fn parse_keys(packet: pcap::Packet) -> Result<(Key, Key), NetError> {
         protocol,
         src_mac,
         dst_mac,
-    };
-    let key_reverse_value = Key {
-        src_ip: dst_ip,
-        src_port: dst_port,
-        dst_ip: src_ip,
-        dst_port: src_port,
-        protocol,
-        src_mac: dst_mac,
-        dst_mac: src_mac,
+        outer_vlan: vlan.outer,
+        inner_vlan: vlan.inner,
+        tunnel_id: tunnel.map_or(0, |tunnel| tunnel.id),
     };
 
-    Ok((key_value, key_reverse_value))
+    Ok(key_value)
 }
 
 fn arp_keys(packet: ArpPacket) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetError> {
@@ -390,12 +252,13 @@ fn ipv4_keys(packet: Ipv4Packet) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetEr
 fn ipv6_keys(packet: Ipv6Packet) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetError> {
     let src_ip = packet.get_source();
     let dst_ip = packet.get_destination();
-    let protocol = packet.get_next_header().0;
-    let (src_port, dst_port) = parse_ports(protocol, packet.payload())?;
+    let upper_layer = parse_ipv6_upper_layer(&packet);
+    let protocol = upper_layer.protocol;
+    let (src_port, dst_port) = upper_layer.ports()?;
 
     // Handle ICMPv6 specially
     if protocol == 58
-        && let Some(icmpv6) = Icmpv6Packet::new(packet.payload()) {
+        && let Some(icmpv6) = Icmpv6Packet::new(upper_layer.payload) {
             return Ok((
                 std::net::IpAddr::V6(src_ip),
                 std::net::IpAddr::V6(dst_ip),
@@ -413,23 +276,3 @@ fn ipv6_keys(packet: Ipv6Packet) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetEr
         protocol,
     ))
 }
-
-fn vlan_keys(packet: VlanPacket) -> Result<(IpAddr, IpAddr, u16, u16, u8), NetError> {
-    trace!("Parsing VLAN packet");
-    let inner_packet = EthernetPacket::new(packet.payload()).ok_or(NetError::InvalidPacket)?;
-    match inner_packet.get_ethertype() {
-        EtherTypes::Ipv4 => {
-            let ipv4_packet =
-                Ipv4Packet::new(inner_packet.payload()).ok_or(NetError::InvalidPacket)?;
-            ipv4_keys(ipv4_packet)
-        }
-        EtherTypes::Ipv6 => {
-            let ipv6_packet =
-                Ipv6Packet::new(inner_packet.payload()).ok_or(NetError::InvalidPacket)?;
-            ipv6_keys(ipv6_packet)
-        }
-        _ => Err(NetError::UnknownEtherType(
-            inner_packet.get_ethertype().to_string(),
-        )),
-    }
-}
@@ -2,22 +2,28 @@ mod etherprotocol;
 mod flags;
 mod fluereflows;
 mod ipv4;
+mod ipv6;
 mod keys;
 mod ports;
 // mod protocol;
 mod raw;
 mod time;
 mod tos;
+mod tunnel;
 mod udp;
+mod vlan;
 
 pub use etherprotocol::_parse_etherprotocol;
 pub use flags::parse_flags;
 pub use fluereflows::parse_fluereflow;
 pub use ipv4::_parse_ipv4;
+pub use ipv6::{parse_ipv6_upper_layer, parse_ipv6_upper_layer_bytes};
 pub use keys::parse_keys;
 pub use ports::parse_ports;
 // pub use protocol::protocol_to_number;
 pub use time::microseconds_to_timestamp;
 pub use time::parse_microseconds;
 pub use tos::dscp_to_tos;
+pub use tunnel::{DecodedFrame, Tunnel, decapsulate};
 pub use udp::_parse_udp;
+pub use vlan::{VlanTags, strip_vlan_tags};
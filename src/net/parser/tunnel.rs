@@ -0,0 +1,435 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::net::NetError;
+use crate::net::parser::{VlanTags, parse_ipv6_upper_layer_bytes, strip_vlan_tags};
+use crate::net::types::MacAddress;
+
+use fluereflow::TunnelType;
+use log::trace;
+use pnet::packet::{
+    ethernet::{EtherType, EtherTypes},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+};
+
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const GRE_HEADER_LENGTH: usize = 4;
+const UDP_HEADER_LENGTH: usize = 8;
+const VXLAN_HEADER_LENGTH: usize = 8;
+const GENEVE_HEADER_LENGTH: usize = 8;
+const MPLS_LABEL_LENGTH: usize = 4;
+
+const VXLAN_PORT: u16 = 4789;
+const GENEVE_PORT: u16 = 6081;
+
+// GRE protocol type of an Ethernet frame (Transparent Ethernet Bridging)
+const TRANSPARENT_ETHERNET: u16 = 0x6558;
+
+const GRE_CHECKSUM_PRESENT: u16 = 0x8000;
+const GRE_KEY_PRESENT: u16 = 0x2000;
+const GRE_SEQUENCE_PRESENT: u16 = 0x1000;
+const GRE_VERSION_MASK: u16 = 0x0007;
+
+/// Outermost tunnel a frame was carried in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tunnel {
+    pub kind: TunnelType,
+    /// Outer source address, unspecified for MPLS directly over Ethernet
+    pub src: IpAddr,
+    /// Outer destination address, unspecified for MPLS directly over Ethernet
+    pub dst: IpAddr,
+    /// VNI, GRE key, NVGRE virtual subnet ID or top MPLS label, 0 when absent
+    pub id: u32,
+}
+
+/// Innermost L3 header of a frame, after tunnel decapsulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedFrame<'a> {
+    /// MAC addresses of the innermost Ethernet header
+    pub src_mac: MacAddress,
+    pub dst_mac: MacAddress,
+    /// VLAN tags of the innermost Ethernet header
+    pub vlan: VlanTags,
+    /// EtherType of the L3 header
+    pub ethertype: EtherType,
+    /// L3 header and data
+    pub payload: &'a [u8],
+    pub tunnel: Option<Tunnel>,
+}
+
+// What a tunnel header is followed by
+enum Inner<'a> {
+    Ethernet(&'a [u8]),
+    L3(EtherType, &'a [u8]),
+}
+
+/// Decodes an Ethernet frame and strips up to `max_depth` layers of tunnel encapsulation.
+///
+/// VXLAN (any VNI, UDP 4789), GENEVE (UDP 6081), GRE, NVGRE, IP-in-IP, 6in4 and MPLS are
+/// recognised. A `max_depth` of 0 keeps the outer headers. When an inner frame cannot be
+/// decoded, or a tunnel carries neither Ethernet nor IP, the frame is kept as it was before
+/// that layer.
+pub fn decapsulate(data: &[u8], max_depth: u8) -> Result<DecodedFrame<'_>, NetError> {
+    let mut frame = ethernet_frame(data)?;
+
+    for _ in 0..max_depth {
+        let Some((tunnel, inner)) = next_layer(frame.ethertype, frame.payload) else {
+            break;
+        };
+        trace!("{} tunnel detected, id {}", tunnel.kind, tunnel.id);
+        let outermost = *frame.tunnel.get_or_insert(tunnel);
+
+        match inner {
+            Inner::Ethernet(data) => match ethernet_frame(data) {
+                Ok(inner_frame) => {
+                    frame = DecodedFrame {
+                        tunnel: Some(outermost),
+                        ..inner_frame
+                    }
+                }
+                Err(_) => break,
+            },
+            Inner::L3(ethertype, payload) => {
+                frame.ethertype = ethertype;
+                frame.payload = payload;
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+fn ethernet_frame(data: &[u8]) -> Result<DecodedFrame<'_>, NetError> {
+    if data.len() < ETHERNET_HEADER_LENGTH {
+        return Err(NetError::InvalidPacket);
+    }
+    let mut dst_mac = [0; 6];
+    let mut src_mac = [0; 6];
+    dst_mac.copy_from_slice(&data[0..6]);
+    src_mac.copy_from_slice(&data[6..12]);
+    let ethertype = EtherType(u16::from_be_bytes([data[12], data[13]]));
+    let (vlan, ethertype, payload) = strip_vlan_tags(ethertype, &data[ETHERNET_HEADER_LENGTH..]);
+
+    Ok(DecodedFrame {
+        src_mac: MacAddress::new(src_mac),
+        dst_mac: MacAddress::new(dst_mac),
+        vlan,
+        ethertype,
+        payload,
+        tunnel: None,
+    })
+}
+
+fn next_layer(ethertype: EtherType, payload: &[u8]) -> Option<(Tunnel, Inner<'_>)> {
+    match ethertype {
+        EtherTypes::Ipv4 => {
+            let packet = Ipv4Packet::new(payload)?;
+            if packet.get_fragment_offset() != 0 {
+                return None;
+            }
+            let header_length = packet.get_header_length() as usize * 4;
+            let end = (packet.get_total_length() as usize).min(payload.len());
+            let ip_payload = payload.get(header_length..end)?;
+            let src = IpAddr::V4(packet.get_source());
+            let dst = IpAddr::V4(packet.get_destination());
+            ip_tunnel(src, dst, packet.get_next_level_protocol().0, ip_payload)
+        }
+        EtherTypes::Ipv6 => {
+            let packet = Ipv6Packet::new(payload)?;
+            let upper_layer = parse_ipv6_upper_layer_bytes(payload)?;
+            if upper_layer.is_later_fragment {
+                return None;
+            }
+            let src = IpAddr::V6(packet.get_source());
+            let dst = IpAddr::V6(packet.get_destination());
+            ip_tunnel(src, dst, upper_layer.protocol, upper_layer.payload)
+        }
+        EtherTypes::Mpls | EtherTypes::MplsMcast => mpls(payload),
+        _ => None,
+    }
+}
+
+fn ip_tunnel(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    payload: &[u8],
+) -> Option<(Tunnel, Inner<'_>)> {
+    let (kind, id, inner) = match protocol {
+        4 => (TunnelType::IpInIp, 0, Inner::L3(EtherTypes::Ipv4, payload)),
+        41 => (
+            TunnelType::Ipv6InIp,
+            0,
+            Inner::L3(EtherTypes::Ipv6, payload),
+        ),
+        47 => gre(payload)?,
+        17 => udp_tunnel(payload)?,
+        _ => return None,
+    };
+
+    Some((Tunnel { kind, src, dst, id }, inner))
+}
+
+fn gre(payload: &[u8]) -> Option<(TunnelType, u32, Inner<'_>)> {
+    let flags = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]);
+    let protocol = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    // Version 1 is the enhanced GRE of PPTP, which carries PPP
+    if flags & GRE_VERSION_MASK != 0 {
+        return None;
+    }
+
+    let mut offset = GRE_HEADER_LENGTH;
+    if flags & GRE_CHECKSUM_PRESENT != 0 {
+        offset += 4;
+    }
+    let key = if flags & GRE_KEY_PRESENT != 0 {
+        let key = read_u32(payload, offset)?;
+        offset += 4;
+        Some(key)
+    } else {
+        None
+    };
+    if flags & GRE_SEQUENCE_PRESENT != 0 {
+        offset += 4;
+    }
+    let inner_payload = payload.get(offset..)?;
+
+    Some(match (protocol, key) {
+        // The low byte of the NVGRE key is the flow ID, the rest is the virtual subnet ID
+        (TRANSPARENT_ETHERNET, Some(key)) => {
+            (TunnelType::Nvgre, key >> 8, Inner::Ethernet(inner_payload))
+        }
+        (TRANSPARENT_ETHERNET, None) => (TunnelType::Gre, 0, Inner::Ethernet(inner_payload)),
+        (protocol, key) => (
+            TunnelType::Gre,
+            key.unwrap_or(0),
+            ip_inner(protocol, inner_payload)?,
+        ),
+    })
+}
+
+fn udp_tunnel(payload: &[u8]) -> Option<(TunnelType, u32, Inner<'_>)> {
+    let dst_port = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    let udp_payload = payload.get(UDP_HEADER_LENGTH..)?;
+
+    match dst_port {
+        VXLAN_PORT => vxlan(udp_payload),
+        GENEVE_PORT => geneve(udp_payload),
+        _ => None,
+    }
+}
+
+fn vxlan(payload: &[u8]) -> Option<(TunnelType, u32, Inner<'_>)> {
+    // The I flag marks a valid VNI
+    if payload.first()? & 0x08 == 0 {
+        return None;
+    }
+    let vni = read_u32(payload, 4)? >> 8;
+    let inner_payload = payload.get(VXLAN_HEADER_LENGTH..)?;
+
+    Some((TunnelType::Vxlan, vni, Inner::Ethernet(inner_payload)))
+}
+
+fn geneve(payload: &[u8]) -> Option<(TunnelType, u32, Inner<'_>)> {
+    let version_and_length = *payload.first()?;
+    if version_and_length >> 6 != 0 {
+        return None;
+    }
+    let options_length = (version_and_length & 0x3f) as usize * 4;
+    let protocol = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    let vni = read_u32(payload, 4)? >> 8;
+    let inner_payload = payload.get(GENEVE_HEADER_LENGTH + options_length..)?;
+
+    let inner = match protocol {
+        TRANSPARENT_ETHERNET => Inner::Ethernet(inner_payload),
+        protocol => ip_inner(protocol, inner_payload)?,
+    };
+    Some((TunnelType::Geneve, vni, inner))
+}
+
+// Inner IP header of a tunnel naming its payload by EtherType. Other payloads cannot be keyed,
+// so the tunnel is not decapsulated.
+fn ip_inner(protocol: u16, payload: &[u8]) -> Option<Inner<'_>> {
+    match EtherType(protocol) {
+        ethertype @ (EtherTypes::Ipv4 | EtherTypes::Ipv6) => Some(Inner::L3(ethertype, payload)),
+        _ => None,
+    }
+}
+
+fn mpls(payload: &[u8]) -> Option<(Tunnel, Inner<'_>)> {
+    let top_label = read_u32(payload, 0)? >> 12;
+
+    // Pop labels until the bottom of the stack
+    let mut offset = 0;
+    loop {
+        let bottom_of_stack = payload.get(offset + 2)? & 0x01 != 0;
+        offset += MPLS_LABEL_LENGTH;
+        if bottom_of_stack {
+            break;
+        }
+    }
+    let inner_payload = payload.get(offset..)?;
+
+    // MPLS does not say what it carries, guess from the IP version
+    let ethertype = match inner_payload.first()? >> 4 {
+        4 => EtherTypes::Ipv4,
+        6 => EtherTypes::Ipv6,
+        _ => return None,
+    };
+    let tunnel = Tunnel {
+        kind: TunnelType::Mpls,
+        src: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        dst: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        id: top_label,
+    };
+    Some((tunnel, Inner::L3(ethertype, inner_payload)))
+}
+
+fn read_u32(payload: &[u8], offset: usize) -> Option<u32> {
+    let bytes = payload.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::net::parser::parse_keys;
+
+    const INNER_IPV4_UDP: [u8; 28] = [
+        0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x40, 0x11, 0x00, 0x00, // IPv4, UDP
+        192, 168, 0, 1, 192, 168, 0, 2, // 192.168.0.1 -> 192.168.0.2
+        0x30, 0x39, 0x00, 0x35, 0x00, 0x08, 0x00, 0x00, // UDP 12345 -> 53
+    ];
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb,
+        ];
+        data.extend_from_slice(&ethertype.to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn outer_ipv4(protocol: u8, payload: &[u8]) -> Vec<u8> {
+        let total_length = (20 + payload.len()) as u16;
+        let mut data = vec![0x45, 0x00];
+        data.extend_from_slice(&total_length.to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x40, protocol, 0x00, 0x00]);
+        data.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn outer_udp(dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xc0, 0x00];
+        data.extend_from_slice(&dst_port.to_be_bytes());
+        data.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[0x00, 0x00]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn assert_inner_udp(frame: &DecodedFrame) {
+        assert_eq!(frame.ethertype, EtherTypes::Ipv4);
+        assert_eq!(frame.payload, &INNER_IPV4_UDP);
+    }
+
+    #[test]
+    fn test_vxlan_any_vni() {
+        let mut vxlan = vec![0x08, 0x00, 0x00, 0x00, 0x01, 0x23, 0x45, 0x00]; // VNI 0x12345
+        vxlan.extend_from_slice(&ethernet(0x0800, &INNER_IPV4_UDP));
+        let data = ethernet(0x0800, &outer_ipv4(17, &outer_udp(VXLAN_PORT, &vxlan)));
+
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_inner_udp(&frame);
+        let tunnel = frame.tunnel.unwrap();
+        assert_eq!(tunnel.kind, TunnelType::Vxlan);
+        assert_eq!(tunnel.id, 0x12345);
+        assert_eq!(tunnel.src, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(tunnel.dst, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+
+        // Depth 0 keeps the outer headers
+        let frame = decapsulate(&data, 0).unwrap();
+        assert!(frame.tunnel.is_none());
+        assert_eq!(frame.payload.len(), 20 + 8 + vxlan.len());
+    }
+
+    #[test]
+    fn test_geneve_with_options() {
+        let mut geneve = vec![0x01, 0x00, 0x65, 0x58, 0x00, 0x00, 0x2a, 0x00]; // 4 bytes options
+        geneve.extend_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        geneve.extend_from_slice(&ethernet(0x0800, &INNER_IPV4_UDP));
+        let data = ethernet(0x0800, &outer_ipv4(17, &outer_udp(GENEVE_PORT, &geneve)));
+
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_inner_udp(&frame);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::Geneve);
+        assert_eq!(frame.tunnel.unwrap().id, 42);
+    }
+
+    #[test]
+    fn test_gre_and_nvgre() {
+        let mut gre = vec![0x20, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x07]; // key 7, IPv4
+        gre.extend_from_slice(&INNER_IPV4_UDP);
+        let data = ethernet(0x0800, &outer_ipv4(47, &gre));
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_inner_udp(&frame);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::Gre);
+        assert_eq!(frame.tunnel.unwrap().id, 7);
+
+        let mut nvgre = vec![0x20, 0x00, 0x65, 0x58, 0x00, 0x10, 0x00, 0x01]; // VSID 0x1000
+        nvgre.extend_from_slice(&ethernet(0x0800, &INNER_IPV4_UDP));
+        let data = ethernet(0x0800, &outer_ipv4(47, &nvgre));
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_inner_udp(&frame);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::Nvgre);
+        assert_eq!(frame.tunnel.unwrap().id, 0x1000);
+    }
+
+    #[test]
+    fn test_unknown_gre_protocol_keeps_the_outer_headers() {
+        let mut gre = vec![0x00, 0x00, 0x88, 0x0b]; // PPP
+        gre.extend_from_slice(&[0xff, 0x03, 0x00, 0x21]);
+        let outer = outer_ipv4(47, &gre);
+        let data = ethernet(0x0800, &outer);
+
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_eq!(frame.ethertype, EtherTypes::Ipv4);
+        assert_eq!(frame.payload, &outer[..]);
+        assert!(frame.tunnel.is_none());
+
+        let key = parse_keys(&frame).unwrap();
+        assert_eq!(key.src_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(key.dst_ip, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(key.protocol, 47);
+    }
+
+    #[test]
+    fn test_ip_in_ip() {
+        let data = ethernet(0x0800, &outer_ipv4(4, &INNER_IPV4_UDP));
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_inner_udp(&frame);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::IpInIp);
+    }
+
+    #[test]
+    fn test_mpls_then_ip_in_ip() {
+        let mut mpls = vec![
+            0x00, 0x01, 0x00, 0x40, // label 16
+            0x00, 0x01, 0x11, 0x40, // label 17, bottom of stack
+        ];
+        mpls.extend_from_slice(&outer_ipv4(4, &INNER_IPV4_UDP));
+        let data = ethernet(0x8847, &mpls);
+
+        let frame = decapsulate(&data, 1).unwrap();
+        assert_eq!(frame.ethertype, EtherTypes::Ipv4);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::Mpls);
+        assert_eq!(frame.tunnel.unwrap().id, 16);
+
+        // The outermost tunnel is kept when going deeper
+        let frame = decapsulate(&data, 2).unwrap();
+        assert_inner_udp(&frame);
+        assert_eq!(frame.tunnel.unwrap().kind, TunnelType::Mpls);
+    }
+}
@@ -0,0 +1,85 @@
+use pnet::packet::ethernet::{EtherType, EtherTypes};
+
+// Length of an 802.1Q/802.1ad tag following the MAC addresses (TCI + EtherType)
+const VLAN_TAG_LENGTH: usize = 4;
+
+/// VLAN IDs of an 802.1Q or 802.1ad (QinQ) tagged frame, 0 when the tag is absent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VlanTags {
+    /// Service (outer) VLAN ID, or the only VLAN ID of a single tagged frame
+    pub outer: u16,
+    /// Customer (inner) VLAN ID of a double tagged frame
+    pub inner: u16,
+}
+
+/// Strips the VLAN tag stack in front of the L3 header of an Ethernet frame.
+///
+/// Takes the EtherType and payload of the frame and returns the VLAN IDs found, the EtherType
+/// of the L3 header and the L3 payload. Tags beyond the second are skipped.
+pub fn strip_vlan_tags(ethertype: EtherType, payload: &[u8]) -> (VlanTags, EtherType, &[u8]) {
+    let mut tags = VlanTags::default();
+    let mut ethertype = ethertype;
+    let mut payload = payload;
+    let mut depth = 0;
+
+    while is_vlan_ethertype(ethertype) && payload.len() >= VLAN_TAG_LENGTH {
+        let vlan_id = u16::from_be_bytes([payload[0], payload[1]]) & 0x0fff;
+        match depth {
+            0 => tags.outer = vlan_id,
+            1 => tags.inner = vlan_id,
+            _ => {}
+        }
+        ethertype = EtherType(u16::from_be_bytes([payload[2], payload[3]]));
+        payload = &payload[VLAN_TAG_LENGTH..];
+        depth += 1;
+    }
+
+    (tags, ethertype, payload)
+}
+
+fn is_vlan_ethertype(ethertype: EtherType) -> bool {
+    // 0x9100 is the pre-standard QinQ tag still used by some switches
+    matches!(
+        ethertype,
+        EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_untagged() {
+        let payload = [0x45, 0x00];
+        let (tags, ethertype, inner) = strip_vlan_tags(EtherTypes::Ipv4, &payload);
+
+        assert_eq!(tags, VlanTags::default());
+        assert_eq!(ethertype, EtherTypes::Ipv4);
+        assert_eq!(inner, &payload);
+    }
+
+    #[test]
+    fn test_single_tag() {
+        let payload = [0x20, 0x64, 0x08, 0x00, 0x45, 0x00]; // PCP=1, VLAN 100, IPv4
+        let (tags, ethertype, inner) = strip_vlan_tags(EtherTypes::Vlan, &payload);
+
+        assert_eq!((tags.outer, tags.inner), (100, 0));
+        assert_eq!(ethertype, EtherTypes::Ipv4);
+        assert_eq!(inner, &[0x45, 0x00]);
+    }
+
+    #[test]
+    fn test_qinq() {
+        let payload = [
+            0x00, 0x0a, 0x81, 0x00, // S-tag VLAN 10, C-tag follows
+            0x0f, 0xff, 0x86, 0xdd, // C-tag VLAN 4095, IPv6
+            0x60, 0x00,
+        ];
+        let (tags, ethertype, inner) = strip_vlan_tags(EtherTypes::PBridge, &payload);
+
+        assert_eq!((tags.outer, tags.inner), (10, 4095));
+        assert_eq!(ethertype, EtherTypes::Ipv6);
+        assert_eq!(inner, &[0x60, 0x00]);
+    }
+}
@@ -0,0 +1,446 @@
+// This file contains the sharded packet processing pipeline of the online and offline modes.
+// The capture thread hashes every packet by its endpoints, read at fixed offsets without parsing
+// the packet, and hands it to one of the worker threads, each owning the flow table of its share
+// of the flows. Since both directions of a flow hash to the same shard, shards never share
+// state, and the records they finish are merged into a single channel feeding the export and
+// plugin stages. Decapsulation and flow keys are left to the workers.
+// The live mode keeps a single flow table instead, which its UI reads from.
+
+use std::{
+    hash::{BuildHasher, RandomState},
+    mem::take,
+    net::IpAddr,
+    sync::mpsc::{Receiver, SyncSender, sync_channel},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    FluereError,
+    net::{
+        FlowTable,
+        parser::{parse_ipv6_upper_layer_bytes, parse_microseconds, strip_vlan_tags},
+    },
+    types::Parameters,
+};
+
+use fluereflow::FluereRecord;
+use log::{debug, error, trace};
+use pcap::{Packet, PacketHeader};
+use pnet::packet::{
+    ethernet::{EtherType, EtherTypes},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+};
+use tokio::sync::mpsc;
+
+// Packets handed to a worker at once, to keep the channel overhead off the capture thread
+const PACKET_BATCH: usize = 64;
+// Batches queued per worker before the capture thread waits for it
+const SHARD_QUEUE: usize = 64;
+// Record batches queued before the workers wait for the export stage
+const RECORD_QUEUE: usize = 1024;
+// Packet time between expiration ticks, in microseconds, so that shards receiving no packets
+// still expire their idle flows
+const TICK_INTERVAL: u64 = 1_000_000;
+const ETHERNET_HEADER_LENGTH: usize = 14;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+// UDP tunnels whose flows are keyed by their inner headers
+const TUNNEL_PORTS: [u16; 2] = [4789, 6081];
+
+// Lower and higher endpoint of a packet, and its protocol
+type ShardKey = ((IpAddr, u16), (IpAddr, u16), u8);
+
+enum ShardMessage {
+    Packets(PacketBatch),
+    // Expire the flows idle at this packet time
+    Tick(u64),
+    // Flush the active flows, report how many there were, and send the records of the next
+    // flows to another receiver
+    Restart {
+        records: mpsc::Sender<Vec<FluereRecord>>,
+        active: SyncSender<usize>,
+    },
+}
+
+// Packets copied out of the capture buffer, into a single allocation per batch
+#[derive(Default)]
+struct PacketBatch {
+    // Header of every packet, and the end of its data
+    headers: Vec<(PacketHeader, usize)>,
+    data: Vec<u8>,
+}
+
+impl PacketBatch {
+    fn push(&mut self, packet: &Packet) {
+        self.data.extend_from_slice(packet.data);
+        self.headers.push((*packet.header, self.data.len()));
+    }
+
+    fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    fn packets(&self) -> impl Iterator<Item = Packet<'_>> {
+        let mut start = 0;
+        self.headers.iter().map(move |(header, end)| {
+            let packet = Packet::new(header, &self.data[start..*end]);
+            start = *end;
+            packet
+        })
+    }
+}
+
+struct Shard {
+    sender: SyncSender<ShardMessage>,
+    batch: PacketBatch,
+    // Returns the number of flows still active when the capture ended
+    worker: JoinHandle<usize>,
+}
+
+/// Flow table split across worker threads by the endpoints of the flows.
+///
+/// Packets are handed to [`ShardedFlowTable::dispatch`] from the capture thread. Finished
+/// records of every shard arrive, in batches, on the receiver returned by
+/// [`ShardedFlowTable::from_parameters`] or [`ShardedFlowTable::restart`], which is closed once
+/// [`ShardedFlowTable::finish`] or the next restart has flushed every shard.
+pub struct ShardedFlowTable {
+    shards: Vec<Shard>,
+    hasher: RandomState,
+    // Packet time of the last expiration tick
+    last_tick: u64,
+}
+
+impl ShardedFlowTable {
+    /// Starts `parameters.workers` worker threads, each with a flow table built from the
+    /// parameters. Flow count and memory limits are split evenly between the shards.
+    pub fn from_parameters(
+        parameters: &Parameters,
+    ) -> Result<(Self, mpsc::Receiver<Vec<FluereRecord>>), FluereError> {
+        let workers = parameters.workers.unwrap_or(1).max(1);
+        let (records, receiver) = mpsc::channel(RECORD_QUEUE);
+
+        let mut shards = Vec::with_capacity(workers);
+        for index in 0..workers {
+            let table = FlowTable::from_parameters(parameters)?.with_shards(workers);
+            shards.push(spawn_shard(index, table, records.clone())?);
+        }
+        debug!("Started {} flow table workers", workers);
+
+        Ok((
+            Self {
+                shards,
+                hasher: RandomState::new(),
+                last_tick: 0,
+            },
+            receiver,
+        ))
+    }
+
+    /// Number of worker threads.
+    pub fn workers(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Hands a packet to the shard owning its flow.
+    pub fn dispatch(&mut self, packet: &Packet) {
+        let packet_time = parse_microseconds(
+            packet.header.ts.tv_sec as u64,
+            packet.header.ts.tv_usec as u64,
+        );
+        if packet_time >= self.last_tick + TICK_INTERVAL {
+            self.tick(packet_time);
+        }
+
+        let index = self.shard_index(packet.data);
+        let shard = &mut self.shards[index];
+        shard.batch.push(packet);
+        if shard.batch.len() >= PACKET_BATCH {
+            shard.send_batch();
+        }
+    }
+
+    /// Hands the packets waiting in the batches to the workers, e.g. when the capture is idle.
+    pub fn flush_batches(&mut self) {
+        for shard in self.shards.iter_mut() {
+            if !shard.batch.is_empty() {
+                shard.send_batch();
+            }
+        }
+    }
+
+    /// Flushes the active flows of every shard to the current receiver, which is then closed,
+    /// and sends the records of the flows started afterwards to the returned receiver, e.g. at
+    /// the end of an offline window. The workers keep running.
+    ///
+    /// Returns the number of flows flushed, along with the new receiver.
+    pub fn restart(&mut self) -> (usize, mpsc::Receiver<Vec<FluereRecord>>) {
+        self.flush_batches();
+        let (records, receiver) = mpsc::channel(RECORD_QUEUE);
+        let (active, counts) = sync_channel(self.shards.len());
+        for shard in self.shards.iter() {
+            shard.send(ShardMessage::Restart {
+                records: records.clone(),
+                active: active.clone(),
+            });
+        }
+        drop(active);
+
+        // Workers that stopped drop their message, and are not waited for
+        (counts.iter().sum(), receiver)
+    }
+
+    /// Stops the workers once they processed every packet and flushed their active flows.
+    ///
+    /// Returns the number of flows that were still active when the capture ended.
+    pub fn finish(mut self) -> usize {
+        self.flush_batches();
+
+        let mut active = 0;
+        for (index, shard) in self.shards.into_iter().enumerate() {
+            drop(shard.sender);
+            match shard.worker.join() {
+                Ok(count) => active += count,
+                Err(_) => error!("Flow table worker {} panicked", index),
+            }
+        }
+
+        active
+    }
+
+    fn tick(&mut self, now: u64) {
+        self.flush_batches();
+        for shard in self.shards.iter() {
+            shard.send(ShardMessage::Tick(now));
+        }
+        self.last_tick = now;
+    }
+
+    // Packets other than IP go to the first shard
+    fn shard_index(&self, data: &[u8]) -> usize {
+        if self.shards.len() == 1 {
+            return 0;
+        }
+        match shard_key(data) {
+            Some(key) => (self.hasher.hash_one(key) % self.shards.len() as u64) as usize,
+            None => 0,
+        }
+    }
+}
+
+// Endpoints of the outermost IP header, the same for both directions and for every packet of a
+// flow, since the flow key is made of them or of the headers they tunnel. Only TCP and UDP have
+// ports, which UDP tunnels go without, the source port of their outer header varying.
+fn shard_key(data: &[u8]) -> Option<ShardKey> {
+    let ethertype = EtherType(u16::from_be_bytes([*data.get(12)?, *data.get(13)?]));
+    let (_, ethertype, payload) = strip_vlan_tags(ethertype, data.get(ETHERNET_HEADER_LENGTH..)?);
+    let (src, dst, protocol, upper_layer) = match ethertype {
+        EtherTypes::Ipv4 => {
+            let packet = Ipv4Packet::new(payload)?;
+            let header_length = packet.get_header_length() as usize * 4;
+            (
+                IpAddr::V4(packet.get_source()),
+                IpAddr::V4(packet.get_destination()),
+                packet.get_next_level_protocol().0,
+                payload.get(header_length..).unwrap_or_default(),
+            )
+        }
+        EtherTypes::Ipv6 => {
+            let packet = Ipv6Packet::new(payload)?;
+            let upper_layer = parse_ipv6_upper_layer_bytes(payload)?;
+            (
+                IpAddr::V6(packet.get_source()),
+                IpAddr::V6(packet.get_destination()),
+                upper_layer.protocol,
+                upper_layer.payload,
+            )
+        }
+        _ => return None,
+    };
+
+    let (src_port, dst_port) = match (protocol, upper_layer.get(0..4)) {
+        (TCP | UDP, Some(ports)) => (
+            u16::from_be_bytes([ports[0], ports[1]]),
+            u16::from_be_bytes([ports[2], ports[3]]),
+        ),
+        _ => (0, 0),
+    };
+    let (src_port, dst_port) = match protocol == UDP
+        && (TUNNEL_PORTS.contains(&src_port) || TUNNEL_PORTS.contains(&dst_port))
+    {
+        true => (0, 0),
+        false => (src_port, dst_port),
+    };
+
+    let (low, high) = match (src, src_port) <= (dst, dst_port) {
+        true => ((src, src_port), (dst, dst_port)),
+        false => ((dst, dst_port), (src, src_port)),
+    };
+    Some((low, high, protocol))
+}
+
+impl Shard {
+    fn send(&self, message: ShardMessage) {
+        if self.sender.send(message).is_err() {
+            trace!("Flow table worker stopped, dropping its packets");
+        }
+    }
+
+    fn send_batch(&mut self) {
+        let packets = take(&mut self.batch);
+        self.send(ShardMessage::Packets(packets));
+    }
+}
+
+fn spawn_shard(
+    index: usize,
+    table: FlowTable,
+    records: mpsc::Sender<Vec<FluereRecord>>,
+) -> Result<Shard, FluereError> {
+    let (sender, messages) = sync_channel(SHARD_QUEUE);
+    let worker = thread::Builder::new()
+        .name(format!("fluere-worker-{}", index))
+        .spawn(move || run_worker(table, messages, records))?;
+
+    Ok(Shard {
+        sender,
+        batch: PacketBatch::default(),
+        worker,
+    })
+}
+
+fn run_worker(
+    mut table: FlowTable,
+    messages: Receiver<ShardMessage>,
+    mut records: mpsc::Sender<Vec<FluereRecord>>,
+) -> usize {
+    for message in messages {
+        let finished: Vec<FluereRecord> = match message {
+            ShardMessage::Packets(packets) => packets
+                .packets()
+                .flat_map(|packet| table.ingest(&packet))
+                .collect(),
+            ShardMessage::Tick(now) => table.expire(now),
+            ShardMessage::Restart {
+                records: next,
+                active,
+            } => {
+                let count = table.len();
+                let _ = records.blocking_send(table.flush());
+                records = next;
+                let _ = active.send(count);
+                continue;
+            }
+        };
+        if !finished.is_empty() && records.blocking_send(finished).is_err() {
+            debug!("Record receiver closed, stopping flow table worker");
+            return 0;
+        }
+    }
+
+    let active = table.len();
+    let _ = records.blocking_send(table.flush());
+    active
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_packet(host: u8, reply: bool) -> Vec<u8> {
+        let mut data = vec![
+            // Ethernet: dst mac, src mac, EtherType IPv4
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0x08, 0x00,
+            // IPv4: Version=4, IHL=5, Total Length=28, TTL=64, Protocol=UDP
+            0x45, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00,
+        ];
+        let (client, server) = ([10, 0, 0, host], [192, 168, 0, 1]);
+        let (src, dst, ports) = match reply {
+            false => (client, server, [0xc0, 0x00, 0x00, 0x35]),
+            true => (server, client, [0x00, 0x35, 0xc0, 0x00]),
+        };
+        data.extend_from_slice(&src);
+        data.extend_from_slice(&dst);
+        data.extend_from_slice(&ports);
+        data.extend_from_slice(&[0x00, 0x08, 0x00, 0x00]);
+        data
+    }
+
+    fn header(sec: i64) -> PacketHeader {
+        PacketHeader {
+            ts: libc::timeval {
+                tv_sec: sec as libc::time_t,
+                tv_usec: 0,
+            },
+            caplen: 42,
+            len: 42,
+        }
+    }
+
+    #[test]
+    fn test_shard_key_is_the_same_both_ways() {
+        let forward = udp_packet(1, false);
+        assert!(shard_key(&forward).is_some());
+        assert_eq!(shard_key(&forward), shard_key(&udp_packet(1, true)));
+        assert_ne!(shard_key(&forward), shard_key(&udp_packet(2, false)));
+
+        // Each VTEP picks its own outer source port for a VXLAN flow
+        let mut vxlan_forward = udp_packet(1, false);
+        vxlan_forward[34..38].copy_from_slice(&[0xc0, 0x01, 0x12, 0xb5]);
+        let mut vxlan_reply = udp_packet(1, true);
+        vxlan_reply[34..38].copy_from_slice(&[0xd0, 0x02, 0x12, 0xb5]);
+        assert_eq!(shard_key(&vxlan_forward), shard_key(&vxlan_reply));
+    }
+
+    #[test]
+    fn test_flows_stay_whole_across_shards() {
+        let parameters =
+            Parameters::new(Some(false), Some(600_000), None, None, None).with_workers(Some(4));
+        let (mut table, mut receiver) = ShardedFlowTable::from_parameters(&parameters).unwrap();
+        assert_eq!(table.workers(), 4);
+
+        for host in 1..=100 {
+            let header = header(1_700_000_000 + host as i64);
+            table.dispatch(&Packet::new(&header, &udp_packet(host, false)));
+            table.dispatch(&Packet::new(&header, &udp_packet(host, true)));
+        }
+        assert_eq!(table.finish(), 100);
+
+        let mut records = Vec::new();
+        while let Some(batch) = receiver.blocking_recv() {
+            records.extend(batch);
+        }
+        assert_eq!(records.len(), 100);
+        assert!(records.iter().all(|record| record.d_pkts == 2));
+        assert!(records.iter().all(|record| record.in_pkts == 1));
+    }
+
+    #[test]
+    fn test_restart_flushes_to_the_previous_receiver() {
+        let parameters =
+            Parameters::new(Some(false), Some(600_000), None, None, None).with_workers(Some(2));
+        let (mut table, mut first) = ShardedFlowTable::from_parameters(&parameters).unwrap();
+        let header = header(1_700_000_000);
+        for host in 1..=10 {
+            table.dispatch(&Packet::new(&header, &udp_packet(host, false)));
+        }
+        let (flushed, mut second) = table.restart();
+        assert_eq!(flushed, 10);
+        table.dispatch(&Packet::new(&header, &udp_packet(11, false)));
+        assert_eq!(table.finish(), 1);
+
+        let count = |receiver: &mut mpsc::Receiver<Vec<FluereRecord>>| {
+            let mut count = 0;
+            while let Some(batch) = receiver.blocking_recv() {
+                count += batch.len();
+            }
+            count
+        };
+        assert_eq!(count(&mut first), 10);
+        assert_eq!(count(&mut second), 1);
+    }
+}
@@ -0,0 +1,181 @@
+// This file contains the hierarchical timer wheel used for flow expiration.
+// Deadlines are kept in levels of 64 slots, each level covering 64 times the span of the one
+// below it. Inserting is O(1), and a deadline moves down one level at most once per level
+// until it lands in the slot of its own tick, so expiring is amortised O(1) as well.
+
+use std::mem::take;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = (SLOTS - 1) as u64;
+// With 1 ms ticks, six levels cover a little over two years
+const LEVELS: usize = 6;
+
+// Length of a tick in microseconds
+const DEFAULT_TICK: u64 = 1_000;
+
+/// Hierarchical timer wheel of values to be released at a deadline, in microseconds.
+#[derive(Debug)]
+pub struct TimerWheel<T> {
+    levels: Vec<Vec<Vec<(u64, T)>>>,
+    level_len: [usize; LEVELS],
+    // Length of a tick in microseconds
+    tick: u64,
+    // Tick the wheel has advanced to
+    now_tick: u64,
+}
+
+impl<T> Default for TimerWheel<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_TICK)
+    }
+}
+
+impl<T> TimerWheel<T> {
+    pub fn new(tick: u64) -> Self {
+        Self {
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            level_len: [0; LEVELS],
+            tick: tick.max(1),
+            now_tick: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.level_len.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Schedules `value` to be released once the wheel advances to `deadline`.
+    ///
+    /// `now` is the current time, used to start the wheel where the first deadline is.
+    pub fn schedule(&mut self, now: u64, deadline: u64, value: T) {
+        if self.is_empty() {
+            self.now_tick = self.now_tick.max(now / self.tick);
+        }
+        self.insert(deadline, value);
+    }
+
+    /// Advances the wheel to `now` and returns every value whose deadline is not after it.
+    pub fn advance(&mut self, now: u64) -> Vec<T> {
+        let target = now / self.tick;
+        let mut expired = Vec::new();
+
+        while self.now_tick < target {
+            let slot = (self.now_tick & SLOT_MASK) as usize;
+            let due = take(&mut self.levels[0][slot]);
+            self.level_len[0] -= due.len();
+            expired.extend(due.into_iter().map(|(_, value)| value));
+
+            self.now_tick = self.next_tick(target);
+            self.cascade();
+        }
+
+        // The slot of the current tick may hold deadlines later within that tick
+        let slot = (self.now_tick & SLOT_MASK) as usize;
+        let (due, pending): (Vec<_>, Vec<_>) = take(&mut self.levels[0][slot])
+            .into_iter()
+            .partition(|&(deadline, _)| deadline <= now);
+        self.levels[0][slot] = pending;
+        self.level_len[0] -= due.len();
+        expired.extend(due.into_iter().map(|(_, value)| value));
+
+        expired
+    }
+
+    /// Removes every scheduled value.
+    pub fn clear(&mut self) {
+        self.levels
+            .iter_mut()
+            .flatten()
+            .for_each(|slot| slot.clear());
+        self.level_len = [0; LEVELS];
+    }
+
+    fn insert(&mut self, deadline: u64, value: T) {
+        let (level, slot) = self.position(deadline / self.tick);
+        self.levels[level][slot].push((deadline, value));
+        self.level_len[level] += 1;
+    }
+
+    // Level and slot of a deadline: the level is given by the highest bit where the deadline
+    // differs from the current tick, so the slot is reached before its parent slot changes.
+    fn position(&self, deadline_tick: u64) -> (usize, usize) {
+        if deadline_tick <= self.now_tick {
+            return (0, (self.now_tick & SLOT_MASK) as usize);
+        }
+        let highest_bit = 63 - (deadline_tick ^ self.now_tick).leading_zeros();
+        let level = ((highest_bit / SLOT_BITS) as usize).min(LEVELS - 1);
+        let slot = (deadline_tick >> (SLOT_BITS * level as u32)) & SLOT_MASK;
+
+        (level, slot as usize)
+    }
+
+    // Next tick where something can happen: the next tick when the lowest level holds values,
+    // otherwise the start of the next slot of the lowest level that does.
+    fn next_tick(&self, target: u64) -> u64 {
+        let Some(level) = self.level_len.iter().position(|&len| len > 0) else {
+            return target;
+        };
+        let span_bits = SLOT_BITS * level as u32;
+
+        (((self.now_tick >> span_bits) + 1) << span_bits).min(target)
+    }
+
+    // Moves the values of the higher level slots starting at the current tick down the wheel.
+    fn cascade(&mut self) {
+        for level in 1..LEVELS {
+            let span_bits = SLOT_BITS * level as u32;
+            if self.now_tick & ((1 << span_bits) - 1) != 0 {
+                break;
+            }
+            let slot = ((self.now_tick >> span_bits) & SLOT_MASK) as usize;
+            let values = take(&mut self.levels[level][slot]);
+            self.level_len[level] -= values.len();
+            for (deadline, value) in values {
+                self.insert(deadline, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_expire_at_their_deadline() {
+        let mut wheel = TimerWheel::new(1_000);
+        let start = 1_700_000_000_000_000;
+        wheel.schedule(start, start + 500, "same tick");
+        wheel.schedule(start, start + 70_000, "level 1");
+        wheel.schedule(start, start + 600_000_000, "level 3");
+        assert_eq!(wheel.len(), 3);
+
+        assert!(wheel.advance(start + 499).is_empty());
+        assert_eq!(wheel.advance(start + 500), vec!["same tick"]);
+        assert!(wheel.advance(start + 69_999).is_empty());
+        assert_eq!(wheel.advance(start + 70_000), vec!["level 1"]);
+        assert!(wheel.advance(start + 599_999_999).is_empty());
+        assert_eq!(wheel.advance(start + 600_000_000), vec!["level 3"]);
+        assert!(wheel.is_empty());
+    }
+
+    #[test]
+    fn test_large_jump_releases_everything() {
+        let mut wheel = TimerWheel::new(1_000);
+        for i in 0..1_000u64 {
+            wheel.schedule(0, i * 7_919_000, i);
+        }
+
+        let mut expired = wheel.advance(u64::MAX / 2);
+        expired.sort();
+        assert_eq!(expired, (0..1_000).collect::<Vec<u64>>());
+        assert!(wheel.is_empty());
+    }
+}
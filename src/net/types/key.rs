@@ -11,10 +11,38 @@ pub struct Key {
     pub protocol: u8,
     pub src_mac: MacAddress,
     pub dst_mac: MacAddress,
+    pub outer_vlan: u16,
+    pub inner_vlan: u16,
+    pub tunnel_id: u32,
 }
 impl Key {
     pub fn mac_defaultate(&mut self) {
         self.src_mac = MacAddress::new([0, 0, 0, 0, 0, 0]);
         self.dst_mac = MacAddress::new([0, 0, 0, 0, 0, 0]);
     }
+
+    /// Returns the key of the opposite direction.
+    pub fn reversed(&self) -> Key {
+        Key {
+            src_ip: self.dst_ip,
+            src_port: self.dst_port,
+            dst_ip: self.src_ip,
+            dst_port: self.src_port,
+            src_mac: self.dst_mac,
+            dst_mac: self.src_mac,
+            ..*self
+        }
+    }
+
+    /// Returns the direction-independent form of the key, with the lower endpoint as source,
+    /// and whether the endpoints had to be swapped to get it.
+    pub fn canonical(&self) -> (Key, bool) {
+        let src = (self.src_ip, self.src_port, self.src_mac);
+        let dst = (self.dst_ip, self.dst_port, self.dst_mac);
+        if src <= dst {
+            (*self, false)
+        } else {
+            (self.reversed(), true)
+        }
+    }
 }
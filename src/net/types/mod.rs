@@ -3,6 +3,7 @@ mod flags;
 pub mod ipv4;
 mod key;
 mod protocols;
+mod tcp_state;
 
 pub use ether::EtherFrame;
 pub use ether::EtherProtocol;
@@ -12,3 +13,5 @@ pub use key::Key;
 pub use protocols::Ports;
 pub use protocols::Protocol;
 pub use protocols::Udp;
+pub use tcp_state::TcpState;
+pub use tcp_state::TcpTracker;
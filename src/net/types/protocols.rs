@@ -87,6 +87,36 @@ impl Protocol {
         *self as u8
     }
 
+    /// Lowercase name of the protocol, `None` for unknown protocols.
+    pub fn name(&self) -> Option<&'static str> {
+        let name = match self {
+            Protocol::HOPOPT => "hopopt",
+            Protocol::ICMP => "icmp",
+            Protocol::IGMP => "igmp",
+            Protocol::GGP => "ggp",
+            Protocol::IPV4 => "ipv4",
+            Protocol::TCP => "tcp",
+            Protocol::UDP => "udp",
+            Protocol::SFTP => "sftp",
+            Protocol::GRE => "gre",
+            Protocol::ESP => "esp",
+            Protocol::AH => "ah",
+            Protocol::DNS => "dns",
+            Protocol::ICMPV6 => "ipv6-icmp",
+            Protocol::NoNxt => "ipv6-nonxt",
+            Protocol::EIGRP => "eigrp",
+            Protocol::OSPF => "ospf",
+            Protocol::VRRP => "vrrp",
+            Protocol::L2TP => "l2tp",
+            Protocol::SCTP => "sctp",
+            Protocol::VXLAN => "vxlan",
+            Protocol::PIM => "pim",
+            Protocol::MOBILITY => "mobility",
+            Protocol::Unknown => return None,
+        };
+        Some(name)
+    }
+
     pub fn get_default_ports(&self) -> Option<(u16, u16)> {
         match self {
             Protocol::SFTP => Some((22, 22)),
@@ -0,0 +1,158 @@
+use crate::net::types::TcpFlags;
+
+use fluereflow::FlowEndReason;
+
+/// Connection state of a TCP flow, as seen by a passive observer of both directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TcpState {
+    #[default]
+    Closed,
+    SynSent,
+    SynReceived,
+    Established,
+    // One side has sent a FIN
+    FinWait,
+    // Both sides have sent a FIN, waiting for the last ACK
+    LastAck,
+    TimeWait,
+    Reset,
+}
+
+impl TcpState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TcpState::Closed => "CLOSED",
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynReceived => "SYN_RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::FinWait => "FIN_WAIT",
+            TcpState::LastAck => "LAST_ACK",
+            TcpState::TimeWait => "TIME_WAIT",
+            TcpState::Reset => "RESET",
+        }
+    }
+}
+
+/// Tracks the state of a single TCP connection.
+///
+/// Directions are relative to the initiator of the connection: `is_reverse` is `false` for
+/// packets sent by the initiator and `true` for packets sent by the responder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TcpTracker {
+    state: TcpState,
+    fin_forward: bool,
+    fin_reverse: bool,
+}
+
+impl TcpTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking a connection that was already open when it was first seen.
+    pub fn midstream() -> Self {
+        Self {
+            state: TcpState::Established,
+            ..Self::default()
+        }
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.state
+    }
+
+    /// Whether the connection has ended and the flow can be exported.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, TcpState::TimeWait | TcpState::Reset)
+    }
+
+    /// Why the connection ended, if it did.
+    pub fn end_reason(&self) -> Option<FlowEndReason> {
+        match self.state {
+            TcpState::TimeWait => Some(FlowEndReason::TcpFin),
+            TcpState::Reset => Some(FlowEndReason::TcpReset),
+            _ => None,
+        }
+    }
+
+    /// Advances the state machine with a packet and returns the new state.
+    pub fn update(&mut self, flags: TcpFlags, is_reverse: bool) -> TcpState {
+        if flags.rst > 0 {
+            self.state = TcpState::Reset;
+            return self.state;
+        }
+        if flags.fin > 0 {
+            if is_reverse {
+                self.fin_reverse = true;
+            } else {
+                self.fin_forward = true;
+            }
+        }
+
+        self.state = match self.state {
+            TcpState::Closed if flags.syn > 0 => TcpState::SynSent,
+            TcpState::SynSent if flags.syn > 0 && flags.ack > 0 && is_reverse => {
+                TcpState::SynReceived
+            }
+            TcpState::SynReceived if flags.ack > 0 && !is_reverse => TcpState::Established,
+            // Data or a FIN right after the handshake also means the connection is open
+            TcpState::SynSent | TcpState::SynReceived if flags.syn == 0 => self.closing_state(),
+            TcpState::Established | TcpState::FinWait => self.closing_state(),
+            TcpState::LastAck if flags.ack > 0 && flags.fin == 0 => TcpState::TimeWait,
+            state => state,
+        };
+
+        self.state
+    }
+
+    fn closing_state(&self) -> TcpState {
+        match (self.fin_forward, self.fin_reverse) {
+            (true, true) => TcpState::LastAck,
+            (true, false) | (false, true) => TcpState::FinWait,
+            (false, false) => TcpState::Established,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(fin: u8, syn: u8, rst: u8, ack: u8) -> TcpFlags {
+        TcpFlags::new([fin, syn, rst, 0, ack, 0, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_tcp_tracker_full_lifecycle() {
+        let mut tracker = TcpTracker::new();
+
+        assert_eq!(tracker.update(flags(0, 1, 0, 0), false), TcpState::SynSent);
+        assert_eq!(
+            tracker.update(flags(0, 1, 0, 1), true),
+            TcpState::SynReceived
+        );
+        assert_eq!(
+            tracker.update(flags(0, 0, 0, 1), false),
+            TcpState::Established
+        );
+        assert_eq!(tracker.update(flags(1, 0, 0, 1), false), TcpState::FinWait);
+        assert_eq!(tracker.update(flags(0, 0, 0, 1), true), TcpState::FinWait);
+        assert!(!tracker.is_closed());
+        assert_eq!(tracker.update(flags(1, 0, 0, 1), true), TcpState::LastAck);
+        assert_eq!(tracker.update(flags(0, 0, 0, 1), false), TcpState::TimeWait);
+        assert!(tracker.is_closed());
+        assert_eq!(tracker.end_reason(), Some(FlowEndReason::TcpFin));
+    }
+
+    #[test]
+    fn test_tcp_tracker_reset() {
+        let mut tracker = TcpTracker::midstream();
+
+        assert_eq!(
+            tracker.update(flags(0, 0, 0, 1), true),
+            TcpState::Established
+        );
+        assert_eq!(tracker.update(flags(0, 0, 1, 0), true), TcpState::Reset);
+        assert_eq!(tracker.end_reason(), Some(FlowEndReason::TcpReset));
+    }
+}
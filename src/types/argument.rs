@@ -1,3 +1,8 @@
+use super::{
+    AddressFormat, CompressionFormat, EvictionPolicy, ExportFormat, ProtocolFormat, StreamFormat,
+    TimestampFormat,
+};
+
 #[derive(Debug, Default)]
 pub struct Args {
     pub interface: Option<String>,
@@ -27,11 +32,94 @@ pub struct Files {
     pub csv: Option<String>,
     pub file: Option<String>,
     pub pcap: Option<String>,
+    // NetFlow collector address, as `host:port`
+    pub netflow: Option<String>,
+    // IPFIX collector (`udp://` or `tcp://host:port`) or file path
+    pub ipfix: Option<String>,
+    // SQLite database the flows are stored in
+    pub sqlite: Option<String>,
+    pub format: Option<ExportFormat>,
+    // Write the records to the standard output instead of files
+    pub stdout: Option<bool>,
+    // Stream consumer, as `tcp://host:port` or `unix:///path`
+    pub stream: Option<String>,
+    // Directory the flow record files are written to
+    pub output_dir: Option<String>,
+    // Address the collector receives exported flows on, as `host:port`
+    pub listen: Option<String>,
+    // Address the collector accepts IPFIX over TCP on
+    pub listen_tcp: Option<String>,
+    // Capture files, directories and glob patterns converted together, `file` being the first
+    pub inputs: Vec<String>,
 }
 
 impl Files {
     pub fn new(csv: Option<String>, file: Option<String>, pcap: Option<String>) -> Self {
-        Self { csv, file, pcap }
+        Self {
+            csv,
+            file,
+            pcap,
+            netflow: None,
+            ipfix: None,
+            sqlite: None,
+            format: None,
+            stdout: None,
+            stream: None,
+            output_dir: None,
+            listen: None,
+            listen_tcp: None,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn with_netflow(mut self, netflow: Option<String>) -> Self {
+        self.netflow = netflow;
+        self
+    }
+
+    pub fn with_ipfix(mut self, ipfix: Option<String>) -> Self {
+        self.ipfix = ipfix;
+        self
+    }
+
+    pub fn with_sqlite(mut self, sqlite: Option<String>) -> Self {
+        self.sqlite = sqlite;
+        self
+    }
+
+    pub fn with_format(mut self, format: Option<ExportFormat>) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_stdout(mut self, stdout: Option<bool>) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    pub fn with_stream(mut self, stream: Option<String>) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    pub fn with_output_dir(mut self, output_dir: Option<String>) -> Self {
+        self.output_dir = output_dir;
+        self
+    }
+
+    pub fn with_listen(mut self, listen: Option<String>) -> Self {
+        self.listen = listen;
+        self
+    }
+
+    pub fn with_listen_tcp(mut self, listen_tcp: Option<String>) -> Self {
+        self.listen_tcp = listen_tcp;
+        self
+    }
+
+    pub fn with_inputs(mut self, inputs: Vec<String>) -> Self {
+        self.inputs = inputs;
+        self
     }
 }
 
@@ -42,6 +130,45 @@ pub struct Parameters {
     pub duration: Option<u64>,
     pub interval: Option<u64>,
     pub sleep_windows: Option<u64>,
+    pub active_timeout: Option<u64>,
+    pub midstream: Option<bool>,
+    pub use_ipv6: Option<bool>,
+    pub tunnel_depth: Option<u8>,
+    pub max_flows: Option<usize>,
+    // Memory budget of the flow table, in megabytes
+    pub max_memory: Option<usize>,
+    pub eviction_policy: Option<EvictionPolicy>,
+    // Flow table worker threads
+    pub workers: Option<usize>,
+    pub netflow_version: Option<u8>,
+    // Private enterprise number the Fluere IPFIX elements are exported and collected under
+    pub ipfix_enterprise: Option<u32>,
+    // Hours the SQLite sink keeps flows for, 0 keeps them all
+    pub sqlite_retention: Option<u64>,
+    // CSV column or JSON Lines field selection, e.g. `source=src,destination=dst,prot`
+    pub fields: Option<String>,
+    // CSV delimiter, a single character or `tab`
+    pub delimiter: Option<String>,
+    // Whether CSV files start with a header row
+    pub header: Option<bool>,
+    pub timestamp_format: Option<TimestampFormat>,
+    pub protocol_format: Option<ProtocolFormat>,
+    // How Parquet exports store IP addresses
+    pub address_format: Option<AddressFormat>,
+    pub stream_format: Option<StreamFormat>,
+    // Records held while the stream consumer is down
+    pub stream_buffer: Option<usize>,
+    // strftime pattern of the record file names, with `{name}`, `{hostname}` and `{interface}`
+    pub file_template: Option<String>,
+    // Size in megabytes, and record count, past which a new file is started; 0 for no limit
+    pub rotate_size: Option<u64>,
+    pub rotate_records: Option<u64>,
+    pub compression: Option<CompressionFormat>,
+    // Finished files kept, and their maximum age in hours; 0 for no limit
+    pub max_files: Option<usize>,
+    pub max_age: Option<u64>,
+    // Packet time each offline result covers, in milliseconds; 0 for a single result
+    pub window: Option<u64>,
 }
 
 impl Parameters {
@@ -58,6 +185,162 @@ impl Parameters {
             duration,
             interval,
             sleep_windows,
+            active_timeout: None,
+            midstream: None,
+            use_ipv6: None,
+            tunnel_depth: None,
+            max_flows: None,
+            max_memory: None,
+            eviction_policy: None,
+            workers: None,
+            netflow_version: None,
+            ipfix_enterprise: None,
+            sqlite_retention: None,
+            fields: None,
+            delimiter: None,
+            header: None,
+            timestamp_format: None,
+            protocol_format: None,
+            address_format: None,
+            stream_format: None,
+            stream_buffer: None,
+            file_template: None,
+            rotate_size: None,
+            rotate_records: None,
+            compression: None,
+            max_files: None,
+            max_age: None,
+            window: None,
         }
     }
+
+    pub fn with_active_timeout(mut self, active_timeout: Option<u64>) -> Self {
+        self.active_timeout = active_timeout;
+        self
+    }
+
+    pub fn with_midstream(mut self, midstream: Option<bool>) -> Self {
+        self.midstream = midstream;
+        self
+    }
+
+    pub fn with_use_ipv6(mut self, use_ipv6: Option<bool>) -> Self {
+        self.use_ipv6 = use_ipv6;
+        self
+    }
+
+    pub fn with_tunnel_depth(mut self, tunnel_depth: Option<u8>) -> Self {
+        self.tunnel_depth = tunnel_depth;
+        self
+    }
+
+    pub fn with_max_flows(mut self, max_flows: Option<usize>) -> Self {
+        self.max_flows = max_flows;
+        self
+    }
+
+    pub fn with_max_memory(mut self, max_memory: Option<usize>) -> Self {
+        self.max_memory = max_memory;
+        self
+    }
+
+    pub fn with_eviction_policy(mut self, eviction_policy: Option<EvictionPolicy>) -> Self {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    pub fn with_workers(mut self, workers: Option<usize>) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn with_netflow_version(mut self, netflow_version: Option<u8>) -> Self {
+        self.netflow_version = netflow_version;
+        self
+    }
+
+    pub fn with_ipfix_enterprise(mut self, ipfix_enterprise: Option<u32>) -> Self {
+        self.ipfix_enterprise = ipfix_enterprise;
+        self
+    }
+
+    pub fn with_sqlite_retention(mut self, sqlite_retention: Option<u64>) -> Self {
+        self.sqlite_retention = sqlite_retention;
+        self
+    }
+
+    pub fn with_fields(mut self, fields: Option<String>) -> Self {
+        self.fields = fields;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: Option<String>) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_header(mut self, header: Option<bool>) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn with_timestamp_format(mut self, timestamp_format: Option<TimestampFormat>) -> Self {
+        self.timestamp_format = timestamp_format;
+        self
+    }
+
+    pub fn with_protocol_format(mut self, protocol_format: Option<ProtocolFormat>) -> Self {
+        self.protocol_format = protocol_format;
+        self
+    }
+
+    pub fn with_address_format(mut self, address_format: Option<AddressFormat>) -> Self {
+        self.address_format = address_format;
+        self
+    }
+
+    pub fn with_stream_format(mut self, stream_format: Option<StreamFormat>) -> Self {
+        self.stream_format = stream_format;
+        self
+    }
+
+    pub fn with_stream_buffer(mut self, stream_buffer: Option<usize>) -> Self {
+        self.stream_buffer = stream_buffer;
+        self
+    }
+
+    pub fn with_file_template(mut self, file_template: Option<String>) -> Self {
+        self.file_template = file_template;
+        self
+    }
+
+    pub fn with_rotate_size(mut self, rotate_size: Option<u64>) -> Self {
+        self.rotate_size = rotate_size;
+        self
+    }
+
+    pub fn with_rotate_records(mut self, rotate_records: Option<u64>) -> Self {
+        self.rotate_records = rotate_records;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Option<CompressionFormat>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_max_files(mut self, max_files: Option<usize>) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Option<u64>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn with_window(mut self, window: Option<u64>) -> Self {
+        self.window = window;
+        self
+    }
 }
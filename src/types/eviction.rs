@@ -0,0 +1,47 @@
+use std::fmt;
+
+use crate::FluereError;
+
+/// Which flows are exported early when the flow table is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EvictionPolicy {
+    /// Flows whose first packet is the oldest.
+    #[default]
+    Oldest,
+    /// Flows that have been idle the longest.
+    Idle,
+    /// Flows with the fewest packets.
+    LeastActive,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionPolicy::Oldest => "oldest",
+            EvictionPolicy::Idle => "idle",
+            EvictionPolicy::LeastActive => "least-active",
+        }
+    }
+}
+
+impl TryFrom<&str> for EvictionPolicy {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "oldest" => Ok(EvictionPolicy::Oldest),
+            "idle" => Ok(EvictionPolicy::Idle),
+            "least-active" => Ok(EvictionPolicy::LeastActive),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid eviction policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for EvictionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -0,0 +1,266 @@
+use std::fmt;
+
+use crate::FluereError;
+
+/// Format of the flow records written to files or standard output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// Columnar Parquet files, needs the `parquet` feature.
+    Parquet,
+}
+
+/// How flow start and end times are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampFormat {
+    /// Microseconds since the Unix epoch.
+    #[default]
+    Micros,
+    /// Seconds since the Unix epoch, with a fraction, e.g. `1700000000.5`.
+    Seconds,
+    /// RFC 3339 (ISO 8601) date and time in UTC, e.g. `2023-11-14T22:13:20.000000Z`.
+    Rfc3339,
+    /// RFC 3339 (ISO 8601) date and time in the local time zone, with its offset.
+    Local,
+}
+
+/// How the IP protocol of a flow is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProtocolFormat {
+    /// IANA protocol number.
+    #[default]
+    Number,
+    /// Protocol name, e.g. `tcp`, falling back to the number for unnamed protocols.
+    Name,
+}
+
+/// How IP addresses are stored in columnar exports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressFormat {
+    /// Textual address, e.g. `192.168.0.1`.
+    #[default]
+    String,
+    /// The 4 or 16 bytes of the address.
+    Binary,
+}
+
+/// Encoding of the records streamed to a consumer socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFormat {
+    /// One JSON object per line, like the JSON Lines export.
+    #[default]
+    Json,
+    /// Records prefixed with their length, as a big-endian `u32`.
+    Binary,
+}
+
+/// How finished export files are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionFormat {
+    #[default]
+    None,
+    /// Gzip, adding `.gz` to the file name.
+    Gzip,
+    /// Zstandard, adding `.zst` to the file name.
+    Zstd,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl TimestampFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimestampFormat::Micros => "micros",
+            TimestampFormat::Seconds => "seconds",
+            TimestampFormat::Rfc3339 => "rfc3339",
+            TimestampFormat::Local => "local",
+        }
+    }
+}
+
+impl ProtocolFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProtocolFormat::Number => "number",
+            ProtocolFormat::Name => "name",
+        }
+    }
+}
+
+impl AddressFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AddressFormat::String => "string",
+            AddressFormat::Binary => "binary",
+        }
+    }
+}
+
+impl StreamFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StreamFormat::Json => "json",
+            StreamFormat::Binary => "binary",
+        }
+    }
+}
+
+impl CompressionFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionFormat::None => "none",
+            CompressionFormat::Gzip => "gzip",
+            CompressionFormat::Zstd => "zstd",
+        }
+    }
+
+    /// Suffix the compression adds to file names.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CompressionFormat::None => "",
+            CompressionFormat::Gzip => ".gz",
+            CompressionFormat::Zstd => ".zst",
+        }
+    }
+}
+
+impl TryFrom<&str> for ExportFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid export format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for TimestampFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "micros" => Ok(TimestampFormat::Micros),
+            "seconds" => Ok(TimestampFormat::Seconds),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "local" => Ok(TimestampFormat::Local),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid timestamp format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for ProtocolFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "number" => Ok(ProtocolFormat::Number),
+            "name" => Ok(ProtocolFormat::Name),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid protocol format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for AddressFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "string" => Ok(AddressFormat::String),
+            "binary" => Ok(AddressFormat::Binary),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid address format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for StreamFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "json" => Ok(StreamFormat::Json),
+            "binary" => Ok(StreamFormat::Binary),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid stream format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&str> for CompressionFormat {
+    type Error = FluereError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s {
+            "none" => Ok(CompressionFormat::None),
+            "gzip" => Ok(CompressionFormat::Gzip),
+            "zstd" => Ok(CompressionFormat::Zstd),
+            _ => Err(FluereError::ConfigError(format!(
+                "Invalid compression format: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for TimestampFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ProtocolFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for AddressFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for CompressionFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -3,6 +3,8 @@ use crate::net::types::TcpFlags;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct UDFlowKey {
     pub doctets: usize,
+    // Packets the update stands for, more than one for sampled packets
+    pub packets: u32,
     pub pkt: u32,
     pub ttl: u8,
     pub flags: TcpFlags,
@@ -1,7 +1,16 @@
 mod argument;
+mod eviction;
+mod export;
 mod flow;
 
 pub use argument::Args;
 pub use argument::Files;
 pub use argument::Parameters;
+pub use eviction::EvictionPolicy;
+pub use export::AddressFormat;
+pub use export::CompressionFormat;
+pub use export::ExportFormat;
+pub use export::ProtocolFormat;
+pub use export::StreamFormat;
+pub use export::TimestampFormat;
 pub use flow::UDFlowKey;
@@ -0,0 +1,152 @@
+// This file contains the exporter of the collected data that is not flows, such as the
+// interface statistics sent by sFlow agents and the IPFIX enterprise elements Fluere does not
+// know. Each is written as its own CSV files next to the flow records, opened with the first
+// rows and rotated along with the flow exporters.
+
+use std::{io, marker::PhantomData};
+
+use super::{OutputWriter, RecordOutput};
+
+use log::{debug, info};
+use serde::Serialize;
+
+/// Writes rows of collected data as CSV, one per serialized value.
+pub struct CsvRowsExporter<T> {
+    // What the rows are, for the logs
+    kind: &'static str,
+    output: RecordOutput,
+    path: Option<String>,
+    writer: Option<csv::Writer<OutputWriter>>,
+    // Files opened so far, and rows written to the current one
+    files: u32,
+    records: u64,
+    rows: PhantomData<T>,
+}
+
+impl<T: Serialize> CsvRowsExporter<T> {
+    pub fn new(kind: &'static str, output: RecordOutput) -> Self {
+        Self {
+            kind,
+            output,
+            path: None,
+            writer: None,
+            files: 0,
+            records: 0,
+            rows: PhantomData,
+        }
+    }
+
+    pub fn write_batch(&mut self, rows: &[T]) -> io::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            self.open()?;
+        }
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("{} file is not open", self.kind),
+            ));
+        };
+
+        debug!("Writing {} {}", rows.len(), self.kind);
+        for row in rows {
+            writer.serialize(row)?;
+        }
+
+        self.records += rows.len() as u64;
+        if self
+            .output
+            .is_full(writer.get_ref().written(), self.records)
+        {
+            self.close()?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the current file, the next rows start a new one.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        match self.output.rotates() {
+            true => self.close(),
+            false => self.writer.as_mut().map_or(Ok(()), |writer| writer.flush()),
+        }
+    }
+
+    pub fn close(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+            drop(writer);
+            if let Some(path) = self.path.take() {
+                info!("{} {} Finished", self.kind, path);
+                self.output.finish(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // The header row is written along with the first rows
+    fn open(&mut self) -> io::Result<()> {
+        let (path, output) = self.output.open(self.files)?;
+        info!("{} {} Started", self.kind, path);
+        self.path = Some(path);
+        self.writer = Some(csv::Writer::from_writer(output));
+        self.files += 1;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::utils::{FileOptions, InterfaceCounters};
+
+    #[test]
+    fn test_counters_are_written_as_csv() {
+        let directory =
+            std::env::temp_dir().join(format!("fluere-counters-{}", std::process::id()));
+        let output = RecordOutput::File {
+            directory: directory.to_string_lossy().to_string(),
+            name: "interfaces".to_string(),
+            extension: ".csv",
+            options: FileOptions::default(),
+        };
+        let mut exporter = CsvRowsExporter::new("Interface counters", output);
+        let counters = InterfaceCounters {
+            time: 1_000_000,
+            agent: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+            if_index: 3,
+            if_type: 6,
+            if_speed: 1_000_000_000,
+            if_direction: 1,
+            if_status: 3,
+            in_octets: 4096,
+            in_ucast_pkts: 40,
+            in_multicast_pkts: 0,
+            in_broadcast_pkts: 0,
+            in_discards: 0,
+            in_errors: 0,
+            in_unknown_protos: 0,
+            out_octets: 8192,
+            out_ucast_pkts: 80,
+            out_multicast_pkts: 0,
+            out_broadcast_pkts: 0,
+            out_discards: 0,
+            out_errors: 0,
+            promiscuous_mode: 2,
+        };
+        exporter.write_batch(&[counters, counters]).unwrap();
+        exporter.close().unwrap();
+
+        let written = std::fs::read_to_string(directory.join("interfaces.csv")).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("time,agent,if_index,if_type,if_speed"));
+        assert!(lines[1].starts_with("1000000,192.168.0.1,3,6,1000000000,1,3,4096,40"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
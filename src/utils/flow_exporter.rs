@@ -0,0 +1,586 @@
+// This file contains the interface shared by every flow record destination, and the fan-out
+// that feeds finished records to all the configured sinks at once.
+// Each sink is isolated: a sink whose operation fails is closed and reopened on its next use,
+// while the other sinks keep receiving records.
+
+use std::{io, time::Duration};
+
+use super::{
+    CsvExporter, CsvFormat, DEFAULT_IPFIX_ENTERPRISE, FileOptions, IpfixExporter, JsonlExporter,
+    JsonlFormat, NetflowV5Exporter, NetflowV9Exporter, RecordFields, RecordOutput, StreamExporter,
+};
+use crate::{
+    FluereError,
+    types::{
+        AddressFormat, ExportFormat, Files, Parameters, ProtocolFormat, StreamFormat,
+        TimestampFormat,
+    },
+};
+
+use fluere_config::Sink;
+use fluereflow::FluereRecord;
+use log::{debug, error, info, warn};
+
+// Directory of the record files, unless configured
+const DEFAULT_DIRECTORY: &str = "./output";
+// Records a stream exporter holds while its consumer is down, unless configured
+const DEFAULT_STREAM_BUFFER: usize = 10_000;
+
+/// Destination of finished flow records.
+///
+/// Records are written in batches between `open` and `close`. `rotate` is called at every
+/// export interval, to start a new file for example; sinks without such a unit only flush.
+pub trait FlowExporter: Send {
+    /// Description of the destination for logs, e.g. a file or collector address.
+    fn name(&self) -> String;
+
+    /// Creates the output or connects to the collector.
+    fn open(&mut self) -> io::Result<()>;
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()>;
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    fn close(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+struct SinkState {
+    exporter: Box<dyn FlowExporter>,
+    is_open: bool,
+    // Failed operations so far
+    failures: u64,
+}
+
+impl SinkState {
+    // Runs an operation on the exporter, opening it first if needed.
+    // On failure the exporter is closed, so that the next operation reopens it.
+    fn run(
+        &mut self,
+        operation: &str,
+        action: impl FnOnce(&mut dyn FlowExporter) -> io::Result<()>,
+    ) {
+        let result = match self.is_open {
+            true => action(self.exporter.as_mut()),
+            false => self
+                .exporter
+                .open()
+                .and_then(|_| action(self.exporter.as_mut())),
+        };
+        self.is_open = result.is_ok();
+
+        if let Err(err) = result {
+            self.failures += 1;
+            error!(
+                "Failed to {} sink {}: {} ({} failures)",
+                operation,
+                self.exporter.name(),
+                err,
+                self.failures
+            );
+            let _ = self.exporter.close();
+        }
+    }
+}
+
+/// Every sink finished flow records are exported to.
+pub struct Exporters {
+    sinks: Vec<SinkState>,
+}
+
+impl Exporters {
+    /// Opens the exporters. Those that fail to open are retried on the next batch.
+    pub fn new(exporters: Vec<Box<dyn FlowExporter>>) -> Self {
+        let mut sinks: Vec<SinkState> = exporters
+            .into_iter()
+            .map(|exporter| SinkState {
+                exporter,
+                is_open: false,
+                failures: 0,
+            })
+            .collect();
+        for sink in sinks.iter_mut() {
+            sink.run("open", |_| Ok(()));
+            if sink.is_open {
+                debug!("Opened sink {}", sink.exporter.name());
+            }
+        }
+
+        Self { sinks }
+    }
+
+    /// Exporters of the command line, the record exporter of the mode first, followed by the
+    /// sinks of the config file.
+    pub fn from_args(
+        records: Box<dyn FlowExporter>,
+        files: &Files,
+        parameters: &Parameters,
+        configured: &[Sink],
+    ) -> Result<Self, FluereError> {
+        let mut exporters: Vec<Box<dyn FlowExporter>> = vec![records];
+        if let Some(collector) = files.netflow.as_deref() {
+            exporters.push(netflow_exporter(collector, parameters.netflow_version));
+        }
+        if let Some(destination) = files.ipfix.as_deref() {
+            exporters.push(ipfix_exporter(destination, parameters.ipfix_enterprise));
+        }
+        if let Some(path) = files.sqlite.as_deref() {
+            exporters.push(sqlite_exporter(path, parameters.sqlite_retention)?);
+        }
+        if let Some(destination) = files.stream.as_deref() {
+            exporters.push(Box::new(StreamExporter::new(
+                destination,
+                parameters.stream_format.unwrap_or_default(),
+                jsonl_format(parameters)?,
+                parameters.stream_buffer.unwrap_or(DEFAULT_STREAM_BUFFER),
+            )?));
+        }
+        for sink in configured {
+            exporters.push(configured_exporter(sink, parameters)?);
+        }
+
+        Ok(Self::new(exporters))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn write_batch(&mut self, records: &[FluereRecord]) {
+        if records.is_empty() {
+            return;
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.run("write to", |exporter| exporter.write_batch(records));
+        }
+    }
+
+    pub fn rotate(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.run("rotate", |exporter| exporter.rotate());
+        }
+    }
+
+    /// Closes the record exporter of the mode, the first sink, and writes the next records to
+    /// `records` instead. The other sinks are rotated.
+    pub fn replace_records(&mut self, records: Box<dyn FlowExporter>) {
+        let Some((first, others)) = self.sinks.split_first_mut() else {
+            return;
+        };
+        if first.is_open
+            && let Err(err) = first.exporter.close()
+        {
+            error!("Failed to close sink {}: {}", first.exporter.name(), err);
+        }
+        *first = SinkState {
+            exporter: records,
+            is_open: false,
+            failures: 0,
+        };
+        first.run("open", |_| Ok(()));
+        for sink in others {
+            sink.run("rotate", |exporter| exporter.rotate());
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for sink in self.sinks.iter_mut() {
+            sink.run("flush", |exporter| exporter.flush());
+        }
+    }
+
+    /// Closes every sink that is open.
+    pub fn close(mut self) {
+        for sink in self.sinks.iter_mut().filter(|sink| sink.is_open) {
+            match sink.exporter.close() {
+                Ok(()) => info!("Closed sink {}", sink.exporter.name()),
+                Err(err) => error!("Failed to close sink {}: {}", sink.exporter.name(), err),
+            }
+        }
+    }
+}
+
+fn netflow_exporter(collector: &str, version: Option<u8>) -> Box<dyn FlowExporter> {
+    match version {
+        Some(9) => Box::new(NetflowV9Exporter::new(collector)),
+        Some(5) | None => Box::new(NetflowV5Exporter::new(collector)),
+        Some(version) => {
+            warn!(
+                "Unsupported NetFlow version {}, sending NetFlow v5",
+                version
+            );
+            Box::new(NetflowV5Exporter::new(collector))
+        }
+    }
+}
+
+fn ipfix_exporter(destination: &str, enterprise_number: Option<u32>) -> Box<dyn FlowExporter> {
+    Box::new(
+        IpfixExporter::new(destination)
+            .with_enterprise_number(enterprise_number.unwrap_or(DEFAULT_IPFIX_ENTERPRISE)),
+    )
+}
+
+/// Exporter writing the records in the format of the command line, to the standard output
+/// if asked, else to `name` files in the output directory, a new one at every rotation if
+/// `rotated`. The interface captured from may be part of the file names.
+pub fn format_exporter(
+    files: &Files,
+    parameters: &Parameters,
+    interface: Option<&str>,
+    name: &str,
+    rotated: bool,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    let directory = files.output_dir.as_deref().unwrap_or(DEFAULT_DIRECTORY);
+    let options = file_options(parameters, interface)?;
+
+    let format = files.format.unwrap_or_default();
+    let extension = match format {
+        ExportFormat::Csv => ".csv",
+        ExportFormat::Jsonl => ".jsonl",
+        ExportFormat::Parquet => ".parquet",
+    };
+    let output = match (files.stdout.unwrap_or(false), rotated) {
+        (true, _) => RecordOutput::Stdout,
+        (false, true) => RecordOutput::Timestamped {
+            directory: directory.to_string(),
+            name: name.to_string(),
+            extension,
+            options: options.clone(),
+        },
+        (false, false) => RecordOutput::File {
+            directory: directory.to_string(),
+            name: name.to_string(),
+            extension,
+            options,
+        },
+    };
+
+    Ok(match format {
+        ExportFormat::Csv => {
+            let format = CsvFormat::new(record_fields(parameters)?)
+                .with_delimiter(delimiter(parameters.delimiter.as_deref())?)
+                .with_header(parameters.header.unwrap_or(true));
+            Box::new(CsvExporter::new(output, format))
+        }
+        ExportFormat::Jsonl => Box::new(JsonlExporter::new(output, jsonl_format(parameters)?)),
+        ExportFormat::Parquet => {
+            parquet_exporter(output, parameters.address_format.unwrap_or_default())?
+        }
+    })
+}
+
+/// Output of collected data that is not flows, such as the interface counters sampled by sFlow
+/// agents: `name` CSV files in the output directory, a new one at every rotation.
+pub fn csv_rows_output(
+    files: &Files,
+    parameters: &Parameters,
+    name: &str,
+) -> Result<RecordOutput, FluereError> {
+    Ok(RecordOutput::Timestamped {
+        directory: files
+            .output_dir
+            .as_deref()
+            .unwrap_or(DEFAULT_DIRECTORY)
+            .to_string(),
+        name: name.to_string(),
+        extension: ".csv",
+        options: file_options(parameters, None)?,
+    })
+}
+
+// Naming, rotation and retention of record files as set on the command line
+fn file_options(
+    parameters: &Parameters,
+    interface: Option<&str>,
+) -> Result<FileOptions, FluereError> {
+    let options = FileOptions {
+        template: parameters.file_template.clone(),
+        interface: interface.map(str::to_string),
+        max_bytes: parameters
+            .rotate_size
+            .filter(|megabytes| *megabytes > 0)
+            .map(|megabytes| megabytes * 1024 * 1024),
+        max_records: parameters.rotate_records.filter(|records| *records > 0),
+        compression: parameters.compression.unwrap_or_default(),
+        max_files: parameters.max_files.filter(|files| *files > 0),
+        max_age: parameters
+            .max_age
+            .filter(|hours| *hours > 0)
+            .map(|hours| Duration::from_secs(hours * 3_600)),
+    };
+    options.validate()?;
+    Ok(options)
+}
+
+// JSON objects as selected on the command line
+fn jsonl_format(parameters: &Parameters) -> Result<JsonlFormat, FluereError> {
+    JsonlFormat::new(
+        parameters.fields.as_deref(),
+        parameters.timestamp_format.unwrap_or_default(),
+        parameters.protocol_format.unwrap_or_default(),
+    )
+}
+
+// CSV columns as selected on the command line
+fn record_fields(parameters: &Parameters) -> Result<RecordFields, FluereError> {
+    RecordFields::new(
+        parameters.fields.as_deref(),
+        parameters.timestamp_format.unwrap_or_default(),
+        parameters.protocol_format.unwrap_or_default(),
+    )
+}
+
+// A single ASCII character, or `tab`, a comma by default
+fn delimiter(text: Option<&str>) -> Result<u8, FluereError> {
+    match text.unwrap_or(",") {
+        "tab" | "\\t" | "\t" => Ok(b'\t'),
+        text if text.len() == 1 && text.is_ascii() => Ok(text.as_bytes()[0]),
+        text => Err(FluereError::ConfigError(format!(
+            "CSV delimiter must be a single ASCII character: {}",
+            text
+        ))),
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_exporter(
+    output: RecordOutput,
+    addresses: AddressFormat,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    Ok(Box::new(super::ParquetExporter::new(output, addresses)))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_exporter(
+    _output: RecordOutput,
+    _addresses: AddressFormat,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    Err(FluereError::ConfigError(
+        "Parquet export needs fluere built with the `parquet` feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_exporter(
+    path: &str,
+    retention_hours: Option<u64>,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    Ok(Box::new(super::SqliteExporter::new(path, retention_hours)))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_exporter(
+    _path: &str,
+    _retention_hours: Option<u64>,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    Err(FluereError::ConfigError(
+        "SQLite export needs fluere built with the `sqlite` feature".to_string(),
+    ))
+}
+
+fn timestamp_format(text: Option<&str>) -> Result<TimestampFormat, FluereError> {
+    Ok(text
+        .map(TimestampFormat::try_from)
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn protocol_format(text: Option<&str>) -> Result<ProtocolFormat, FluereError> {
+    Ok(text
+        .map(ProtocolFormat::try_from)
+        .transpose()?
+        .unwrap_or_default())
+}
+
+fn configured_exporter(
+    sink: &Sink,
+    parameters: &Parameters,
+) -> Result<Box<dyn FlowExporter>, FluereError> {
+    let exporter: Box<dyn FlowExporter> = match sink {
+        Sink::Csv {
+            directory,
+            name,
+            columns,
+            timestamps,
+            protocol,
+            delimiter: separator,
+            header,
+        } => {
+            let columns = RecordFields::new(
+                columns.as_ref().map(|columns| columns.join(",")).as_deref(),
+                timestamp_format(timestamps.as_deref())?,
+                protocol_format(protocol.as_deref())?,
+            )?;
+            let format = CsvFormat::new(columns)
+                .with_delimiter(delimiter(separator.as_deref())?)
+                .with_header(header.unwrap_or(true));
+            let output = RecordOutput::Timestamped {
+                directory: directory
+                    .as_deref()
+                    .unwrap_or(DEFAULT_DIRECTORY)
+                    .to_string(),
+                name: name.clone(),
+                extension: ".csv",
+                options: FileOptions::default(),
+            };
+            Box::new(CsvExporter::new(output, format))
+        }
+        Sink::Jsonl {
+            directory,
+            name,
+            fields,
+            timestamps,
+            protocol,
+        } => {
+            let format = JsonlFormat::new(
+                fields.as_ref().map(|fields| fields.join(",")).as_deref(),
+                timestamp_format(timestamps.as_deref())?,
+                protocol_format(protocol.as_deref())?,
+            )?;
+            let output = RecordOutput::Timestamped {
+                directory: directory
+                    .as_deref()
+                    .unwrap_or(DEFAULT_DIRECTORY)
+                    .to_string(),
+                name: name.clone(),
+                extension: ".jsonl",
+                options: FileOptions::default(),
+            };
+            Box::new(JsonlExporter::new(output, format))
+        }
+        Sink::Parquet {
+            directory,
+            name,
+            addresses,
+        } => {
+            let output = RecordOutput::Timestamped {
+                directory: directory
+                    .as_deref()
+                    .unwrap_or(DEFAULT_DIRECTORY)
+                    .to_string(),
+                name: name.clone(),
+                extension: ".parquet",
+                options: FileOptions::default(),
+            };
+            let addresses = addresses
+                .as_deref()
+                .map(AddressFormat::try_from)
+                .transpose()?
+                .unwrap_or_default();
+            parquet_exporter(output, addresses)?
+        }
+        Sink::Sqlite {
+            path,
+            retention_hours,
+        } => sqlite_exporter(path, *retention_hours)?,
+        Sink::Netflow { collector, version } => netflow_exporter(collector, *version),
+        Sink::Ipfix { destination } => ipfix_exporter(destination, parameters.ipfix_enterprise),
+        Sink::Stream {
+            destination,
+            format,
+            buffer,
+        } => {
+            let format = format
+                .as_deref()
+                .map(StreamFormat::try_from)
+                .transpose()?
+                .unwrap_or_default();
+            let json =
+                JsonlFormat::new(None, TimestampFormat::default(), ProtocolFormat::default())?;
+            Box::new(StreamExporter::new(
+                destination,
+                format,
+                json,
+                buffer.unwrap_or(DEFAULT_STREAM_BUFFER),
+            )?)
+        }
+    };
+
+    Ok(exporter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::utils::test_support::udp_flow;
+
+    // Count shared between a test and the exporter it hands over
+    type Counter = Arc<Mutex<usize>>;
+
+    // Counts the records it receives, failing its first writes
+    struct CountingExporter {
+        failing_writes: usize,
+        opens: Counter,
+        records: Counter,
+    }
+
+    impl FlowExporter for CountingExporter {
+        fn name(&self) -> String {
+            "counting".to_string()
+        }
+
+        fn open(&mut self) -> io::Result<()> {
+            *self.opens.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+            if self.failing_writes > 0 {
+                self.failing_writes -= 1;
+                return Err(io::Error::other("collector unreachable"));
+            }
+            *self.records.lock().unwrap() += records.len();
+            Ok(())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_failing_sink_does_not_stop_others() {
+        let counters: Vec<(Counter, Counter)> = (0..2)
+            .map(|_| (Arc::new(Mutex::new(0)), Arc::new(Mutex::new(0))))
+            .collect();
+        let mut exporters = Exporters::new(
+            counters
+                .iter()
+                .zip([1, 0])
+                .map(|((opens, records), failing_writes)| {
+                    Box::new(CountingExporter {
+                        failing_writes,
+                        opens: opens.clone(),
+                        records: records.clone(),
+                    }) as Box<dyn FlowExporter>
+                })
+                .collect(),
+        );
+        assert_eq!(exporters.len(), 2);
+
+        let record = udp_flow([10, 0, 0, 1], [10, 0, 0, 2], 0);
+        let batch = vec![record; 3];
+        exporters.write_batch(&batch);
+        exporters.write_batch(&batch);
+        exporters.close();
+
+        // The failing sink lost its first batch and was reopened for the second
+        assert_eq!(*counters[0].0.lock().unwrap(), 2);
+        assert_eq!(*counters[0].1.lock().unwrap(), 3);
+        assert_eq!(*counters[1].0.lock().unwrap(), 1);
+        assert_eq!(*counters[1].1.lock().unwrap(), 6);
+    }
+}
@@ -1,81 +1,236 @@
+// This file contains the CSV exporter, which writes flow records to CSV files or the standard
+// output. Files are started at every rotation, or once they reach their size or record count.
+// Columns are chosen like the JSON Lines fields, derived ones included, e.g.
+// `source,destination,prot_name,duration,bytes_per_packet`.
+
+use std::io;
+
+use super::{FlowExporter, OutputWriter, RecordFields, RecordOutput};
+
 use fluereflow::FluereRecord;
-use log::{debug, error, trace};
-use std::fs::File;
-
-pub async fn fluere_exporter(records: Vec<FluereRecord>, file: File) -> Result<(), csv::Error> {
-    let mut wtr = csv::Writer::from_writer(file);
-
-    debug!("Writing {} records", records.len());
-    trace!(" record: {:?}", records);
-    wtr.write_record([
-        "source",
-        "destination",
-        "src_port",
-        "dst_port",
-        "prot",
-        "d_pkts",
-        "d_octets",
-        "in_pkts",
-        "out_pkts",
-        "in_bytes",
-        "out_bytes",
-        "first",
-        "last",
-        "min_pkt",
-        "max_pkt",
-        "min_ttl",
-        "max_ttl",
-        "fin_cnt",
-        "syn_cnt",
-        "rst_cnt",
-        "psh_cnt",
-        "ack_cnt",
-        "urg_cnt",
-        "ece_cnt",
-        "cwr_cnt",
-        "ns_cnt",
-        "tos",
-    ])
-    .map_err(|e| {
-        error!("Failed to write CSV header: {}", e);
-        e
-    })?;
-
-    for flow in records.iter() {
-        wtr.write_record([
-            &flow.source.to_string(),
-            &flow.destination.to_string(),
-            &flow.src_port.to_string(),
-            &flow.dst_port.to_string(),
-            &flow.prot.to_string(),
-            &flow.d_pkts.to_string(),
-            &flow.d_octets.to_string(),
-            &flow.in_pkts.to_string(),
-            &flow.out_pkts.to_string(),
-            &flow.in_bytes.to_string(),
-            &flow.out_bytes.to_string(),
-            &flow.first.to_string(),
-            &flow.last.to_string(),
-            &flow.min_pkt.to_string(),
-            &flow.max_pkt.to_string(),
-            &flow.min_ttl.to_string(),
-            &flow.max_ttl.to_string(),
-            &flow.fin_cnt.to_string(),
-            &flow.syn_cnt.to_string(),
-            &flow.rst_cnt.to_string(),
-            &flow.psh_cnt.to_string(),
-            &flow.ack_cnt.to_string(),
-            &flow.urg_cnt.to_string(),
-            &flow.ece_cnt.to_string(),
-            &flow.cwr_cnt.to_string(),
-            &flow.ns_cnt.to_string(),
-            &flow.tos.to_string(),
-        ])
-        .map_err(|e| {
-            error!("Failed to write CSV record: {}", e);
-            e
-        })?;
+use log::{debug, info, trace};
+use serde_json::Value;
+
+pub(super) const HEADER: [&str; 36] = [
+    "source",
+    "destination",
+    "src_port",
+    "dst_port",
+    "prot",
+    "d_pkts",
+    "d_octets",
+    "in_pkts",
+    "out_pkts",
+    "in_bytes",
+    "out_bytes",
+    "first",
+    "last",
+    "min_pkt",
+    "max_pkt",
+    "min_ttl",
+    "max_ttl",
+    "fin_cnt",
+    "syn_cnt",
+    "rst_cnt",
+    "psh_cnt",
+    "ack_cnt",
+    "urg_cnt",
+    "ece_cnt",
+    "cwr_cnt",
+    "ns_cnt",
+    "tos",
+    "flow_label",
+    "outer_vlan",
+    "inner_vlan",
+    "tunnel_type",
+    "tunnel_src",
+    "tunnel_dst",
+    "tunnel_id",
+    "end_reason",
+    "exporter",
+];
+
+/// Columns, delimiter and header of the exported CSV files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvFormat {
+    columns: RecordFields,
+    delimiter: u8,
+    header: bool,
+}
+
+impl CsvFormat {
+    /// Comma separated columns, under a header row.
+    pub fn new(columns: RecordFields) -> Self {
+        Self {
+            columns,
+            delimiter: b',',
+            header: true,
+        }
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+}
+
+/// Writes flow records as CSV rows, under a header row unless disabled.
+pub struct CsvExporter {
+    output: RecordOutput,
+    format: CsvFormat,
+    path: Option<String>,
+    writer: Option<csv::Writer<OutputWriter>>,
+    // Files opened so far, and records written to the current one
+    files: u32,
+    records: u64,
+}
+
+impl CsvExporter {
+    pub fn new(output: RecordOutput, format: CsvFormat) -> Self {
+        Self {
+            output,
+            format,
+            path: None,
+            writer: None,
+            files: 0,
+            records: 0,
+        }
+    }
+}
+
+impl FlowExporter for CsvExporter {
+    fn name(&self) -> String {
+        self.path.clone().unwrap_or_else(|| self.output.name())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let (path, output) = self.output.open(self.files)?;
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.format.delimiter)
+            .from_writer(output);
+        if self.format.header {
+            writer.write_record(self.format.columns.keys())?;
+        }
+
+        info!("Export {} Started", path);
+        self.path = Some(path);
+        self.writer = Some(writer);
+        self.files += 1;
+        self.records = 0;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "CSV file is not open",
+            ));
+        };
+
+        debug!("Writing {} records", records.len());
+        trace!(" record: {:?}", records);
+        for flow in records.iter() {
+            writer.write_record(self.format.columns.values(flow).map(
+                |(_, value)| match value {
+                    Value::String(text) => text,
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                },
+            ))?;
+        }
+
+        self.records += records.len() as u64;
+        // Rows buffered by the CSV writer are not counted yet, the size is approximate
+        if self
+            .output
+            .is_full(writer.get_ref().written(), self.records)
+        {
+            self.close()?;
+            self.open()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.output.rotates() {
+            true => {
+                self.close()?;
+                self.open()
+            }
+            false => self.flush(),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+            drop(writer);
+            info!("Export {} Finished", self.name());
+            if let Some(path) = self.path.as_deref() {
+                self.output.finish(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        types::{ProtocolFormat, TimestampFormat},
+        utils::{FileOptions, test_support::tcp_flow},
+    };
+
+    #[test]
+    fn test_selected_columns_are_derived_and_delimited() {
+        let directory = std::env::temp_dir().to_string_lossy().to_string();
+        let name = format!("fluere-csv-{}", std::process::id());
+        let output = RecordOutput::File {
+            directory: directory.clone(),
+            name: name.clone(),
+            extension: ".csv",
+            options: FileOptions::default(),
+        };
+        let columns = RecordFields::new(
+            Some("source=src,prot_name,first,duration,bytes_per_packet"),
+            TimestampFormat::Seconds,
+            ProtocolFormat::Number,
+        )
+        .unwrap();
+        let format = CsvFormat::new(columns)
+            .with_delimiter(b'\t')
+            .with_header(false);
+        let mut exporter = CsvExporter::new(output, format);
+
+        let first = 1_700_000_000_500_000;
+        let record = FluereRecord {
+            d_pkts: 4,
+            d_octets: 600,
+            last: first + 1_500_000,
+            ..tcp_flow([10, 0, 0, 1], [192, 168, 0, 1], first)
+        };
+        exporter.open().unwrap();
+        exporter.write_batch(&[record]).unwrap();
+        exporter.close().unwrap();
+
+        let path = format!("{}/{}.csv", directory, name);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "10.0.0.1\ttcp\t1700000000.5\t1.5\t150.0\n");
     }
-    debug!("Wrote {} records", records.len());
-    Ok(())
 }
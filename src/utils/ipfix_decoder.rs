@@ -0,0 +1,605 @@
+// This file contains the IPFIX (RFC 7011) decoder of the collector mode.
+// Templates and options templates are cached per transport session and observation domain,
+// and withdrawn when the exporter asks for it or its TCP session ends. Variable length elements
+// are supported, and enterprise elements Fluere does not know are kept as raw extensions.
+// Options data only matters for the exporter's boot time, which resolves flow uptimes.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    net::{IpAddr, SocketAddr},
+};
+
+use super::{
+    ipfix_exporter::{
+        ACK_COUNT, CWR_COUNT, ECE_COUNT, ENTERPRISE_BIT, FIN_COUNT, FLOW_END_MILLISECONDS,
+        FLOW_START_MILLISECONDS, HEADER_LENGTH, IN_OCTETS, IN_PACKETS, MAX_PKT, MIN_PKT, NS_COUNT,
+        OUT_OCTETS, OUT_PACKETS, PSH_COUNT, RST_COUNT, SYN_COUNT, TEMPLATE_SET_ID, URG_COUNT,
+    },
+    netflow_decoder::{apply_field, flowsets, read_uint, slice, split_directions, uptime},
+    netflow_v9_exporter::{FIRST_SWITCHED, IN_BYTES, IN_PKTS, LAST_SWITCHED},
+};
+use crate::FluereError;
+
+use fluereflow::FluereRecord;
+use log::{debug, trace};
+use serde::Serialize;
+
+const VERSION: u64 = 10;
+const OPTIONS_TEMPLATE_SET_ID: u16 = 3;
+// Set IDs below this one are templates or reserved
+const MIN_DATA_SET_ID: u16 = 256;
+// Element length announcing a variable length value
+const VARIABLE_LENGTH: u16 = 65_535;
+
+// Standard Information Elements not shared with the NetFlow v9 decoder
+const OCTET_TOTAL_COUNT: u16 = 85;
+const PACKET_TOTAL_COUNT: u16 = 86;
+const FLOW_START_SECONDS: u16 = 150;
+const FLOW_END_SECONDS: u16 = 151;
+const FLOW_START_MICROSECONDS: u16 = 154;
+const FLOW_END_MICROSECONDS: u16 = 155;
+const SYSTEM_INIT_TIME_MILLISECONDS: u16 = 160;
+
+// Seconds between the NTP era (1900) and the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Field {
+    id: u16,
+    length: u16,
+    enterprise: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    fields: Vec<Field>,
+    // Options templates describe data about the exporter rather than flows
+    options: bool,
+}
+
+impl Template {
+    // Shortest record of the template, with empty variable length values
+    fn min_length(&self) -> usize {
+        self.fields
+            .iter()
+            .map(|field| match field.length {
+                VARIABLE_LENGTH => 1,
+                length => length as usize,
+            })
+            .sum()
+    }
+}
+
+/// Flow record collected from an exporter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectedRecord {
+    pub record: FluereRecord,
+    /// Raw values of the enterprise elements Fluere does not know, by enterprise number and
+    /// element ID. Only IPFIX records have some.
+    pub extensions: BTreeMap<(u32, u16), Vec<u8>>,
+}
+
+impl CollectedRecord {
+    /// Extensions as text, the values in hexadecimal by `<enterprise>.<element>`, e.g. `2959.1`.
+    pub fn extension_values(&self) -> BTreeMap<String, String> {
+        self.extensions
+            .iter()
+            .map(|((enterprise, element), value)| {
+                (format!("{}.{}", enterprise, element), hex(value))
+            })
+            .collect()
+    }
+
+    /// Extensions as rows naming the flow they belong to.
+    pub fn enterprise_elements(&self) -> Vec<EnterpriseElement> {
+        let record = &self.record;
+        self.extensions
+            .iter()
+            .map(|(&(enterprise, element), value)| EnterpriseElement {
+                exporter: record.exporter,
+                source: record.source,
+                destination: record.destination,
+                src_port: record.src_port,
+                dst_port: record.dst_port,
+                prot: record.prot,
+                first: record.first,
+                last: record.last,
+                enterprise,
+                element,
+                value: hex(value),
+            })
+            .collect()
+    }
+}
+
+impl From<FluereRecord> for CollectedRecord {
+    fn from(record: FluereRecord) -> Self {
+        Self {
+            record,
+            extensions: BTreeMap::new(),
+        }
+    }
+}
+
+/// Enterprise element Fluere does not know, with the flow it was collected in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnterpriseElement {
+    pub exporter: IpAddr,
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub prot: u8,
+    pub first: u64,
+    pub last: u64,
+    pub enterprise: u32,
+    pub element: u16,
+    /// Raw value in hexadecimal
+    pub value: String,
+}
+
+/// Decodes IPFIX messages into flow records.
+#[derive(Debug)]
+pub struct IpfixDecoder {
+    // Private enterprise number the Fluere elements are exported under
+    enterprise_number: u32,
+    // Templates by transport session, observation domain and template ID
+    templates: HashMap<(SocketAddr, u32, u16), Template>,
+    // Exporter boot times reported in options data, in microseconds
+    boot_times: HashMap<(SocketAddr, u32), u64>,
+    // Data sets dropped because their template was not known yet
+    missing_templates: u64,
+}
+
+impl IpfixDecoder {
+    /// Decoder of the Fluere elements exported under the private enterprise number, other
+    /// enterprise elements are kept as extensions.
+    pub fn new(enterprise_number: u32) -> Self {
+        Self {
+            enterprise_number,
+            templates: HashMap::new(),
+            boot_times: HashMap::new(),
+            missing_templates: 0,
+        }
+    }
+
+    /// Decodes a message of the exporter's transport session.
+    pub fn decode(
+        &mut self,
+        exporter: SocketAddr,
+        message: &[u8],
+    ) -> Result<Vec<CollectedRecord>, FluereError> {
+        let header = slice(message, 0, HEADER_LENGTH)?;
+        let version = read_uint(&header[0..2]);
+        if version != VERSION {
+            return Err(FluereError::ParseError(format!(
+                "Unsupported IPFIX version: {}",
+                version
+            )));
+        }
+        let length = (read_uint(&header[2..4]) as usize).max(HEADER_LENGTH);
+        let domain = read_uint(&header[12..16]) as u32;
+
+        let mut records = Vec::new();
+        for (id, content) in flowsets(slice(message, HEADER_LENGTH, length - HEADER_LENGTH)?)? {
+            match id {
+                TEMPLATE_SET_ID | OPTIONS_TEMPLATE_SET_ID => {
+                    self.read_templates(exporter, domain, id, content)?
+                }
+                id if id < MIN_DATA_SET_ID => {}
+                id => records.extend(self.read_data(exporter, domain, id, content)?),
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Forgets the templates of a transport session, once its TCP connection is closed.
+    pub fn close_session(&mut self, exporter: SocketAddr) {
+        self.templates
+            .retain(|(session, _, _), _| *session != exporter);
+        self.boot_times
+            .retain(|(session, _), _| *session != exporter);
+    }
+
+    /// Data sets dropped so far because their template was not known yet.
+    pub fn missing_templates(&self) -> u64 {
+        self.missing_templates
+    }
+
+    fn read_templates(
+        &mut self,
+        exporter: SocketAddr,
+        domain: u32,
+        set_id: u16,
+        mut content: &[u8],
+    ) -> Result<(), FluereError> {
+        let options = set_id == OPTIONS_TEMPLATE_SET_ID;
+        // Template records are at least 4 bytes, anything shorter is padding
+        while content.len() >= 4 {
+            let id = read_uint(&content[0..2]) as u16;
+            let count = read_uint(&content[2..4]) as usize;
+            // Only data set IDs, or the set ID itself, name templates, anything else is padding
+            if id < MIN_DATA_SET_ID && id != set_id {
+                break;
+            }
+            if count == 0 {
+                self.withdraw(exporter, domain, set_id, id);
+                content = &content[4..];
+                continue;
+            }
+            // Options templates tell how many of the fields are scope fields, which are read
+            // like the others
+            let mut offset = if options { 6 } else { 4 };
+            let mut fields = Vec::with_capacity(count);
+            for _ in 0..count {
+                let field = read_field(content, &mut offset)?;
+                fields.push(field);
+            }
+            content = &content[offset..];
+            let template = Template { fields, options };
+            // Records taking no bytes would never end a data set
+            if template.min_length() == 0 {
+                debug!("Empty template {} from {} ({})", id, exporter, domain);
+                self.templates.remove(&(exporter, domain, id));
+                continue;
+            }
+            trace!("Template {} from {} ({})", id, exporter, domain);
+            self.templates.insert((exporter, domain, id), template);
+        }
+        Ok(())
+    }
+
+    // A withdrawal naming the set ID withdraws every template of the set's kind
+    fn withdraw(&mut self, exporter: SocketAddr, domain: u32, set_id: u16, id: u16) {
+        debug!("Template {} withdrawn by {} ({})", id, exporter, domain);
+        let options = set_id == OPTIONS_TEMPLATE_SET_ID;
+        match id == set_id {
+            true => self
+                .templates
+                .retain(|(session, template_domain, _), template| {
+                    *session != exporter
+                        || *template_domain != domain
+                        || template.options != options
+                }),
+            false => {
+                self.templates.remove(&(exporter, domain, id));
+            }
+        }
+    }
+
+    fn read_data(
+        &mut self,
+        exporter: SocketAddr,
+        domain: u32,
+        id: u16,
+        content: &[u8],
+    ) -> Result<Vec<CollectedRecord>, FluereError> {
+        let Some(template) = self.templates.get(&(exporter, domain, id)) else {
+            debug!("No template {} from {} ({}) yet", id, exporter, domain);
+            self.missing_templates += 1;
+            return Ok(Vec::new());
+        };
+        let min_length = template.min_length();
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        // Whatever is left once no record fits is padding
+        while content.len() - offset >= min_length {
+            let mut values = Vec::with_capacity(template.fields.len());
+            for field in template.fields.iter() {
+                values.push((*field, read_value(content, &mut offset, field.length)?));
+            }
+            match template.options {
+                true => {
+                    if let Some(boot_time) = boot_time(&values) {
+                        self.boot_times.insert((exporter, domain), boot_time);
+                    }
+                }
+                false => records.push(values),
+            }
+        }
+
+        let boot_time = self.boot_times.get(&(exporter, domain)).copied();
+        Ok(records
+            .into_iter()
+            .map(|values| flow_record(exporter, &values, boot_time, self.enterprise_number))
+            .collect())
+    }
+}
+
+// Element ID, length and, when its high bit is set, enterprise number
+fn read_field(content: &[u8], offset: &mut usize) -> Result<Field, FluereError> {
+    let id = read_uint(slice(content, *offset, 2)?) as u16;
+    let length = read_uint(slice(content, *offset + 2, 2)?) as u16;
+    *offset += 4;
+    let enterprise = match id & ENTERPRISE_BIT {
+        0 => None,
+        _ => {
+            let enterprise = read_uint(slice(content, *offset, 4)?) as u32;
+            *offset += 4;
+            Some(enterprise)
+        }
+    };
+
+    Ok(Field {
+        id: id & !ENTERPRISE_BIT,
+        length,
+        enterprise,
+    })
+}
+
+// Variable length values start with their length, on one byte or on three bytes past 254
+fn read_value<'a>(
+    content: &'a [u8],
+    offset: &mut usize,
+    length: u16,
+) -> Result<&'a [u8], FluereError> {
+    let length = match length {
+        VARIABLE_LENGTH => {
+            let short = slice(content, *offset, 1)?[0];
+            *offset += 1;
+            match short {
+                255 => {
+                    let long = read_uint(slice(content, *offset, 2)?) as usize;
+                    *offset += 2;
+                    long
+                }
+                short => short as usize,
+            }
+        }
+        length => length as usize,
+    };
+    let value = slice(content, *offset, length)?;
+    *offset += length;
+    Ok(value)
+}
+
+fn boot_time(values: &[(Field, &[u8])]) -> Option<u64> {
+    values
+        .iter()
+        .find(|(field, _)| field.enterprise.is_none() && field.id == SYSTEM_INIT_TIME_MILLISECONDS)
+        .map(|(_, value)| read_uint(value).saturating_mul(1_000))
+}
+
+fn flow_record(
+    exporter: SocketAddr,
+    values: &[(Field, &[u8])],
+    boot_time: Option<u64>,
+    enterprise_number: u32,
+) -> CollectedRecord {
+    let mut record = FluereRecord::default();
+    let mut extensions = BTreeMap::new();
+    let mut directions = false;
+    for &(field, value) in values {
+        match field.enterprise {
+            None => apply_element(&mut record, field.id, value, boot_time),
+            Some(enterprise)
+                if enterprise == enterprise_number
+                    && apply_fluere_element(&mut record, field.id, value) =>
+            {
+                directions |= matches!(field.id, IN_PACKETS | OUT_PACKETS | IN_OCTETS | OUT_OCTETS);
+            }
+            Some(enterprise) => {
+                extensions.insert((enterprise, field.id), value.to_vec());
+            }
+        }
+    }
+    // Flows exported by Fluere carry both directions, others only their totals
+    if !directions {
+        split_directions(&mut record);
+    }
+    record.exporter = exporter.ip();
+
+    CollectedRecord { record, extensions }
+}
+
+fn apply_element(record: &mut FluereRecord, id: u16, value: &[u8], boot_time: Option<u64>) {
+    let number = read_uint(value);
+    match id {
+        // Times are 8 bytes long at most, those out of range are clamped
+        FLOW_START_SECONDS => record.first = number.saturating_mul(1_000_000),
+        FLOW_END_SECONDS => record.last = number.saturating_mul(1_000_000),
+        FLOW_START_MILLISECONDS => record.first = number.saturating_mul(1_000),
+        FLOW_END_MILLISECONDS => record.last = number.saturating_mul(1_000),
+        FLOW_START_MICROSECONDS => record.first = ntp_micros(number),
+        FLOW_END_MICROSECONDS => record.last = ntp_micros(number),
+        // Uptimes need the boot time from the options data, they are left out until it is known
+        FIRST_SWITCHED => record.first = boot_time.map_or(0, |boot| uptime(boot, number)),
+        LAST_SWITCHED => record.last = boot_time.map_or(0, |boot| uptime(boot, number)),
+        OCTET_TOTAL_COUNT => apply_field(record, IN_BYTES, value),
+        PACKET_TOTAL_COUNT => apply_field(record, IN_PKTS, value),
+        id => apply_field(record, id, value),
+    }
+}
+
+// Returns whether the element is one of Fluere's
+fn apply_fluere_element(record: &mut FluereRecord, id: u16, value: &[u8]) -> bool {
+    let number = read_uint(value);
+    match id {
+        MIN_PKT => record.min_pkt = number as u32,
+        MAX_PKT => record.max_pkt = number as u32,
+        IN_PACKETS => record.in_pkts = number as u32,
+        OUT_PACKETS => record.out_pkts = number as u32,
+        IN_OCTETS => record.in_bytes = number as usize,
+        OUT_OCTETS => record.out_bytes = number as usize,
+        FIN_COUNT => record.fin_cnt = number as u32,
+        SYN_COUNT => record.syn_cnt = number as u32,
+        RST_COUNT => record.rst_cnt = number as u32,
+        PSH_COUNT => record.psh_cnt = number as u32,
+        ACK_COUNT => record.ack_cnt = number as u32,
+        URG_COUNT => record.urg_cnt = number as u32,
+        ECE_COUNT => record.ece_cnt = number as u32,
+        CWR_COUNT => record.cwr_cnt = number as u32,
+        NS_COUNT => record.ns_cnt = number as u32,
+        _ => return false,
+    }
+    true
+}
+
+fn hex(value: &[u8]) -> String {
+    value.iter().fold(String::new(), |mut text, byte| {
+        let _ = write!(text, "{:02x}", byte);
+        text
+    })
+}
+
+// dateTimeMicroseconds are NTP timestamps, seconds since 1900 and a binary fraction
+fn ntp_micros(timestamp: u64) -> u64 {
+    let seconds = (timestamp >> 32).saturating_sub(NTP_UNIX_OFFSET);
+    let fraction = ((timestamp & 0xffff_ffff) * 1_000_000) >> 32;
+    seconds * 1_000_000 + fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    use crate::utils::{
+        DEFAULT_IPFIX_ENTERPRISE, FlowExporter, IpfixExporter, test_support::tcp_flow,
+    };
+
+    use fluereflow::FlowEndReason;
+
+    fn session() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 4739)
+    }
+
+    #[test]
+    fn test_fluere_messages_are_decoded() {
+        let path = std::env::temp_dir().join(format!("fluere-ipfix-{}.ipfix", std::process::id()));
+        let mut exporter = IpfixExporter::new(&path.to_string_lossy());
+        let start = 1_700_000_000_000_000;
+        let flow = FluereRecord {
+            ece_cnt: 1,
+            end_reason: FlowEndReason::IdleTimeout,
+            ..tcp_flow([10, 0, 0, 1], [192, 168, 0, 1], start)
+        };
+        exporter.open().unwrap();
+        exporter.export(&[flow]).unwrap();
+        exporter.close().unwrap();
+        let message = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut decoder = IpfixDecoder::new(DEFAULT_IPFIX_ENTERPRISE);
+        let records = decoder.decode(session(), &message).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].extensions.is_empty());
+        let mut expected = flow;
+        expected.exporter = session().ip();
+        assert_eq!(records[0].record, expected);
+    }
+
+    #[test]
+    fn test_fluere_elements_follow_the_enterprise_number() {
+        let path =
+            std::env::temp_dir().join(format!("fluere-ipfix-pen-{}.ipfix", std::process::id()));
+        let mut exporter = IpfixExporter::new(&path.to_string_lossy()).with_enterprise_number(4242);
+        let flow = tcp_flow([10, 0, 0, 1], [192, 168, 0, 1], 1_700_000_000_000_000);
+        exporter.open().unwrap();
+        exporter.export(&[flow]).unwrap();
+        exporter.close().unwrap();
+        let message = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let records = IpfixDecoder::new(4242).decode(session(), &message).unwrap();
+        assert!(records[0].extensions.is_empty());
+        assert_eq!(records[0].record.min_pkt, 60);
+
+        // Under another number, they are someone else's elements
+        let records = IpfixDecoder::new(DEFAULT_IPFIX_ENTERPRISE)
+            .decode(session(), &message)
+            .unwrap();
+        assert_eq!(records[0].record.min_pkt, 0);
+        assert_eq!(
+            records[0].extensions.get(&(4242, MIN_PKT)),
+            Some(&60u32.to_be_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn test_variable_length_and_enterprise_elements() {
+        let template_set = [
+            // Template 300: sourceIPv4Address, an unknown enterprise element of variable
+            // length, and octetTotalCount
+            &[0, 2, 0, 28, 0x01, 0x2c, 0, 3][..],
+            &[0, 8, 0, 4],
+            &[0x80, 0x01, 0xff, 0xff, 0, 0, 0x0b, 0x8f],
+            &[0, 85, 0, 4],
+            // Padding
+            &[0, 0, 0, 0],
+        ]
+        .concat();
+        let data_set = [
+            &[0x01, 0x2c, 0, 15, 10, 0, 0, 1, 2][..],
+            b"fw",
+            &[0, 0, 0, 100],
+        ]
+        .concat();
+        let withdrawal = [0, 2, 0, 8, 0x01, 0x2c, 0, 0];
+        let message = |sets: &[&[u8]]| {
+            let sets = sets.concat();
+            let length = (HEADER_LENGTH + sets.len()) as u16;
+            [&[0, 10][..], &length.to_be_bytes(), &[0; 12], &sets].concat()
+        };
+
+        let mut decoder = IpfixDecoder::new(DEFAULT_IPFIX_ENTERPRISE);
+        let records = decoder
+            .decode(session(), &message(&[&template_set, &data_set]))
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.record.source, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(record.record.d_octets, 100);
+        assert_eq!(record.extensions.get(&(2959, 1)), Some(&b"fw".to_vec()));
+        assert_eq!(
+            record.extension_values().get("2959.1").map(String::as_str),
+            Some("6677")
+        );
+        let elements = record.enterprise_elements();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].exporter, session().ip());
+        assert_eq!(elements[0].element, 1);
+
+        // Data of a withdrawn template is dropped
+        let records = decoder
+            .decode(session(), &message(&[&withdrawal, &data_set]))
+            .unwrap();
+        assert!(records.is_empty());
+        assert_eq!(decoder.missing_templates(), 1);
+    }
+
+    #[test]
+    fn test_out_of_range_times_are_clamped() {
+        // Template 302: flowStartSeconds and flowEndMilliseconds, 8 bytes long
+        let template_set = [0, 2, 0, 16, 0x01, 0x2e, 0, 2, 0, 150, 0, 8, 0, 153, 0, 8];
+        let data_set = [&[0x01, 0x2e, 0, 20][..], &[0xff; 16]].concat();
+        let sets = [&template_set[..], &data_set].concat();
+        let length = (HEADER_LENGTH + sets.len()) as u16;
+        let message = [&[0, 10][..], &length.to_be_bytes(), &[0; 12], &sets].concat();
+
+        let mut decoder = IpfixDecoder::new(DEFAULT_IPFIX_ENTERPRISE);
+        let records = decoder.decode(session(), &message).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].record.first, u64::MAX);
+        assert_eq!(records[0].record.last, u64::MAX);
+    }
+
+    #[test]
+    fn test_templates_of_empty_records_are_dropped() {
+        // Template 301: sourceIPv4Address and octetTotalCount, both zero bytes long
+        let template_set = [0, 2, 0, 16, 0x01, 0x2d, 0, 2, 0, 8, 0, 0, 0, 85, 0, 0];
+        let data_set = [0x01, 0x2d, 0, 8, 10, 0, 0, 1];
+        let sets = [&template_set[..], &data_set].concat();
+        let length = (HEADER_LENGTH + sets.len()) as u16;
+        let message = [&[0, 10][..], &length.to_be_bytes(), &[0; 12], &sets].concat();
+
+        let mut decoder = IpfixDecoder::new(DEFAULT_IPFIX_ENTERPRISE);
+        let records = decoder.decode(session(), &message).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(decoder.missing_templates(), 1);
+    }
+}
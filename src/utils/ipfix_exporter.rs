@@ -0,0 +1,466 @@
+// This file contains the IPFIX (RFC 7011) exporter.
+// Records are described by an IPv4 and an IPv6 template made of standard Information Elements,
+// plus Fluere specific elements under a private enterprise number. Messages are sent
+// over UDP, with the templates refreshed periodically, over TCP, or written to an IPFIX file
+// (RFC 5655), where the templates are only needed once at the start of the stream.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{TcpStream, UdpSocket},
+};
+
+use super::{
+    FlowExporter, connect_udp,
+    netflow_v9_exporter::{
+        FLOW_END_REASON, IN_BYTES, IN_PKTS, IPV4_DST_ADDR, IPV4_SRC_ADDR, IPV6_DST_ADDR,
+        IPV6_FLOW_LABEL, IPV6_SRC_ADDR, L4_DST_PORT, L4_SRC_PORT, MAX_TTL, MIN_TTL, PROTOCOL,
+        SRC_TOS, TCP_FLAGS, field_value, write_address, write_uint,
+    },
+};
+
+use fluereflow::FluereRecord;
+use log::debug;
+
+const VERSION: u16 = 10;
+pub(super) const HEADER_LENGTH: usize = 16;
+pub(super) const SET_HEADER_LENGTH: usize = 4;
+pub(super) const TEMPLATE_SET_ID: u16 = 2;
+// Keeps UDP messages below the usual path MTU, stream messages can be larger
+const MAX_DATAGRAM: usize = 1400;
+const MAX_STREAM_MESSAGE: usize = 65_000;
+
+// Templates are sent again over UDP after this many messages, or this much packet time in
+// microseconds
+const TEMPLATE_REFRESH_MESSAGES: u32 = 20;
+const TEMPLATE_REFRESH_TIME: u64 = 60_000_000;
+
+// Fluere has no private enterprise number registered with IANA yet. Its elements default to the
+// number reserved for documentation (RFC 5612), a placeholder to replace with a registered
+// number (`--ipfix-enterprise`) for exports leaving a lab.
+pub const DEFAULT_IPFIX_ENTERPRISE: u32 = 32473;
+pub(super) const ENTERPRISE_BIT: u16 = 0x8000;
+
+// Standard Information Elements not shared with the NetFlow v9 exporter
+pub(super) const FLOW_START_MILLISECONDS: u16 = 152;
+pub(super) const FLOW_END_MILLISECONDS: u16 = 153;
+
+// Fluere Information Elements
+pub(super) const MIN_PKT: u16 = 1;
+pub(super) const MAX_PKT: u16 = 2;
+pub(super) const IN_PACKETS: u16 = 3;
+pub(super) const OUT_PACKETS: u16 = 4;
+pub(super) const IN_OCTETS: u16 = 5;
+pub(super) const OUT_OCTETS: u16 = 6;
+pub(super) const FIN_COUNT: u16 = 7;
+pub(super) const SYN_COUNT: u16 = 8;
+pub(super) const RST_COUNT: u16 = 9;
+pub(super) const PSH_COUNT: u16 = 10;
+pub(super) const ACK_COUNT: u16 = 11;
+pub(super) const URG_COUNT: u16 = 12;
+pub(super) const ECE_COUNT: u16 = 13;
+pub(super) const CWR_COUNT: u16 = 14;
+pub(super) const NS_COUNT: u16 = 15;
+
+#[derive(Debug, Clone, Copy)]
+struct Element {
+    id: u16,
+    length: u16,
+    enterprise: bool,
+}
+
+const fn standard(id: u16, length: u16) -> Element {
+    Element {
+        id,
+        length,
+        enterprise: false,
+    }
+}
+
+const fn fluere(id: u16, length: u16) -> Element {
+    Element {
+        id,
+        length,
+        enterprise: true,
+    }
+}
+
+// Elements following the family specific ones in both templates
+const COMMON_ELEMENTS: [Element; 27] = [
+    standard(L4_SRC_PORT, 2),
+    standard(L4_DST_PORT, 2),
+    standard(PROTOCOL, 1),
+    standard(SRC_TOS, 1),
+    standard(IN_BYTES, 8),
+    standard(IN_PKTS, 8),
+    standard(FLOW_START_MILLISECONDS, 8),
+    standard(FLOW_END_MILLISECONDS, 8),
+    standard(TCP_FLAGS, 2),
+    standard(MIN_TTL, 1),
+    standard(MAX_TTL, 1),
+    standard(FLOW_END_REASON, 1),
+    fluere(MIN_PKT, 4),
+    fluere(MAX_PKT, 4),
+    fluere(IN_PACKETS, 4),
+    fluere(OUT_PACKETS, 4),
+    fluere(IN_OCTETS, 8),
+    fluere(OUT_OCTETS, 8),
+    fluere(FIN_COUNT, 4),
+    fluere(SYN_COUNT, 4),
+    fluere(RST_COUNT, 4),
+    fluere(PSH_COUNT, 4),
+    fluere(ACK_COUNT, 4),
+    fluere(URG_COUNT, 4),
+    fluere(ECE_COUNT, 4),
+    fluere(CWR_COUNT, 4),
+    fluere(NS_COUNT, 4),
+];
+
+struct Template {
+    id: u16,
+    // Address elements and the elements only one of the families has
+    family_elements: &'static [Element],
+}
+
+const IPV4_TEMPLATE: Template = Template {
+    id: 256,
+    family_elements: &[standard(IPV4_SRC_ADDR, 4), standard(IPV4_DST_ADDR, 4)],
+};
+const IPV6_TEMPLATE: Template = Template {
+    id: 257,
+    family_elements: &[
+        standard(IPV6_SRC_ADDR, 16),
+        standard(IPV6_DST_ADDR, 16),
+        standard(IPV6_FLOW_LABEL, 4),
+    ],
+};
+
+impl Template {
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.family_elements.iter().chain(COMMON_ELEMENTS.iter())
+    }
+
+    fn record_length(&self) -> usize {
+        self.elements().map(|element| element.length as usize).sum()
+    }
+
+    fn write(&self, message: &mut Vec<u8>, enterprise_number: u32) {
+        message.extend_from_slice(&self.id.to_be_bytes());
+        message.extend_from_slice(&(self.elements().count() as u16).to_be_bytes());
+        for element in self.elements() {
+            if element.enterprise {
+                message.extend_from_slice(&(element.id | ENTERPRISE_BIT).to_be_bytes());
+                message.extend_from_slice(&element.length.to_be_bytes());
+                message.extend_from_slice(&enterprise_number.to_be_bytes());
+            } else {
+                message.extend_from_slice(&element.id.to_be_bytes());
+                message.extend_from_slice(&element.length.to_be_bytes());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Destination {
+    Udp(String),
+    Tcp(String),
+    File(String),
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    File(BufWriter<File>),
+}
+
+/// Sends flow records to an IPFIX collector, or writes them to an IPFIX file.
+#[derive(Debug)]
+pub struct IpfixExporter {
+    destination: Destination,
+    transport: Option<Transport>,
+    // Private enterprise number of the Fluere elements
+    enterprise_number: u32,
+    // Data records sent so far
+    sequence: u32,
+    // Messages sent and packet time since the templates were last sent
+    messages_since_templates: u32,
+    last_templates: Option<u64>,
+}
+
+impl IpfixExporter {
+    /// Exporter to `udp://host:port` or `tcp://host:port` for a collector, anything else is
+    /// the path of the IPFIX file to create. The destination is opened by
+    /// [`FlowExporter::open`].
+    pub fn new(destination: &str) -> Self {
+        let destination = if let Some(collector) = destination.strip_prefix("udp://") {
+            Destination::Udp(collector.to_string())
+        } else if let Some(collector) = destination.strip_prefix("tcp://") {
+            Destination::Tcp(collector.to_string())
+        } else {
+            Destination::File(destination.to_string())
+        };
+
+        Self {
+            destination,
+            transport: None,
+            enterprise_number: DEFAULT_IPFIX_ENTERPRISE,
+            sequence: 0,
+            messages_since_templates: 0,
+            last_templates: None,
+        }
+    }
+
+    /// Exports the Fluere elements under another private enterprise number.
+    pub fn with_enterprise_number(mut self, enterprise_number: u32) -> Self {
+        self.enterprise_number = enterprise_number;
+        self
+    }
+
+    /// Sends the records and returns how many were sent.
+    pub fn export(&mut self, records: &[FluereRecord]) -> io::Result<usize> {
+        let (ipv4, ipv6): (Vec<&FluereRecord>, Vec<&FluereRecord>) =
+            records.iter().partition(|record| record.source.is_ipv4());
+
+        for (template, flows) in [(&IPV4_TEMPLATE, ipv4), (&IPV6_TEMPLATE, ipv6)] {
+            for chunk in flows.chunks(self.records_per_message(template)) {
+                let message = self.encode(template, chunk);
+                self.send(&message)?;
+            }
+        }
+
+        Ok(records.len())
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        match &mut self.transport {
+            Some(Transport::Udp(socket)) => socket.send(message).map(|_| ()),
+            Some(Transport::Tcp(stream)) => stream.write_all(message),
+            Some(Transport::File(file)) => file.write_all(message),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "IPFIX destination is not open",
+            )),
+        }
+    }
+
+    fn records_per_message(&self, template: &Template) -> usize {
+        let max_message = match self.destination {
+            Destination::Udp(_) => MAX_DATAGRAM,
+            Destination::Tcp(_) | Destination::File(_) => MAX_STREAM_MESSAGE,
+        };
+        let templates = SET_HEADER_LENGTH
+            + [&IPV4_TEMPLATE, &IPV6_TEMPLATE]
+                .iter()
+                .map(|template| {
+                    4 + template
+                        .elements()
+                        .map(|element| if element.enterprise { 8 } else { 4 })
+                        .sum::<usize>()
+                })
+                .sum::<usize>();
+
+        (max_message - HEADER_LENGTH - templates - SET_HEADER_LENGTH) / template.record_length()
+    }
+
+    fn encode(&mut self, template: &Template, records: &[&FluereRecord]) -> Vec<u8> {
+        let now = records.iter().map(|record| record.last).max().unwrap_or(0);
+
+        let mut message = Vec::with_capacity(MAX_DATAGRAM);
+        message.extend_from_slice(&VERSION.to_be_bytes());
+        // Length, filled in once the sets are written
+        message.extend_from_slice(&[0, 0]);
+        message.extend_from_slice(&((now / 1_000_000) as u32).to_be_bytes());
+        message.extend_from_slice(&self.sequence.to_be_bytes());
+        // Observation domain ID
+        message.extend_from_slice(&0u32.to_be_bytes());
+
+        if self.templates_due(now) {
+            let enterprise_number = self.enterprise_number;
+            write_set(&mut message, TEMPLATE_SET_ID, |message| {
+                IPV4_TEMPLATE.write(message, enterprise_number);
+                IPV6_TEMPLATE.write(message, enterprise_number);
+            });
+        }
+        write_set(&mut message, template.id, |message| {
+            for record in records {
+                for element in template.elements() {
+                    write_element(message, element, record);
+                }
+            }
+        });
+        let length = message.len() as u16;
+        message[2..4].copy_from_slice(&length.to_be_bytes());
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+
+        message
+    }
+
+    // Streams carry the templates once, UDP collectors need them again from time to time
+    fn templates_due(&mut self, now: u64) -> bool {
+        let due = match (self.last_templates, &self.destination) {
+            (None, _) => true,
+            (Some(_), Destination::Tcp(_) | Destination::File(_)) => false,
+            (Some(last), Destination::Udp(_)) => {
+                self.messages_since_templates >= TEMPLATE_REFRESH_MESSAGES
+                    || now >= last + TEMPLATE_REFRESH_TIME
+            }
+        };
+        if due {
+            self.last_templates = Some(now);
+            self.messages_since_templates = 0;
+        }
+        self.messages_since_templates += 1;
+
+        due
+    }
+}
+
+impl FlowExporter for IpfixExporter {
+    fn name(&self) -> String {
+        match &self.destination {
+            Destination::Udp(collector) => format!("ipfix udp://{}", collector),
+            Destination::Tcp(collector) => format!("ipfix tcp://{}", collector),
+            Destination::File(path) => format!("ipfix {}", path),
+        }
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let transport = match &self.destination {
+            Destination::Udp(collector) => Transport::Udp(connect_udp(collector)?),
+            Destination::Tcp(collector) => Transport::Tcp(TcpStream::connect(collector)?),
+            Destination::File(path) => Transport::File(BufWriter::new(File::create(path)?)),
+        };
+        debug!("Exporting to {}", self.name());
+        self.transport = Some(transport);
+        // A new stream or file starts without the templates
+        self.last_templates = None;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        self.export(records).map(|_| ())
+    }
+
+    /// Writes out the buffered messages of a file or stream.
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.transport {
+            Some(Transport::Tcp(stream)) => stream.flush(),
+            Some(Transport::File(file)) => file.flush(),
+            Some(Transport::Udp(_)) | None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let flushed = self.flush();
+        self.transport = None;
+        flushed
+    }
+}
+
+// Writes a set header and its content; IPFIX sets need no padding
+fn write_set(message: &mut Vec<u8>, id: u16, content: impl FnOnce(&mut Vec<u8>)) {
+    let start = message.len();
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&[0, 0]);
+    content(message);
+
+    let length = (message.len() - start) as u16;
+    message[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+fn write_element(message: &mut Vec<u8>, element: &Element, record: &FluereRecord) {
+    let value = match (element.enterprise, element.id) {
+        (false, IPV4_SRC_ADDR | IPV6_SRC_ADDR) => return write_address(message, record.source),
+        (false, IPV4_DST_ADDR | IPV6_DST_ADDR) => {
+            return write_address(message, record.destination);
+        }
+        (false, FLOW_START_MILLISECONDS) => record.first / 1_000,
+        (false, FLOW_END_MILLISECONDS) => record.last / 1_000,
+        (false, id) => field_value(id, record),
+        (true, id) => fluere_value(id, record),
+    };
+    write_uint(message, value, element.length);
+}
+
+fn fluere_value(id: u16, record: &FluereRecord) -> u64 {
+    match id {
+        MIN_PKT => record.min_pkt as u64,
+        MAX_PKT => record.max_pkt as u64,
+        IN_PACKETS => record.in_pkts as u64,
+        OUT_PACKETS => record.out_pkts as u64,
+        IN_OCTETS => record.in_bytes as u64,
+        OUT_OCTETS => record.out_bytes as u64,
+        FIN_COUNT => record.fin_cnt as u64,
+        SYN_COUNT => record.syn_cnt as u64,
+        RST_COUNT => record.rst_cnt as u64,
+        PSH_COUNT => record.psh_cnt as u64,
+        ACK_COUNT => record.ack_cnt as u64,
+        URG_COUNT => record.urg_cnt as u64,
+        ECE_COUNT => record.ece_cnt as u64,
+        CWR_COUNT => record.cwr_cnt as u64,
+        NS_COUNT => record.ns_cnt as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_support::tcp_flow;
+
+    use fluereflow::FlowEndReason;
+
+    fn record(first: u64) -> FluereRecord {
+        FluereRecord {
+            end_reason: FlowEndReason::LackOfResources,
+            ..tcp_flow([10, 0, 0, 1], [192, 168, 0, 1], first)
+        }
+    }
+
+    #[test]
+    fn test_file_starts_with_templates() {
+        let path = std::env::temp_dir().join(format!("fluere-{}.ipfix", std::process::id()));
+        let mut exporter = IpfixExporter::new(path.to_str().unwrap());
+        exporter.open().unwrap();
+        let start = 1_700_000_000_000_000;
+        exporter.export(&[record(start)]).unwrap();
+        exporter.export(&[record(start + 1)]).unwrap();
+        exporter.flush().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // First message: header, template set, one data record
+        assert_eq!(&data[0..2], &[0, 10]);
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        assert_eq!(&data[4..8], &1_700_000_001u32.to_be_bytes());
+        assert_eq!(&data[8..12], &0u32.to_be_bytes());
+        assert_eq!(&data[16..18], &TEMPLATE_SET_ID.to_be_bytes());
+        let template_set = u16::from_be_bytes([data[18], data[19]]) as usize;
+        let record_length = IPV4_TEMPLATE.record_length();
+        assert_eq!(
+            length,
+            HEADER_LENGTH + template_set + SET_HEADER_LENGTH + record_length
+        );
+
+        let data_set = &data[HEADER_LENGTH + template_set..length];
+        assert_eq!(&data_set[0..2], &256u16.to_be_bytes());
+        let flow = &data_set[SET_HEADER_LENGTH..];
+        assert_eq!(&flow[0..8], &[10, 0, 0, 1, 192, 168, 0, 1]);
+        // flowStartMilliseconds, after ports, protocol, TOS and the two counters
+        assert_eq!(&flow[30..38], &1_700_000_000_000u64.to_be_bytes());
+        // flowEndReason: lack of resources
+        assert_eq!(flow[50], 5);
+
+        // Second message: no templates, sequence counts the first data record
+        let second = &data[length..];
+        assert_eq!(
+            second.len(),
+            HEADER_LENGTH + SET_HEADER_LENGTH + record_length
+        );
+        assert_eq!(&second[8..12], &1u32.to_be_bytes());
+        assert_eq!(&second[16..18], &256u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,181 @@
+// This file contains the JSON Lines exporter, which writes every flow record as a JSON object on
+// its own line, to rotated files or the standard output.
+// The fields of the objects can be chosen and renamed, e.g. `source=src,destination=dst,prot`.
+
+use std::io::{self, Write};
+
+use super::{FlowExporter, OutputWriter, RecordFields, RecordOutput};
+use crate::{
+    FluereError,
+    types::{ProtocolFormat, TimestampFormat},
+};
+
+use fluereflow::FluereRecord;
+use log::{debug, info};
+
+/// Fields and value formats of the exported JSON objects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonlFormat {
+    fields: RecordFields,
+}
+
+impl JsonlFormat {
+    /// Parses the field selection, a comma separated list of record fields each optionally
+    /// renamed with `field=key`. Every field is written when no selection is given.
+    pub fn new(
+        fields: Option<&str>,
+        timestamps: TimestampFormat,
+        protocol: ProtocolFormat,
+    ) -> Result<Self, FluereError> {
+        Ok(Self {
+            fields: RecordFields::new(fields, timestamps, protocol)?,
+        })
+    }
+
+    pub(super) fn write_record(
+        &self,
+        writer: &mut dyn Write,
+        record: &FluereRecord,
+    ) -> io::Result<()> {
+        writer.write_all(b"{")?;
+        for (index, (key, value)) in self.fields.values(record).enumerate() {
+            if index > 0 {
+                writer.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut *writer, key)?;
+            writer.write_all(b":")?;
+            serde_json::to_writer(&mut *writer, &value)?;
+        }
+        writer.write_all(b"}\n")
+    }
+}
+
+/// Writes flow records as JSON Lines.
+pub struct JsonlExporter {
+    output: RecordOutput,
+    format: JsonlFormat,
+    path: Option<String>,
+    writer: Option<OutputWriter>,
+    // Files opened so far, and records written to the current one
+    files: u32,
+    records: u64,
+}
+
+impl JsonlExporter {
+    pub fn new(output: RecordOutput, format: JsonlFormat) -> Self {
+        Self {
+            output,
+            format,
+            path: None,
+            writer: None,
+            files: 0,
+            records: 0,
+        }
+    }
+}
+
+impl FlowExporter for JsonlExporter {
+    fn name(&self) -> String {
+        self.path.clone().unwrap_or_else(|| self.output.name())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let (path, writer) = self.output.open(self.files)?;
+
+        info!("Export {} Started", path);
+        self.path = Some(path);
+        self.writer = Some(writer);
+        self.files += 1;
+        self.records = 0;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "JSON Lines output is not open",
+            ));
+        };
+
+        debug!("Writing {} records", records.len());
+        for record in records {
+            self.format.write_record(writer, record)?;
+        }
+        // Lines on the standard output are read as they come, e.g. by jq
+        if self.output == RecordOutput::Stdout {
+            writer.flush()?;
+        }
+
+        self.records += records.len() as u64;
+        if self.output.is_full(writer.written(), self.records) {
+            self.close()?;
+            self.open()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.output.rotates() {
+            true => {
+                self.close()?;
+                self.open()
+            }
+            false => self.flush(),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+            drop(writer);
+            info!("Export {} Finished", self.name());
+            if let Some(path) = self.path.as_deref() {
+                self.output.finish(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_support::tcp_flow;
+
+    #[test]
+    fn test_selected_fields_are_renamed_and_formatted() {
+        let format = JsonlFormat::new(
+            Some("source=src, dst_port,prot=proto,first"),
+            TimestampFormat::Rfc3339,
+            ProtocolFormat::Name,
+        )
+        .unwrap();
+        let record = tcp_flow([10, 0, 0, 1], [192, 168, 0, 1], 1_700_000_000_000_001);
+
+        let mut line = Vec::new();
+        format.write_record(&mut line, &record).unwrap();
+        assert_eq!(
+            String::from_utf8(line).unwrap(),
+            "{\"src\":\"10.0.0.1\",\"dst_port\":443,\"proto\":\"tcp\",\
+             \"first\":\"2023-11-14T22:13:20.000001Z\"}\n"
+        );
+
+        assert!(
+            JsonlFormat::new(
+                Some("source,bytes"),
+                TimestampFormat::Micros,
+                ProtocolFormat::Number
+            )
+            .is_err()
+        );
+    }
+}
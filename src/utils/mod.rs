@@ -1,7 +1,42 @@
+mod csv_rows_exporter;
+mod flow_exporter;
 mod fluere_csv_exporter;
+mod ipfix_decoder;
+mod ipfix_exporter;
+mod jsonl_exporter;
+mod netflow_decoder;
+mod netflow_v5_exporter;
+mod netflow_v9_exporter;
 mod nettool;
+#[cfg(feature = "parquet")]
+mod parquet_exporter;
+mod record_fields;
+mod record_output;
+mod sflow_decoder;
+#[cfg(feature = "sqlite")]
+mod sqlite_exporter;
+mod stream_exporter;
+#[cfg(test)]
+mod test_support;
 mod time_file_get;
 
-pub use fluere_csv_exporter::fluere_exporter;
+pub use csv_rows_exporter::CsvRowsExporter;
+pub use flow_exporter::{Exporters, FlowExporter, csv_rows_output, format_exporter};
+pub use fluere_csv_exporter::{CsvExporter, CsvFormat};
+pub use ipfix_decoder::{CollectedRecord, EnterpriseElement, IpfixDecoder};
+pub use ipfix_exporter::{DEFAULT_IPFIX_ENTERPRISE, IpfixExporter};
+pub use jsonl_exporter::{JsonlExporter, JsonlFormat};
+pub use netflow_decoder::NetflowDecoder;
+pub use netflow_v5_exporter::NetflowV5Exporter;
+pub use netflow_v9_exporter::NetflowV9Exporter;
+pub use nettool::connect_udp;
 pub use nettool::get_local_ip;
+#[cfg(feature = "parquet")]
+pub use parquet_exporter::ParquetExporter;
+pub use record_fields::RecordFields;
+pub use record_output::{FileOptions, OutputWriter, RecordOutput};
+pub use sflow_decoder::{InterfaceCounters, PacketSample, SflowDatagram};
+#[cfg(feature = "sqlite")]
+pub use sqlite_exporter::SqliteExporter;
+pub use stream_exporter::StreamExporter;
 pub use time_file_get::cur_time_file;
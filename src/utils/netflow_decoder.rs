@@ -0,0 +1,401 @@
+// This file contains the NetFlow v5 and v9 (RFC 3954) decoders of the collector mode.
+// v9 templates are cached per exporter address and source ID, data FlowSets arriving before
+// their template are dropped until the exporter refreshes it. Flow times are rebuilt from the
+// export time and uptime in the datagram header, like the exporters write them.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use super::netflow_v9_exporter::{
+    DOT1Q_CUSTOMER_VLAN_ID, FIRST_SWITCHED, FLOW_END_REASON, IN_BYTES, IN_PKTS, IPV4_DST_ADDR,
+    IPV4_SRC_ADDR, IPV6_DST_ADDR, IPV6_FLOW_LABEL, IPV6_SRC_ADDR, L4_DST_PORT, L4_SRC_PORT,
+    LAST_SWITCHED, MAX_PKT_LNGTH, MAX_TTL, MIN_PKT_LNGTH, MIN_TTL, PROTOCOL, SRC_TOS, SRC_VLAN,
+    TCP_ACK_TOTAL_COUNT, TCP_FIN_TOTAL_COUNT, TCP_FLAGS, TCP_PSH_TOTAL_COUNT, TCP_RST_TOTAL_COUNT,
+    TCP_SYN_TOTAL_COUNT, TCP_URG_TOTAL_COUNT,
+};
+use crate::FluereError;
+
+use fluereflow::{FlowEndReason, FluereRecord};
+use log::{debug, trace};
+
+const V5_HEADER_LENGTH: usize = 24;
+const V5_RECORD_LENGTH: usize = 48;
+const V9_HEADER_LENGTH: usize = 20;
+const FLOWSET_HEADER_LENGTH: usize = 4;
+const TEMPLATE_FLOWSET_ID: u16 = 0;
+// FlowSet IDs below this one are templates or reserved
+const MIN_DATA_FLOWSET_ID: u16 = 256;
+
+/// Field types and lengths of a template, in record order.
+pub(super) type TemplateFields = Vec<(u16, u16)>;
+
+/// Decodes NetFlow v5 and v9 datagrams into flow records.
+#[derive(Debug, Default)]
+pub struct NetflowDecoder {
+    // v9 templates by exporter address, source ID and template ID
+    templates: HashMap<(IpAddr, u32, u16), TemplateFields>,
+    // Data FlowSets dropped because their template was not known yet
+    missing_templates: u64,
+}
+
+impl NetflowDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a datagram sent by the exporter, by its version.
+    pub fn decode(
+        &mut self,
+        exporter: IpAddr,
+        datagram: &[u8],
+    ) -> Result<Vec<FluereRecord>, FluereError> {
+        let mut records = match read_uint(slice(datagram, 0, 2)?) {
+            5 => decode_v5(datagram)?,
+            9 => self.decode_v9(exporter, datagram)?,
+            version => {
+                return Err(FluereError::ParseError(format!(
+                    "Unsupported NetFlow version: {}",
+                    version
+                )));
+            }
+        };
+        for record in records.iter_mut() {
+            record.exporter = exporter;
+        }
+
+        Ok(records)
+    }
+
+    /// Data FlowSets dropped so far because their template was not known yet.
+    pub fn missing_templates(&self) -> u64 {
+        self.missing_templates
+    }
+
+    fn decode_v9(
+        &mut self,
+        exporter: IpAddr,
+        datagram: &[u8],
+    ) -> Result<Vec<FluereRecord>, FluereError> {
+        let header = slice(datagram, 0, V9_HEADER_LENGTH)?;
+        let sys_uptime = read_uint(&header[4..8]);
+        let unix_secs = read_uint(&header[8..12]);
+        let source_id = read_uint(&header[16..20]) as u32;
+        let boot_time = (unix_secs * 1_000_000).saturating_sub(sys_uptime * 1_000);
+
+        let mut records = Vec::new();
+        for (id, content) in flowsets(&datagram[V9_HEADER_LENGTH..])? {
+            match id {
+                TEMPLATE_FLOWSET_ID => {
+                    for (template_id, fields) in read_templates(content)? {
+                        trace!("Template {} from {} ({})", template_id, exporter, source_id);
+                        self.templates
+                            .insert((exporter, source_id, template_id), fields);
+                    }
+                }
+                // Options templates and reserved IDs describe no flows
+                id if id < MIN_DATA_FLOWSET_ID => {}
+                id => match self.templates.get(&(exporter, source_id, id)) {
+                    Some(fields) => records.extend(
+                        data_records(content, fields)
+                            .map(|values| v9_record(fields, values, boot_time)),
+                    ),
+                    None => {
+                        debug!("No template {} from {} ({}) yet", id, exporter, source_id);
+                        self.missing_templates += 1;
+                    }
+                },
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+fn decode_v5(datagram: &[u8]) -> Result<Vec<FluereRecord>, FluereError> {
+    let header = slice(datagram, 0, V5_HEADER_LENGTH)?;
+    let count = read_uint(&header[2..4]) as usize;
+    let sys_uptime = read_uint(&header[4..8]);
+    let unix_micros = read_uint(&header[8..12]) * 1_000_000 + read_uint(&header[12..16]) / 1_000;
+    let boot_time = unix_micros.saturating_sub(sys_uptime * 1_000);
+
+    let records = slice(datagram, V5_HEADER_LENGTH, count * V5_RECORD_LENGTH)?;
+    Ok(records
+        .chunks_exact(V5_RECORD_LENGTH)
+        .map(|fields| {
+            let mut record = FluereRecord {
+                source: address(&fields[0..4]),
+                destination: address(&fields[4..8]),
+                d_pkts: read_uint(&fields[16..20]) as u32,
+                d_octets: read_uint(&fields[20..24]) as usize,
+                first: boot_time + read_uint(&fields[24..28]) * 1_000,
+                last: boot_time + read_uint(&fields[28..32]) * 1_000,
+                src_port: read_uint(&fields[32..34]) as u16,
+                dst_port: read_uint(&fields[34..36]) as u16,
+                prot: fields[38],
+                tos: fields[39],
+                ..FluereRecord::default()
+            };
+            apply_tcp_flags(&mut record, fields[37]);
+            split_directions(&mut record);
+            record
+        })
+        .collect())
+}
+
+fn v9_record<'a>(
+    fields: &[(u16, u16)],
+    values: impl Iterator<Item = &'a [u8]>,
+    boot_time: u64,
+) -> FluereRecord {
+    let mut record = FluereRecord::default();
+    for (&(field_type, _), value) in fields.iter().zip(values) {
+        match field_type {
+            FIRST_SWITCHED => record.first = uptime(boot_time, read_uint(value)),
+            LAST_SWITCHED => record.last = uptime(boot_time, read_uint(value)),
+            field_type => apply_field(&mut record, field_type, value),
+        }
+    }
+    split_directions(&mut record);
+    record
+}
+
+/// Sets the record field of a NetFlow v9 or IPFIX field type, ignoring the unknown ones.
+///
+/// IN_* are the totals of both directions. OUT_* count what left the exporter's interface,
+/// which says nothing of the responder share, and are ignored.
+pub(super) fn apply_field(record: &mut FluereRecord, field_type: u16, value: &[u8]) {
+    let number = read_uint(value);
+    match field_type {
+        IPV4_SRC_ADDR | IPV6_SRC_ADDR => record.source = address(value),
+        IPV4_DST_ADDR | IPV6_DST_ADDR => record.destination = address(value),
+        IN_BYTES => record.d_octets = number as usize,
+        IN_PKTS => record.d_pkts = number as u32,
+        PROTOCOL => record.prot = number as u8,
+        SRC_TOS => record.tos = number as u8,
+        TCP_FLAGS => apply_tcp_flags(record, number as u8),
+        L4_SRC_PORT => record.src_port = number as u16,
+        L4_DST_PORT => record.dst_port = number as u16,
+        MIN_PKT_LNGTH => record.min_pkt = number as u32,
+        MAX_PKT_LNGTH => record.max_pkt = number as u32,
+        IPV6_FLOW_LABEL => record.flow_label = number as u32,
+        MIN_TTL => record.min_ttl = number as u8,
+        MAX_TTL => record.max_ttl = number as u8,
+        SRC_VLAN => record.outer_vlan = number as u16,
+        DOT1Q_CUSTOMER_VLAN_ID => record.inner_vlan = number as u16,
+        FLOW_END_REASON => record.end_reason = end_reason(number as u8),
+        TCP_SYN_TOTAL_COUNT => record.syn_cnt = number as u32,
+        TCP_FIN_TOTAL_COUNT => record.fin_cnt = number as u32,
+        TCP_RST_TOTAL_COUNT => record.rst_cnt = number as u32,
+        TCP_PSH_TOTAL_COUNT => record.psh_cnt = number as u32,
+        TCP_ACK_TOTAL_COUNT => record.ack_cnt = number as u32,
+        TCP_URG_TOTAL_COUNT => record.urg_cnt = number as u32,
+        _ => {}
+    }
+}
+
+/// Derives the initiator share of the totals, once the responder share is known.
+pub(super) fn split_directions(record: &mut FluereRecord) {
+    record.out_bytes = record.d_octets.saturating_sub(record.in_bytes);
+    record.out_pkts = record.d_pkts.saturating_sub(record.in_pkts);
+}
+
+// The cumulative OR of the flags only tells a flag was seen, count it once unless the total
+// count is known from its own field
+fn apply_tcp_flags(record: &mut FluereRecord, flags: u8) {
+    let counts = [
+        &mut record.fin_cnt,
+        &mut record.syn_cnt,
+        &mut record.rst_cnt,
+        &mut record.psh_cnt,
+        &mut record.ack_cnt,
+        &mut record.urg_cnt,
+        &mut record.ece_cnt,
+        &mut record.cwr_cnt,
+    ];
+    for (bit, count) in counts.into_iter().enumerate() {
+        if flags & 1 << bit != 0 {
+            *count = (*count).max(1);
+        }
+    }
+}
+
+/// Flow end reason of an IPFIX flowEndReason code.
+pub(super) fn end_reason(code: u8) -> FlowEndReason {
+    match code {
+        1 => FlowEndReason::IdleTimeout,
+        2 => FlowEndReason::ActiveTimeout,
+        3 => FlowEndReason::TcpFin,
+        4 => FlowEndReason::ForcedEnd,
+        5 => FlowEndReason::LackOfResources,
+        _ => FlowEndReason::Unknown,
+    }
+}
+
+/// Splits the FlowSets, or IPFIX Sets, into their ID and content.
+pub(super) fn flowsets(mut datagram: &[u8]) -> Result<Vec<(u16, &[u8])>, FluereError> {
+    let mut flowsets = Vec::new();
+    while datagram.len() >= FLOWSET_HEADER_LENGTH {
+        let id = read_uint(&datagram[0..2]) as u16;
+        let length = read_uint(&datagram[2..4]) as usize;
+        if length < FLOWSET_HEADER_LENGTH {
+            return Err(FluereError::ParseError(format!(
+                "Invalid FlowSet length: {}",
+                length
+            )));
+        }
+        flowsets.push((
+            id,
+            slice(
+                datagram,
+                FLOWSET_HEADER_LENGTH,
+                length - FLOWSET_HEADER_LENGTH,
+            )?,
+        ));
+        datagram = &datagram[length..];
+    }
+    Ok(flowsets)
+}
+
+// Templates of a template FlowSet, up to its padding
+fn read_templates(mut content: &[u8]) -> Result<Vec<(u16, TemplateFields)>, FluereError> {
+    let mut templates = Vec::new();
+    while content.len() >= 4 {
+        let id = read_uint(&content[0..2]) as u16;
+        let count = read_uint(&content[2..4]) as usize;
+        let fields = slice(content, 4, count * 4)?
+            .chunks_exact(4)
+            .map(|field| {
+                (
+                    read_uint(&field[0..2]) as u16,
+                    read_uint(&field[2..4]) as u16,
+                )
+            })
+            .collect();
+        templates.push((id, fields));
+        content = &content[4 + count * 4..];
+    }
+    Ok(templates)
+}
+
+/// Field values of the records of a data FlowSet, up to its padding.
+pub(super) fn data_records<'a>(
+    content: &'a [u8],
+    fields: &'a [(u16, u16)],
+) -> impl Iterator<Item = impl Iterator<Item = &'a [u8]>> {
+    let length = fields
+        .iter()
+        .map(|&(_, length)| length as usize)
+        .sum::<usize>();
+    // Templates without fields describe no records
+    let content = if length == 0 { &[][..] } else { content };
+    content.chunks_exact(length.max(1)).map(move |record| {
+        fields.iter().scan(0, move |offset, &(_, length)| {
+            let value = &record[*offset..*offset + length as usize];
+            *offset += length as usize;
+            Some(value)
+        })
+    })
+}
+
+/// Bytes `offset..offset + length` of the datagram, or an error if it is too short.
+pub(super) fn slice(datagram: &[u8], offset: usize, length: usize) -> Result<&[u8], FluereError> {
+    datagram.get(offset..offset + length).ok_or_else(|| {
+        FluereError::ParseError(format!(
+            "Truncated datagram: {} bytes, {} expected",
+            datagram.len(),
+            offset + length
+        ))
+    })
+}
+
+/// Network byte order integer of up to 8 bytes.
+pub(super) fn read_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| value << 8 | *byte as u64)
+}
+
+/// Time, in microseconds, of an uptime in milliseconds since the boot time, clamped when the
+/// exporter sends one out of range.
+pub(super) fn uptime(boot_time: u64, milliseconds: u64) -> u64 {
+    boot_time.saturating_add(milliseconds.saturating_mul(1_000))
+}
+
+/// IPv4 or IPv6 address, by the length of the field.
+pub(super) fn address(bytes: &[u8]) -> IpAddr {
+    match bytes.len() {
+        16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(bytes);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => {
+            let mut octets = [0; 4];
+            let length = bytes.len().min(4);
+            octets[..length].copy_from_slice(&bytes[..length]);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::UdpSocket;
+
+    use crate::utils::{FlowExporter, NetflowV9Exporter, test_support::tcp_flow};
+
+    #[test]
+    fn test_v9_records_are_decoded_with_cached_templates() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut exporter = NetflowV9Exporter::new(&address);
+        exporter.open().unwrap();
+
+        let start = 1_700_000_000_000_000;
+        let flow = FluereRecord {
+            flow_label: 0x12345,
+            end_reason: FlowEndReason::IdleTimeout,
+            ..tcp_flow(
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                start,
+            )
+        };
+        exporter.export(&[flow]).unwrap();
+        exporter.export(&[flow]).unwrap();
+
+        let mut decoder = NetflowDecoder::new();
+        let mut buffer = [0u8; 2048];
+        let exporter_address = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let length = collector.recv(&mut buffer).unwrap();
+        let records = decoder.decode(exporter_address, &buffer[..length]).unwrap();
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record.source, flow.source);
+        assert_eq!(record.destination, flow.destination);
+        assert_eq!((record.first, record.last), (flow.first, flow.last));
+        assert_eq!(
+            (record.src_port, record.dst_port, record.prot),
+            (40000, 443, 6)
+        );
+        assert_eq!((record.d_pkts, record.d_octets), (3, 180));
+        // NetFlow v9 carries no direction split, the totals are left to the initiator
+        assert_eq!((record.in_pkts, record.out_pkts), (0, 3));
+        assert_eq!((record.syn_cnt, record.ack_cnt), (1, 2));
+        assert_eq!(record.flow_label, 0x12345);
+        assert_eq!(record.end_reason, FlowEndReason::IdleTimeout);
+        assert_eq!(record.exporter, exporter_address);
+
+        // The second datagram carries no template, only another exporter lacks it
+        let length = collector.recv(&mut buffer).unwrap();
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        assert!(decoder.decode(other, &buffer[..length]).unwrap().is_empty());
+        assert_eq!(decoder.missing_templates(), 1);
+        let records = decoder.decode(exporter_address, &buffer[..length]).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+}
@@ -0,0 +1,237 @@
+// This file contains the NetFlow v5 exporter.
+// Records are packed into NetFlow v5 datagrams of at most 30 flows and sent over UDP to a
+// collector. The exporter's clock is the packet time of the records, so offline conversions
+// produce the same timestamps a router would have sent at the time of the capture.
+
+use std::net::UdpSocket;
+use std::{io, net::IpAddr};
+
+use super::{FlowExporter, connect_udp};
+
+use fluereflow::{FluereHeader, FluereRecord};
+use log::{debug, trace};
+
+const VERSION: u16 = 5;
+// A NetFlow v5 datagram carries at most 30 flow records
+const MAX_RECORDS: usize = 30;
+const HEADER_LENGTH: usize = 24;
+const RECORD_LENGTH: usize = 48;
+
+/// Sends flow records to a NetFlow v5 collector.
+///
+/// NetFlow v5 only carries IPv4 flows, IPv6 flows are skipped.
+#[derive(Debug)]
+pub struct NetflowV5Exporter {
+    collector: String,
+    socket: Option<UdpSocket>,
+    // Flows sent so far, the sequence number of the next datagram
+    sequence: u32,
+    // Packet time the exporter's uptime counts from, in microseconds
+    boot_time: Option<u64>,
+}
+
+impl NetflowV5Exporter {
+    /// Exporter to the collector, given as `host:port`. The socket is opened by
+    /// [`FlowExporter::open`].
+    pub fn new(collector: &str) -> Self {
+        Self {
+            collector: collector.to_string(),
+            socket: None,
+            sequence: 0,
+            boot_time: None,
+        }
+    }
+
+    /// Sends the IPv4 records, 30 to a datagram, and returns how many were sent.
+    pub fn export(&mut self, records: &[FluereRecord]) -> io::Result<usize> {
+        if self.socket.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "NetFlow v5 socket is not open",
+            ));
+        }
+        let flows: Vec<&FluereRecord> = records
+            .iter()
+            .filter(|record| record.source.is_ipv4() && record.destination.is_ipv4())
+            .collect();
+        if flows.len() < records.len() {
+            trace!(
+                "Skipping {} IPv6 flows, NetFlow v5 only carries IPv4",
+                records.len() - flows.len()
+            );
+        }
+
+        for chunk in flows.chunks(MAX_RECORDS) {
+            let datagram = self.encode(chunk);
+            if let Some(socket) = self.socket.as_ref() {
+                socket.send(&datagram)?;
+            }
+        }
+
+        Ok(flows.len())
+    }
+
+    fn encode(&mut self, records: &[&FluereRecord]) -> Vec<u8> {
+        let now = records.iter().map(|record| record.last).max().unwrap_or(0);
+        let boot_time = *self.boot_time.get_or_insert_with(|| {
+            records
+                .iter()
+                .map(|record| record.first)
+                .min()
+                .unwrap_or(now)
+        });
+        let header = FluereHeader::new(
+            VERSION,
+            records.len() as u16,
+            uptime(now, boot_time),
+            (now / 1_000_000) as u32,
+        );
+
+        let mut datagram = Vec::with_capacity(HEADER_LENGTH + records.len() * RECORD_LENGTH);
+        write_header(&mut datagram, &header, now, self.sequence);
+        for record in records {
+            write_record(&mut datagram, record, boot_time);
+        }
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+
+        datagram
+    }
+}
+
+impl FlowExporter for NetflowV5Exporter {
+    fn name(&self) -> String {
+        format!("netflow v5 {}", self.collector)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        self.socket = Some(connect_udp(&self.collector)?);
+        debug!("Exporting NetFlow v5 to {}", self.collector);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        self.export(records).map(|_| ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.socket = None;
+        Ok(())
+    }
+}
+
+fn write_header(datagram: &mut Vec<u8>, header: &FluereHeader, now: u64, sequence: u32) {
+    let unix_nsecs = (now % 1_000_000) as u32 * 1_000;
+
+    datagram.extend_from_slice(&header.version.to_be_bytes());
+    datagram.extend_from_slice(&header.count.to_be_bytes());
+    datagram.extend_from_slice(&header.sys_uptime.to_be_bytes());
+    datagram.extend_from_slice(&header.unix_secs.to_be_bytes());
+    datagram.extend_from_slice(&unix_nsecs.to_be_bytes());
+    datagram.extend_from_slice(&sequence.to_be_bytes());
+    // Engine type, engine ID and sampling interval
+    datagram.extend_from_slice(&[0, 0, 0, 0]);
+}
+
+fn write_record(datagram: &mut Vec<u8>, record: &FluereRecord, boot_time: u64) {
+    datagram.extend_from_slice(&ipv4_octets(record.source));
+    datagram.extend_from_slice(&ipv4_octets(record.destination));
+    // Next hop, input and output interfaces
+    datagram.extend_from_slice(&[0; 8]);
+    datagram.extend_from_slice(&record.d_pkts.to_be_bytes());
+    datagram.extend_from_slice(&saturate(record.d_octets).to_be_bytes());
+    datagram.extend_from_slice(&uptime(record.first, boot_time).to_be_bytes());
+    datagram.extend_from_slice(&uptime(record.last, boot_time).to_be_bytes());
+    datagram.extend_from_slice(&record.src_port.to_be_bytes());
+    datagram.extend_from_slice(&record.dst_port.to_be_bytes());
+    datagram.extend_from_slice(&[0, tcp_flags(record), record.prot, record.tos]);
+    // Source and destination AS, prefix masks and padding
+    datagram.extend_from_slice(&[0; 8]);
+}
+
+fn ipv4_octets(address: IpAddr) -> [u8; 4] {
+    match address {
+        IpAddr::V4(address) => address.octets(),
+        IpAddr::V6(_) => [0; 4],
+    }
+}
+
+// Milliseconds since the boot time, wrapping like a router's uptime does after 49 days
+pub(super) fn uptime(time: u64, boot_time: u64) -> u32 {
+    (time.saturating_sub(boot_time) / 1_000) as u32
+}
+
+fn saturate(value: usize) -> u32 {
+    u32::try_from(value).unwrap_or(u32::MAX)
+}
+
+// Cumulative OR of the TCP flags seen on the flow
+pub(super) fn tcp_flags(record: &FluereRecord) -> u8 {
+    [
+        record.fin_cnt,
+        record.syn_cnt,
+        record.rst_cnt,
+        record.psh_cnt,
+        record.ack_cnt,
+        record.urg_cnt,
+        record.ece_cnt,
+        record.cwr_cnt,
+    ]
+    .iter()
+    .enumerate()
+    .filter(|(_, count)| **count > 0)
+    .fold(0, |flags, (bit, _)| flags | 1 << bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_support::tcp_flow;
+
+    fn record(host: u8, first: u64) -> FluereRecord {
+        FluereRecord {
+            last: first + 2_500_000,
+            tos: 0x10,
+            ..tcp_flow([10, 0, 0, host], [192, 168, 0, 1], first)
+        }
+    }
+
+    #[test]
+    fn test_datagrams_hold_30_records() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut exporter = NetflowV5Exporter::new(&address);
+        exporter.open().unwrap();
+
+        let start = 1_700_000_000_000_000;
+        let records: Vec<FluereRecord> = (0..31).map(|i| record(i, start + i as u64)).collect();
+        assert_eq!(exporter.export(&records).unwrap(), 31);
+
+        let mut buffer = [0u8; 2048];
+        let length = collector.recv(&mut buffer).unwrap();
+        assert_eq!(length, HEADER_LENGTH + 30 * RECORD_LENGTH);
+        assert_eq!(&buffer[0..4], &[0, 5, 0, 30]);
+        assert_eq!(&buffer[16..20], &0u32.to_be_bytes());
+
+        let length = collector.recv(&mut buffer).unwrap();
+        assert_eq!(length, HEADER_LENGTH + RECORD_LENGTH);
+        assert_eq!(&buffer[0..4], &[0, 5, 0, 1]);
+        assert_eq!(&buffer[16..20], &30u32.to_be_bytes());
+        // System uptime is the last packet time of the datagram, 2.5 s after the first flow
+        assert_eq!(&buffer[4..8], &2_500u32.to_be_bytes());
+        assert_eq!(&buffer[8..12], &1_700_000_002u32.to_be_bytes());
+
+        let flow = &buffer[HEADER_LENGTH..HEADER_LENGTH + RECORD_LENGTH];
+        assert_eq!(&flow[0..8], &[10, 0, 0, 30, 192, 168, 0, 1]);
+        assert_eq!(&flow[16..24], &[0, 0, 0, 3, 0, 0, 0, 180]);
+        // First and last, relative to the boot time
+        assert_eq!(&flow[24..32], &[0, 0, 0, 0, 0, 0, 0x09, 0xc4]);
+        assert_eq!(&flow[32..36], &[0x9c, 0x40, 0x01, 0xbb]);
+        // SYN and ACK, TCP, TOS
+        assert_eq!(&flow[37..40], &[0x12, 6, 0x10]);
+    }
+}
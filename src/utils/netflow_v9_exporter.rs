@@ -0,0 +1,415 @@
+// This file contains the NetFlow v9 (RFC 3954) exporter.
+// Records are described by two templates, one for IPv4 and one for IPv6 flows, which are sent
+// in the first datagram and refreshed periodically so that collectors restarting mid-export
+// can decode the data FlowSets again. Like the v5 exporter, the clock is the packet time.
+
+use std::{
+    io,
+    net::{IpAddr, UdpSocket},
+};
+
+use super::{
+    FlowExporter, connect_udp,
+    netflow_v5_exporter::{tcp_flags, uptime},
+};
+
+use fluereflow::{FlowEndReason, FluereRecord};
+use log::debug;
+
+const VERSION: u16 = 9;
+const HEADER_LENGTH: usize = 20;
+const FLOWSET_HEADER_LENGTH: usize = 4;
+const TEMPLATE_FLOWSET_ID: u16 = 0;
+// Keeps datagrams below the usual path MTU
+const MAX_DATAGRAM: usize = 1400;
+
+// Templates are sent again after this many datagrams, or this much packet time in microseconds
+const TEMPLATE_REFRESH_PACKETS: u32 = 20;
+const TEMPLATE_REFRESH_TIME: u64 = 60_000_000;
+
+// Field types of RFC 3954. Types above 127 are IANA IPFIX Information Elements, which share
+// the NetFlow v9 numbering and are decoded by v9 collectors as well.
+pub(super) const IN_BYTES: u16 = 1;
+pub(super) const IN_PKTS: u16 = 2;
+pub(super) const PROTOCOL: u16 = 4;
+pub(super) const SRC_TOS: u16 = 5;
+pub(super) const TCP_FLAGS: u16 = 6;
+pub(super) const L4_SRC_PORT: u16 = 7;
+pub(super) const IPV4_SRC_ADDR: u16 = 8;
+pub(super) const L4_DST_PORT: u16 = 11;
+pub(super) const IPV4_DST_ADDR: u16 = 12;
+pub(super) const LAST_SWITCHED: u16 = 21;
+pub(super) const FIRST_SWITCHED: u16 = 22;
+pub(super) const MIN_PKT_LNGTH: u16 = 25;
+pub(super) const MAX_PKT_LNGTH: u16 = 26;
+pub(super) const IPV6_SRC_ADDR: u16 = 27;
+pub(super) const IPV6_DST_ADDR: u16 = 28;
+pub(super) const IPV6_FLOW_LABEL: u16 = 31;
+pub(super) const MIN_TTL: u16 = 52;
+pub(super) const MAX_TTL: u16 = 53;
+pub(super) const SRC_VLAN: u16 = 58;
+pub(super) const FLOW_END_REASON: u16 = 136;
+pub(super) const TCP_SYN_TOTAL_COUNT: u16 = 218;
+pub(super) const TCP_FIN_TOTAL_COUNT: u16 = 219;
+pub(super) const TCP_RST_TOTAL_COUNT: u16 = 220;
+pub(super) const TCP_PSH_TOTAL_COUNT: u16 = 221;
+pub(super) const TCP_ACK_TOTAL_COUNT: u16 = 222;
+pub(super) const TCP_URG_TOTAL_COUNT: u16 = 223;
+pub(super) const DOT1Q_CUSTOMER_VLAN_ID: u16 = 245;
+
+// Fields following the family specific fields in both templates, as (field type, length).
+// IN_* carry the totals of both directions. OUT_* count what leaves the observing interface,
+// which is not the responder share, so the direction split and the ECE, CWR and NS counters,
+// which have no standard field type, are left out.
+const COMMON_FIELDS: [(u16, u16); 21] = [
+    (L4_SRC_PORT, 2),
+    (L4_DST_PORT, 2),
+    (PROTOCOL, 1),
+    (SRC_TOS, 1),
+    (IN_BYTES, 8),
+    (IN_PKTS, 4),
+    (FIRST_SWITCHED, 4),
+    (LAST_SWITCHED, 4),
+    (TCP_FLAGS, 1),
+    (MIN_PKT_LNGTH, 2),
+    (MAX_PKT_LNGTH, 2),
+    (MIN_TTL, 1),
+    (MAX_TTL, 1),
+    (SRC_VLAN, 2),
+    (DOT1Q_CUSTOMER_VLAN_ID, 2),
+    (TCP_FIN_TOTAL_COUNT, 4),
+    (TCP_SYN_TOTAL_COUNT, 4),
+    (TCP_RST_TOTAL_COUNT, 4),
+    (TCP_PSH_TOTAL_COUNT, 4),
+    (TCP_ACK_TOTAL_COUNT, 4),
+    (TCP_URG_TOTAL_COUNT, 4),
+];
+
+struct Template {
+    id: u16,
+    family_fields: &'static [(u16, u16)],
+}
+
+const IPV4_TEMPLATE: Template = Template {
+    id: 256,
+    family_fields: &[(IPV4_SRC_ADDR, 4), (IPV4_DST_ADDR, 4), (FLOW_END_REASON, 1)],
+};
+const IPV6_TEMPLATE: Template = Template {
+    id: 257,
+    family_fields: &[
+        (IPV6_SRC_ADDR, 16),
+        (IPV6_DST_ADDR, 16),
+        (IPV6_FLOW_LABEL, 3),
+        (FLOW_END_REASON, 1),
+    ],
+};
+
+impl Template {
+    fn fields(&self) -> impl Iterator<Item = &(u16, u16)> {
+        self.family_fields.iter().chain(COMMON_FIELDS.iter())
+    }
+
+    fn record_length(&self) -> usize {
+        self.fields().map(|&(_, length)| length as usize).sum()
+    }
+
+    fn write(&self, datagram: &mut Vec<u8>) {
+        datagram.extend_from_slice(&self.id.to_be_bytes());
+        datagram.extend_from_slice(&(self.fields().count() as u16).to_be_bytes());
+        for (field_type, length) in self.fields() {
+            datagram.extend_from_slice(&field_type.to_be_bytes());
+            datagram.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+}
+
+/// Sends flow records to a NetFlow v9 collector, IPv4 and IPv6 alike.
+#[derive(Debug)]
+pub struct NetflowV9Exporter {
+    collector: String,
+    socket: Option<UdpSocket>,
+    // Datagrams sent so far
+    sequence: u32,
+    // Packet time the exporter's uptime counts from, in microseconds
+    boot_time: Option<u64>,
+    // Datagrams sent and packet time since the templates were last sent
+    packets_since_templates: u32,
+    last_templates: Option<u64>,
+}
+
+impl NetflowV9Exporter {
+    /// Exporter to the collector, given as `host:port`. The socket is opened by
+    /// [`FlowExporter::open`].
+    pub fn new(collector: &str) -> Self {
+        Self {
+            collector: collector.to_string(),
+            socket: None,
+            sequence: 0,
+            boot_time: None,
+            packets_since_templates: 0,
+            last_templates: None,
+        }
+    }
+
+    /// Sends the records and returns how many were sent.
+    pub fn export(&mut self, records: &[FluereRecord]) -> io::Result<usize> {
+        if self.socket.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "NetFlow v9 socket is not open",
+            ));
+        }
+        let (ipv4, ipv6): (Vec<&FluereRecord>, Vec<&FluereRecord>) =
+            records.iter().partition(|record| record.source.is_ipv4());
+
+        for (template, flows) in [(&IPV4_TEMPLATE, ipv4), (&IPV6_TEMPLATE, ipv6)] {
+            for chunk in flows.chunks(records_per_datagram(template)) {
+                let datagram = self.encode(template, chunk);
+                if let Some(socket) = self.socket.as_ref() {
+                    socket.send(&datagram)?;
+                }
+            }
+        }
+
+        Ok(records.len())
+    }
+
+    fn encode(&mut self, template: &Template, records: &[&FluereRecord]) -> Vec<u8> {
+        let now = records.iter().map(|record| record.last).max().unwrap_or(0);
+        let boot_time = *self.boot_time.get_or_insert_with(|| {
+            records
+                .iter()
+                .map(|record| record.first)
+                .min()
+                .unwrap_or(now)
+        });
+        let with_templates = self.templates_due(now);
+        let count = records.len() + if with_templates { 2 } else { 0 };
+
+        let mut datagram = Vec::with_capacity(MAX_DATAGRAM);
+        datagram.extend_from_slice(&VERSION.to_be_bytes());
+        datagram.extend_from_slice(&(count as u16).to_be_bytes());
+        datagram.extend_from_slice(&uptime(now, boot_time).to_be_bytes());
+        datagram.extend_from_slice(&((now / 1_000_000) as u32).to_be_bytes());
+        datagram.extend_from_slice(&self.sequence.to_be_bytes());
+        // Source ID
+        datagram.extend_from_slice(&0u32.to_be_bytes());
+
+        if with_templates {
+            write_flowset(&mut datagram, TEMPLATE_FLOWSET_ID, |datagram| {
+                IPV4_TEMPLATE.write(datagram);
+                IPV6_TEMPLATE.write(datagram);
+            });
+        }
+        write_flowset(&mut datagram, template.id, |datagram| {
+            for record in records {
+                for &(field_type, length) in template.fields() {
+                    write_field(datagram, field_type, length, record, boot_time);
+                }
+            }
+        });
+        self.sequence = self.sequence.wrapping_add(1);
+
+        datagram
+    }
+
+    fn templates_due(&mut self, now: u64) -> bool {
+        let due = match self.last_templates {
+            None => true,
+            Some(last) => {
+                self.packets_since_templates >= TEMPLATE_REFRESH_PACKETS
+                    || now >= last + TEMPLATE_REFRESH_TIME
+            }
+        };
+        if due {
+            self.last_templates = Some(now);
+            self.packets_since_templates = 0;
+        }
+        self.packets_since_templates += 1;
+
+        due
+    }
+}
+
+impl FlowExporter for NetflowV9Exporter {
+    fn name(&self) -> String {
+        format!("netflow v9 {}", self.collector)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        self.socket = Some(connect_udp(&self.collector)?);
+        debug!("Exporting NetFlow v9 to {}", self.collector);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        self.export(records).map(|_| ())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.socket = None;
+        Ok(())
+    }
+}
+
+// Records of the template that fit in a datagram next to the template FlowSet
+fn records_per_datagram(template: &Template) -> usize {
+    let templates = FLOWSET_HEADER_LENGTH
+        + [&IPV4_TEMPLATE, &IPV6_TEMPLATE]
+            .iter()
+            .map(|template| 4 + 4 * template.fields().count())
+            .sum::<usize>();
+    let available = MAX_DATAGRAM - HEADER_LENGTH - templates - FLOWSET_HEADER_LENGTH - 3;
+
+    available / template.record_length()
+}
+
+// Writes a FlowSet header, the content and the padding to a 4 byte boundary
+fn write_flowset(datagram: &mut Vec<u8>, id: u16, content: impl FnOnce(&mut Vec<u8>)) {
+    let start = datagram.len();
+    datagram.extend_from_slice(&id.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    content(datagram);
+    datagram.resize(datagram.len().next_multiple_of(4), 0);
+
+    let length = (datagram.len() - start) as u16;
+    datagram[start + 2..start + 4].copy_from_slice(&length.to_be_bytes());
+}
+
+fn write_field(
+    datagram: &mut Vec<u8>,
+    field_type: u16,
+    length: u16,
+    record: &FluereRecord,
+    boot_time: u64,
+) {
+    match field_type {
+        IPV4_SRC_ADDR | IPV6_SRC_ADDR => write_address(datagram, record.source),
+        IPV4_DST_ADDR | IPV6_DST_ADDR => write_address(datagram, record.destination),
+        FIRST_SWITCHED => write_uint(datagram, uptime(record.first, boot_time) as u64, length),
+        LAST_SWITCHED => write_uint(datagram, uptime(record.last, boot_time) as u64, length),
+        _ => write_uint(datagram, field_value(field_type, record), length),
+    }
+}
+
+/// Value of a numeric field of the record, by NetFlow v9 or IPFIX field type.
+pub(super) fn field_value(field_type: u16, record: &FluereRecord) -> u64 {
+    match field_type {
+        IN_BYTES => record.d_octets as u64,
+        IN_PKTS => record.d_pkts as u64,
+        PROTOCOL => record.prot as u64,
+        SRC_TOS => record.tos as u64,
+        TCP_FLAGS => tcp_flags(record) as u64,
+        L4_SRC_PORT => record.src_port as u64,
+        L4_DST_PORT => record.dst_port as u64,
+        MIN_PKT_LNGTH => record.min_pkt as u64,
+        MAX_PKT_LNGTH => record.max_pkt as u64,
+        IPV6_FLOW_LABEL => record.flow_label as u64,
+        MIN_TTL => record.min_ttl as u64,
+        MAX_TTL => record.max_ttl as u64,
+        SRC_VLAN => record.outer_vlan as u64,
+        DOT1Q_CUSTOMER_VLAN_ID => record.inner_vlan as u64,
+        FLOW_END_REASON => end_reason_code(record.end_reason) as u64,
+        TCP_SYN_TOTAL_COUNT => record.syn_cnt as u64,
+        TCP_FIN_TOTAL_COUNT => record.fin_cnt as u64,
+        TCP_RST_TOTAL_COUNT => record.rst_cnt as u64,
+        TCP_PSH_TOTAL_COUNT => record.psh_cnt as u64,
+        TCP_ACK_TOTAL_COUNT => record.ack_cnt as u64,
+        TCP_URG_TOTAL_COUNT => record.urg_cnt as u64,
+        _ => 0,
+    }
+}
+
+/// flowEndReason code of the IPFIX Information Element registry.
+pub(super) fn end_reason_code(reason: FlowEndReason) -> u8 {
+    match reason {
+        FlowEndReason::Unknown => 0,
+        FlowEndReason::IdleTimeout => 1,
+        FlowEndReason::ActiveTimeout => 2,
+        FlowEndReason::TcpFin | FlowEndReason::TcpReset => 3,
+        FlowEndReason::ForcedEnd => 4,
+        FlowEndReason::LackOfResources => 5,
+    }
+}
+
+/// Writes the address as 4 or 16 bytes, by its family.
+pub(super) fn write_address(datagram: &mut Vec<u8>, address: IpAddr) {
+    match address {
+        IpAddr::V4(address) => datagram.extend_from_slice(&address.octets()),
+        IpAddr::V6(address) => datagram.extend_from_slice(&address.octets()),
+    }
+}
+
+/// Writes the `length` low order bytes of the value in network byte order.
+pub(super) fn write_uint(datagram: &mut Vec<u8>, value: u64, length: u16) {
+    let bytes = value.to_be_bytes();
+    datagram.extend_from_slice(&bytes[bytes.len() - length as usize..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv6Addr;
+
+    use crate::utils::test_support::tcp_flow;
+
+    fn ipv6_record(first: u64) -> FluereRecord {
+        FluereRecord {
+            flow_label: 0x12345,
+            end_reason: FlowEndReason::IdleTimeout,
+            ..tcp_flow(
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1),
+                Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                first,
+            )
+        }
+    }
+
+    #[test]
+    fn test_templates_precede_ipv6_data() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = collector.local_addr().unwrap().to_string();
+        let mut exporter = NetflowV9Exporter::new(&address);
+        exporter.open().unwrap();
+
+        let start = 1_700_000_000_000_000;
+        exporter.export(&[ipv6_record(start)]).unwrap();
+        exporter.export(&[ipv6_record(start + 1)]).unwrap();
+
+        let mut buffer = [0u8; 2048];
+        let length = collector.recv(&mut buffer).unwrap();
+        // Version 9, two templates and one data record, sequence 0
+        assert_eq!(&buffer[0..4], &[0, 9, 0, 3]);
+        assert_eq!(&buffer[12..16], &0u32.to_be_bytes());
+
+        let templates = &buffer[HEADER_LENGTH..];
+        assert_eq!(&templates[0..2], &TEMPLATE_FLOWSET_ID.to_be_bytes());
+        let templates_length = u16::from_be_bytes([templates[2], templates[3]]) as usize;
+        assert_eq!(&templates[4..6], &256u16.to_be_bytes());
+
+        let data = &buffer[HEADER_LENGTH + templates_length..length];
+        assert_eq!(&data[0..2], &257u16.to_be_bytes());
+        assert_eq!(data.len() % 4, 0);
+        let record = &data[FLOWSET_HEADER_LENGTH..];
+        assert_eq!(&record[0..2], &[0x20, 0x01]);
+        assert_eq!(&record[16..18], &[0x20, 0x01]);
+        // Flow label, end reason, ports and protocol
+        assert_eq!(&record[32..36], &[0x01, 0x23, 0x45, 1]);
+        assert_eq!(&record[36..41], &[0x9c, 0x40, 0x01, 0xbb, 6]);
+        // Totals of both directions, followed by the first switched uptime
+        assert_eq!(&record[42..54], &[0, 0, 0, 0, 0, 0, 0, 180, 0, 0, 0, 3]);
+        assert_eq!(&record[54..58], &0u32.to_be_bytes());
+        let data_length = data.len();
+
+        // The templates are not repeated in the next datagram
+        let length = collector.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[0..4], &[0, 9, 0, 1]);
+        assert_eq!(&buffer[12..16], &1u32.to_be_bytes());
+        assert_eq!(length, HEADER_LENGTH + data_length);
+    }
+}
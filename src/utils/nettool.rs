@@ -1,3 +1,8 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
 use pnet::datalink;
 
 pub fn get_local_ip() {
@@ -10,3 +15,20 @@ pub fn get_local_ip() {
 // pub fn is_in_subnet() -> bool {
 
 // }
+
+/// Opens a UDP socket connected to a collector, given as `host:port`.
+pub fn connect_udp(collector: &str) -> io::Result<UdpSocket> {
+    let address = collector.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No address found for collector {}", collector),
+        )
+    })?;
+    let socket = match address {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0")?,
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0")?,
+    };
+    socket.connect(address)?;
+
+    Ok(socket)
+}
@@ -0,0 +1,318 @@
+// This file contains the Parquet exporter, which writes the flow records of every rotation
+// interval to a zstd compressed Parquet file, one typed column per record field.
+// Columns are named after the CSV header, so that notebooks can read both exports alike.
+
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+    sync::Arc,
+};
+
+use super::{FlowExporter, OutputWriter, RecordOutput};
+use crate::types::AddressFormat;
+
+use arrow_array::{
+    ArrayRef, BinaryArray, RecordBatch, StringArray, TimestampMicrosecondArray, UInt8Array,
+    UInt16Array, UInt32Array, UInt64Array,
+};
+use arrow_schema::{Field, Schema, SchemaRef};
+use fluereflow::FluereRecord;
+use log::{debug, info};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+
+/// Writes flow records to Parquet files.
+pub struct ParquetExporter {
+    output: RecordOutput,
+    addresses: AddressFormat,
+    schema: SchemaRef,
+    path: Option<String>,
+    writer: Option<ArrowWriter<OutputWriter>>,
+    // Files opened so far, and records written to the current one
+    files: u32,
+    records: u64,
+}
+
+impl ParquetExporter {
+    /// Exporter storing IP addresses as text or as their 4 or 16 bytes.
+    pub fn new(output: RecordOutput, addresses: AddressFormat) -> Self {
+        // The schema follows the columns, derived from an empty batch
+        let fields: Vec<Field> = columns(&[], addresses)
+            .into_iter()
+            .map(|(name, column)| Field::new(name, column.data_type().clone(), false))
+            .collect();
+
+        Self {
+            output,
+            addresses,
+            schema: Arc::new(Schema::new(fields)),
+            path: None,
+            writer: None,
+            files: 0,
+            records: 0,
+        }
+    }
+}
+
+impl FlowExporter for ParquetExporter {
+    fn name(&self) -> String {
+        self.path.clone().unwrap_or_else(|| self.output.name())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let (path, output) = self.output.open(self.files)?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let writer = ArrowWriter::try_new(output, self.schema.clone(), Some(properties))
+            .map_err(io::Error::other)?;
+
+        info!("Export {} Started", path);
+        self.path = Some(path);
+        self.writer = Some(writer);
+        self.files += 1;
+        self.records = 0;
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "Parquet file is not open",
+            ));
+        };
+
+        debug!("Writing {} records", records.len());
+        let columns = columns(records, self.addresses)
+            .into_iter()
+            .map(|(_, column)| column)
+            .collect();
+        let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(io::Error::other)?;
+        // Rows are buffered into row groups by the writer
+        writer.write(&batch).map_err(io::Error::other)?;
+
+        self.records += records.len() as u64;
+        let bytes = writer.bytes_written() + writer.in_progress_size();
+        if self.output.is_full(bytes as u64, self.records) {
+            self.close()?;
+            self.open()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        match self.output.rotates() {
+            true => {
+                self.close()?;
+                self.open()
+            }
+            false => self.flush(),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush().map_err(io::Error::other),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            // Writes the footer, without which the file cannot be read
+            writer.into_inner().map_err(io::Error::other)?.flush()?;
+            info!("Export {} Finished", self.name());
+            if let Some(path) = self.path.as_deref() {
+                self.output.finish(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn columns(records: &[FluereRecord], addresses: AddressFormat) -> Vec<(&'static str, ArrayRef)> {
+    vec![
+        ("source", address_column(records, addresses, |r| r.source)),
+        (
+            "destination",
+            address_column(records, addresses, |r| r.destination),
+        ),
+        ("src_port", u16_column(records, |r| r.src_port)),
+        ("dst_port", u16_column(records, |r| r.dst_port)),
+        ("prot", u8_column(records, |r| r.prot)),
+        ("d_pkts", u32_column(records, |r| r.d_pkts)),
+        ("d_octets", u64_column(records, |r| r.d_octets as u64)),
+        ("in_pkts", u32_column(records, |r| r.in_pkts)),
+        ("out_pkts", u32_column(records, |r| r.out_pkts)),
+        ("in_bytes", u64_column(records, |r| r.in_bytes as u64)),
+        ("out_bytes", u64_column(records, |r| r.out_bytes as u64)),
+        ("first", timestamp_column(records, |r| r.first)),
+        ("last", timestamp_column(records, |r| r.last)),
+        ("min_pkt", u32_column(records, |r| r.min_pkt)),
+        ("max_pkt", u32_column(records, |r| r.max_pkt)),
+        ("min_ttl", u8_column(records, |r| r.min_ttl)),
+        ("max_ttl", u8_column(records, |r| r.max_ttl)),
+        ("fin_cnt", u32_column(records, |r| r.fin_cnt)),
+        ("syn_cnt", u32_column(records, |r| r.syn_cnt)),
+        ("rst_cnt", u32_column(records, |r| r.rst_cnt)),
+        ("psh_cnt", u32_column(records, |r| r.psh_cnt)),
+        ("ack_cnt", u32_column(records, |r| r.ack_cnt)),
+        ("urg_cnt", u32_column(records, |r| r.urg_cnt)),
+        ("ece_cnt", u32_column(records, |r| r.ece_cnt)),
+        ("cwr_cnt", u32_column(records, |r| r.cwr_cnt)),
+        ("ns_cnt", u32_column(records, |r| r.ns_cnt)),
+        ("tos", u8_column(records, |r| r.tos)),
+        ("flow_label", u32_column(records, |r| r.flow_label)),
+        ("outer_vlan", u16_column(records, |r| r.outer_vlan)),
+        ("inner_vlan", u16_column(records, |r| r.inner_vlan)),
+        (
+            "tunnel_type",
+            string_column(records, |r| r.tunnel_type.as_str()),
+        ),
+        (
+            "tunnel_src",
+            address_column(records, addresses, |r| r.tunnel_src),
+        ),
+        (
+            "tunnel_dst",
+            address_column(records, addresses, |r| r.tunnel_dst),
+        ),
+        ("tunnel_id", u32_column(records, |r| r.tunnel_id)),
+        (
+            "end_reason",
+            string_column(records, |r| r.end_reason.as_str()),
+        ),
+        (
+            "exporter",
+            address_column(records, addresses, |r| r.exporter),
+        ),
+    ]
+}
+
+fn u8_column(records: &[FluereRecord], value: impl Fn(&FluereRecord) -> u8) -> ArrayRef {
+    Arc::new(UInt8Array::from_iter_values(records.iter().map(value)))
+}
+
+fn u16_column(records: &[FluereRecord], value: impl Fn(&FluereRecord) -> u16) -> ArrayRef {
+    Arc::new(UInt16Array::from_iter_values(records.iter().map(value)))
+}
+
+fn u32_column(records: &[FluereRecord], value: impl Fn(&FluereRecord) -> u32) -> ArrayRef {
+    Arc::new(UInt32Array::from_iter_values(records.iter().map(value)))
+}
+
+fn u64_column(records: &[FluereRecord], value: impl Fn(&FluereRecord) -> u64) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter_values(records.iter().map(value)))
+}
+
+fn string_column(
+    records: &[FluereRecord],
+    value: impl Fn(&FluereRecord) -> &'static str,
+) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(records.iter().map(value)))
+}
+
+// Flow times are microseconds since the epoch, in UTC
+fn timestamp_column(records: &[FluereRecord], value: impl Fn(&FluereRecord) -> u64) -> ArrayRef {
+    let times = records.iter().map(|record| value(record) as i64);
+    Arc::new(TimestampMicrosecondArray::from_iter_values(times).with_timezone("UTC"))
+}
+
+fn address_column(
+    records: &[FluereRecord],
+    addresses: AddressFormat,
+    value: impl Fn(&FluereRecord) -> IpAddr,
+) -> ArrayRef {
+    let values = records.iter().map(value);
+    match addresses {
+        AddressFormat::String => Arc::new(StringArray::from_iter_values(
+            values.map(|address| address.to_string()),
+        )),
+        AddressFormat::Binary => {
+            Arc::new(BinaryArray::from_iter_values(values.map(
+                |address| match address {
+                    IpAddr::V4(address) => address.octets().to_vec(),
+                    IpAddr::V6(address) => address.octets().to_vec(),
+                },
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, net::Ipv6Addr};
+
+    use crate::utils::{FileOptions, test_support::tcp_flow};
+
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_batches_are_readable_as_typed_columns() {
+        let name = format!("fluere-parquet-{}", std::process::id());
+        let directory = std::env::temp_dir().to_string_lossy().to_string();
+        let output = RecordOutput::File {
+            directory: directory.clone(),
+            name: name.clone(),
+            extension: ".parquet",
+            options: FileOptions::default(),
+        };
+        let mut exporter = ParquetExporter::new(output, AddressFormat::Binary);
+
+        let record = FluereRecord {
+            d_octets: 5_000_000_000,
+            ..tcp_flow(
+                Ipv6Addr::LOCALHOST,
+                Ipv6Addr::UNSPECIFIED,
+                1_700_000_000_000_000,
+            )
+        };
+        exporter.open().unwrap();
+        exporter.write_batch(&[record, record]).unwrap();
+        exporter.write_batch(&[record]).unwrap();
+        exporter.close().unwrap();
+
+        let path = format!("{}/{}.parquet", directory, name);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            batches.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+            3
+        );
+        let batch = &batches[0];
+        let source = batch
+            .column_by_name("source")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<BinaryArray>()
+            .unwrap();
+        assert_eq!(source.value(0), Ipv6Addr::LOCALHOST.octets());
+        let octets = batch
+            .column_by_name("d_octets")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap();
+        assert_eq!(octets.value(0), 5_000_000_000);
+        let first = batch
+            .column_by_name("first")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<TimestampMicrosecondArray>()
+            .unwrap();
+        assert_eq!(first.value(0), 1_700_000_000_000_000);
+        assert_eq!(first.timezone(), Some("UTC"));
+    }
+}
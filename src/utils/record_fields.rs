@@ -0,0 +1,165 @@
+// This file contains the fields written by the text exporters: the record columns of the CSV
+// header, along with derived ones, and how their values are rendered.
+// Fields can be chosen and renamed, e.g. `source=src,destination=dst,prot_name,duration`.
+
+use super::fluere_csv_exporter::HEADER;
+use crate::{
+    FluereError,
+    net::types::Protocol,
+    types::{ProtocolFormat, TimestampFormat},
+};
+
+use chrono::{DateTime, Local, SecondsFormat};
+use fluereflow::FluereRecord;
+use serde_json::Value;
+
+/// Fields computed from the record columns.
+///
+/// `duration` is in seconds, `prot_name` is the protocol name whatever the protocol format,
+/// and `bytes_per_packet` is the average packet size.
+const DERIVED: [&str; 3] = ["duration", "prot_name", "bytes_per_packet"];
+
+/// Selected fields of the flow records, and the formats of their values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordFields {
+    // Record field and the key it is written under, in output order
+    fields: Vec<(&'static str, String)>,
+    timestamps: TimestampFormat,
+    protocol: ProtocolFormat,
+}
+
+impl RecordFields {
+    /// Parses the field selection, a comma separated list of record or derived fields each
+    /// optionally renamed with `field=key`. The record columns are selected when none is given.
+    pub fn new(
+        selection: Option<&str>,
+        timestamps: TimestampFormat,
+        protocol: ProtocolFormat,
+    ) -> Result<Self, FluereError> {
+        let fields = match selection {
+            Some(selection) => selection
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(parse_field)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => HEADER
+                .iter()
+                .map(|field| (*field, field.to_string()))
+                .collect(),
+        };
+
+        Ok(Self {
+            fields,
+            timestamps,
+            protocol,
+        })
+    }
+
+    /// Names the fields are written under.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(_, key)| key.as_str())
+    }
+
+    /// Keys and values of the record, in output order.
+    pub fn values<'a>(
+        &'a self,
+        record: &'a FluereRecord,
+    ) -> impl Iterator<Item = (&'a str, Value)> + 'a {
+        self.fields
+            .iter()
+            .map(move |(field, key)| (key.as_str(), self.value(field, record)))
+    }
+
+    fn value(&self, field: &str, record: &FluereRecord) -> Value {
+        match field {
+            "source" => record.source.to_string().into(),
+            "destination" => record.destination.to_string().into(),
+            "src_port" => record.src_port.into(),
+            "dst_port" => record.dst_port.into(),
+            "prot" => self.protocol_value(record.prot),
+            "d_pkts" => record.d_pkts.into(),
+            "d_octets" => record.d_octets.into(),
+            "in_pkts" => record.in_pkts.into(),
+            "out_pkts" => record.out_pkts.into(),
+            "in_bytes" => record.in_bytes.into(),
+            "out_bytes" => record.out_bytes.into(),
+            "first" => self.timestamp_value(record.first),
+            "last" => self.timestamp_value(record.last),
+            "min_pkt" => record.min_pkt.into(),
+            "max_pkt" => record.max_pkt.into(),
+            "min_ttl" => record.min_ttl.into(),
+            "max_ttl" => record.max_ttl.into(),
+            "fin_cnt" => record.fin_cnt.into(),
+            "syn_cnt" => record.syn_cnt.into(),
+            "rst_cnt" => record.rst_cnt.into(),
+            "psh_cnt" => record.psh_cnt.into(),
+            "ack_cnt" => record.ack_cnt.into(),
+            "urg_cnt" => record.urg_cnt.into(),
+            "ece_cnt" => record.ece_cnt.into(),
+            "cwr_cnt" => record.cwr_cnt.into(),
+            "ns_cnt" => record.ns_cnt.into(),
+            "tos" => record.tos.into(),
+            "flow_label" => record.flow_label.into(),
+            "outer_vlan" => record.outer_vlan.into(),
+            "inner_vlan" => record.inner_vlan.into(),
+            "tunnel_type" => record.tunnel_type.as_str().into(),
+            "tunnel_src" => record.tunnel_src.to_string().into(),
+            "tunnel_dst" => record.tunnel_dst.to_string().into(),
+            "tunnel_id" => record.tunnel_id.into(),
+            "end_reason" => record.end_reason.as_str().into(),
+            "exporter" => record.exporter.to_string().into(),
+            "duration" => (record.last.saturating_sub(record.first) as f64 / 1_000_000.0).into(),
+            "prot_name" => protocol_name(record.prot).into(),
+            "bytes_per_packet" => match record.d_pkts {
+                0 => 0.into(),
+                packets => (record.d_octets as f64 / packets as f64).into(),
+            },
+            _ => Value::Null,
+        }
+    }
+
+    fn timestamp_value(&self, micros: u64) -> Value {
+        let time = DateTime::from_timestamp_micros(micros as i64);
+        match (self.timestamps, time) {
+            (TimestampFormat::Micros, _) | (_, None) => micros.into(),
+            (TimestampFormat::Seconds, _) => (micros as f64 / 1_000_000.0).into(),
+            (TimestampFormat::Rfc3339, Some(time)) => {
+                time.to_rfc3339_opts(SecondsFormat::Micros, true).into()
+            }
+            (TimestampFormat::Local, Some(time)) => time
+                .with_timezone(&Local)
+                .to_rfc3339_opts(SecondsFormat::Micros, false)
+                .into(),
+        }
+    }
+
+    fn protocol_value(&self, prot: u8) -> Value {
+        match self.protocol {
+            ProtocolFormat::Number => prot.into(),
+            ProtocolFormat::Name => protocol_name(prot).into(),
+        }
+    }
+}
+
+// Unnamed protocols fall back to their number
+fn protocol_name(prot: u8) -> String {
+    match Protocol::from_u8(prot).name() {
+        Some(name) => name.to_string(),
+        None => prot.to_string(),
+    }
+}
+
+fn parse_field(spec: &str) -> Result<(&'static str, String), FluereError> {
+    let (name, key) = match spec.split_once('=') {
+        Some((name, key)) => (name.trim(), key.trim()),
+        None => (spec, spec),
+    };
+    let field = HEADER
+        .iter()
+        .chain(DERIVED.iter())
+        .find(|field| **field == name)
+        .ok_or_else(|| FluereError::ConfigError(format!("Unknown flow field: {}", name)))?;
+
+    Ok((field, key.to_string()))
+}
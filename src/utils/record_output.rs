@@ -0,0 +1,409 @@
+// This file contains the destination shared by the exporters writing flow records: files in a
+// directory, started anew at every rotation, or the standard output.
+// Besides the export interval, a file is rotated once it reaches a size or record count. Finished
+// files are compressed, and the oldest are deleted past a number of files or an age.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{FluereError, types::CompressionFormat};
+
+use chrono::{
+    Local,
+    format::{Item, StrftimeItems},
+};
+use flate2::write::GzEncoder;
+use log::debug;
+
+// Name of rotated files unless a template is given
+const DEFAULT_TEMPLATE: &str = "{name}_%Y-%m-%d_%H-%M-%S";
+
+/// Naming, rotation and retention of the files of an output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    /// strftime pattern of rotated file names, with `{name}`, `{hostname}` and `{interface}`.
+    pub template: Option<String>,
+    pub interface: Option<String>,
+    /// A new file is started once one reaches this size, in bytes, or this number of records.
+    pub max_bytes: Option<u64>,
+    pub max_records: Option<u64>,
+    pub compression: CompressionFormat,
+    /// Finished files kept in the directory, the oldest deleted first.
+    pub max_files: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+impl FileOptions {
+    /// Checks the template, which would otherwise only fail when the first file is opened.
+    pub fn validate(&self) -> Result<(), FluereError> {
+        match self.template.as_deref() {
+            Some(template) if StrftimeItems::new(template).any(|item| item == Item::Error) => Err(
+                FluereError::ConfigError(format!("Invalid file name template: {}", template)),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where an exporter writes its records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordOutput {
+    /// `<directory>/<template><extension>` files, a new one at every rotation.
+    Timestamped {
+        directory: String,
+        name: String,
+        extension: &'static str,
+        options: FileOptions,
+    },
+    /// A single `<directory>/<name><extension>` file, followed by `<name>_<n><extension>` ones
+    /// when it reaches its size or record count.
+    File {
+        directory: String,
+        name: String,
+        extension: &'static str,
+        options: FileOptions,
+    },
+    Stdout,
+}
+
+/// Writer of an opened output, counting the bytes written through it.
+pub struct OutputWriter {
+    inner: Box<dyn Write + Send>,
+    written: u64,
+}
+
+impl OutputWriter {
+    fn new(inner: Box<dyn Write + Send>) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl RecordOutput {
+    /// Creates the `part`th file of the output, counted from 0, returning its path ("stdout"
+    /// for the standard output) and writer.
+    pub fn open(&self, part: u32) -> io::Result<(String, OutputWriter)> {
+        let path = match self {
+            RecordOutput::Timestamped {
+                directory,
+                name,
+                extension,
+                options,
+            } => {
+                let template = options.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+                let file = render(template, name, options.interface.as_deref())?;
+                unused_path(&format!("{}/{}", directory, file), extension)
+            }
+            RecordOutput::File {
+                directory,
+                name,
+                extension,
+                ..
+            } => match part {
+                0 => format!("{}/{}{}", directory, name, extension),
+                part => format!("{}/{}_{}{}", directory, name, part, extension),
+            },
+            RecordOutput::Stdout => {
+                return Ok((
+                    "stdout".to_string(),
+                    OutputWriter::new(Box::new(io::stdout())),
+                ));
+            }
+        };
+        // Templates may place files in subdirectories, e.g. `%Y/%m/%d/{name}`
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // FIX:TASK: there is a possibility of a permission error
+        // | need to check, if it is a permission error and handle it
+        let file = fs::File::create(&path)?;
+
+        Ok((path, OutputWriter::new(Box::new(BufWriter::new(file)))))
+    }
+
+    /// Description of the output for logs, before it is opened.
+    pub fn name(&self) -> String {
+        match self {
+            RecordOutput::Timestamped {
+                directory, name, ..
+            }
+            | RecordOutput::File {
+                directory, name, ..
+            } => format!("{}/{}", directory, name),
+            RecordOutput::Stdout => "stdout".to_string(),
+        }
+    }
+
+    /// Whether a rotation starts a new file.
+    pub fn rotates(&self) -> bool {
+        matches!(self, RecordOutput::Timestamped { .. })
+    }
+
+    /// Whether a file holding `bytes` and `records` is to be followed by a new one.
+    pub fn is_full(&self, bytes: u64, records: u64) -> bool {
+        self.options().is_some_and(|options| {
+            options.max_bytes.is_some_and(|max| bytes >= max)
+                || options.max_records.is_some_and(|max| records >= max)
+        })
+    }
+
+    /// Compresses the closed file at `path`, then deletes the files past the retention limits.
+    pub fn finish(&self, path: &str) -> io::Result<()> {
+        let Some(options) = self.options() else {
+            return Ok(());
+        };
+        if options.compression != CompressionFormat::None {
+            compress(path, options.compression)?;
+        }
+        if options.max_files.is_some() || options.max_age.is_some() {
+            self.prune(path, options)?;
+        }
+        Ok(())
+    }
+
+    fn options(&self) -> Option<&FileOptions> {
+        match self {
+            RecordOutput::Timestamped { options, .. } | RecordOutput::File { options, .. } => {
+                Some(options)
+            }
+            RecordOutput::Stdout => None,
+        }
+    }
+
+    // Deletes the oldest files of the output, found next to the one just finished
+    fn prune(&self, path: &str, options: &FileOptions) -> io::Result<()> {
+        let (prefix, extension) = match self {
+            RecordOutput::Timestamped {
+                name,
+                extension,
+                options,
+                ..
+            } => {
+                let template = options.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+                let file = template.rsplit('/').next().unwrap_or(template);
+                let prefix = substitute(file, name, options.interface.as_deref());
+                // The literal part of the name, before the time
+                let prefix = prefix.split('%').next().unwrap_or("").to_string();
+                (prefix, *extension)
+            }
+            RecordOutput::File {
+                name, extension, ..
+            } => (name.clone(), *extension),
+            RecordOutput::Stdout => return Ok(()),
+        };
+        let Some(directory) = Path::new(path).parent() else {
+            return Ok(());
+        };
+
+        let mut files: Vec<(SystemTime, String)> = fs::read_dir(directory)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let file = entry.file_name().into_string().ok()?;
+                let ours = file.starts_with(&prefix)
+                    && [
+                        CompressionFormat::None,
+                        CompressionFormat::Gzip,
+                        CompressionFormat::Zstd,
+                    ]
+                    .iter()
+                    .any(|compression| {
+                        file.ends_with(&(extension.to_string() + compression.extension()))
+                    });
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                ours.then_some((modified, file))
+            })
+            .collect();
+        // Newest first
+        files.sort_by(|a, b| b.cmp(a));
+
+        let now = SystemTime::now();
+        for (index, (modified, file)) in files.iter().enumerate() {
+            let too_many = options.max_files.is_some_and(|max| index >= max);
+            let too_old = options
+                .max_age
+                .is_some_and(|max| now.duration_since(*modified).is_ok_and(|age| age > max));
+            if too_many || too_old {
+                fs::remove_file(directory.join(file))?;
+                debug!("Removed old export {}", file);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replaces the placeholders of the template, escaping their values from strftime
+fn substitute(template: &str, name: &str, interface: Option<&str>) -> String {
+    template
+        .replace("{name}", &name.replace('%', "%%"))
+        .replace("{hostname}", &hostname().replace('%', "%%"))
+        .replace(
+            "{interface}",
+            &interface.unwrap_or("any").replace('%', "%%"),
+        )
+}
+
+fn render(template: &str, name: &str, interface: Option<&str>) -> io::Result<String> {
+    let mut file = String::new();
+    write!(
+        file,
+        "{}",
+        Local::now().format(&substitute(template, name, interface))
+    )
+    .map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid file name template: {}", template),
+        )
+    })?;
+    Ok(file)
+}
+
+// Path not taken by an earlier file, compressed or not, numbered if needed
+fn unused_path(stem: &str, extension: &str) -> String {
+    let taken = |path: &str| {
+        [
+            CompressionFormat::None,
+            CompressionFormat::Gzip,
+            CompressionFormat::Zstd,
+        ]
+        .iter()
+        .any(|compression| Path::new(&format!("{}{}", path, compression.extension())).exists())
+    };
+
+    let mut path = format!("{}{}", stem, extension);
+    let mut part = 0;
+    while taken(&path) {
+        part += 1;
+        path = format!("{}_{}{}", stem, part, extension);
+    }
+    path
+}
+
+// Replaces the file with its compressed copy
+fn compress(path: &str, compression: CompressionFormat) -> io::Result<()> {
+    let compressed = format!("{}{}", path, compression.extension());
+    let mut input = fs::File::open(path)?;
+    let output = BufWriter::new(fs::File::create(&compressed)?);
+    let mut output = match compression {
+        CompressionFormat::Gzip => {
+            let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        CompressionFormat::Zstd => {
+            let mut encoder = zstd::Encoder::new(output, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?
+        }
+        CompressionFormat::None => return Ok(()),
+    };
+    output.flush()?;
+    fs::remove_file(path)?;
+
+    debug!("Compressed {} to {}", path, compressed);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer outlives the call, which writes at most its length
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    let end = buffer.iter().position(|byte| *byte == 0).unwrap_or(0);
+    match (result, std::str::from_utf8(&buffer[..end])) {
+        (0, Ok(hostname)) if !hostname.is_empty() => hostname.to_string(),
+        _ => "localhost".to_string(),
+    }
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn test_finished_files_are_compressed_and_pruned() {
+        let directory = std::env::temp_dir()
+            .join(format!("fluere-output-{}", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let output = RecordOutput::Timestamped {
+            directory: directory.clone(),
+            name: "flows".to_string(),
+            extension: ".csv",
+            options: FileOptions {
+                template: Some("{name}_{interface}_%Y".to_string()),
+                interface: Some("eth0".to_string()),
+                max_records: Some(2),
+                compression: CompressionFormat::Gzip,
+                max_files: Some(1),
+                ..FileOptions::default()
+            },
+        };
+        assert!(!output.is_full(10, 1));
+        assert!(output.is_full(10, 2));
+
+        let year = Local::now().format("%Y").to_string();
+        let mut paths = Vec::new();
+        for contents in ["first", "second"] {
+            let (path, mut writer) = output.open(paths.len() as u32).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+            writer.flush().unwrap();
+            assert_eq!(writer.written(), contents.len() as u64);
+            drop(writer);
+            output.finish(&path).unwrap();
+            paths.push(path);
+        }
+
+        // Same name within the same year, so the second file is numbered
+        assert_eq!(
+            paths,
+            vec![
+                format!("{}/flows_eth0_{}.csv", directory, year),
+                format!("{}/flows_eth0_{}_1.csv", directory, year),
+            ]
+        );
+        // Only the newest file is kept, compressed
+        let mut remaining: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        remaining.sort();
+        assert_eq!(remaining, vec![format!("flows_eth0_{}_1.csv.gz", year)]);
+
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(format!("{}.gz", paths[1])).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(contents, "second");
+    }
+}
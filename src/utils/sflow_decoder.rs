@@ -0,0 +1,343 @@
+// This file contains the sFlow v5 decoder of the collector mode.
+// sFlow agents do not export flows: flow samples carry the first bytes of one packet out of
+// every `rate`, which the collector turns into flows with the packet parsers, and counter
+// samples carry the interface statistics. Both are XDR encoded, every field padded to 4 bytes.
+
+use std::net::IpAddr;
+
+use super::netflow_decoder::{address, read_uint, slice};
+use crate::FluereError;
+
+use log::trace;
+use serde::Serialize;
+
+const SFLOW_VERSION: u32 = 5;
+// Standard sFlow data formats, enterprise 0
+const FLOW_SAMPLE: u32 = 1;
+const COUNTER_SAMPLE: u32 = 2;
+const EXPANDED_FLOW_SAMPLE: u32 = 3;
+const EXPANDED_COUNTER_SAMPLE: u32 = 4;
+const RAW_PACKET_HEADER: u32 = 1;
+const GENERIC_INTERFACE_COUNTERS: u32 = 1;
+// Header protocols of raw packet headers
+const HEADER_ETHERNET: u32 = 1;
+const HEADER_IPV4: u32 = 11;
+const HEADER_IPV6: u32 = 12;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERNET_HEADER_LENGTH: usize = 14;
+
+/// Packet header sampled by an agent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketSample {
+    /// Data source the packet was sampled on, type in the top 8 bits and index in the others
+    pub source_id: u32,
+    /// One packet out of this many was sampled
+    pub rate: u32,
+    pub input: u32,
+    pub output: u32,
+    /// Length of the packet before it was truncated to the header
+    pub frame_length: u32,
+    /// Ethernet frame, starting with a blank Ethernet header for IP headers
+    pub header: Vec<u8>,
+}
+
+/// Generic interface counters of an agent's interface, as found in counter samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct InterfaceCounters {
+    /// Time the counters were received, in microseconds since the epoch
+    pub time: u64,
+    pub agent: IpAddr,
+    pub if_index: u32,
+    pub if_type: u32,
+    pub if_speed: u64,
+    pub if_direction: u32,
+    pub if_status: u32,
+    pub in_octets: u64,
+    pub in_ucast_pkts: u32,
+    pub in_multicast_pkts: u32,
+    pub in_broadcast_pkts: u32,
+    pub in_discards: u32,
+    pub in_errors: u32,
+    pub in_unknown_protos: u32,
+    pub out_octets: u64,
+    pub out_ucast_pkts: u32,
+    pub out_multicast_pkts: u32,
+    pub out_broadcast_pkts: u32,
+    pub out_discards: u32,
+    pub out_errors: u32,
+    pub promiscuous_mode: u32,
+}
+
+/// Samples of an sFlow v5 datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SflowDatagram {
+    /// Address of the agent that sampled them
+    pub agent: IpAddr,
+    pub packets: Vec<PacketSample>,
+    pub counters: Vec<InterfaceCounters>,
+}
+
+impl SflowDatagram {
+    /// Decodes a datagram received at `time` (in microseconds). Samples and records of other
+    /// formats are skipped.
+    pub fn decode(datagram: &[u8], time: u64) -> Result<Self, FluereError> {
+        let mut reader = Reader::new(datagram);
+        let version = reader.u32()?;
+        if version != SFLOW_VERSION {
+            return Err(FluereError::ParseError(format!(
+                "Unsupported sFlow version: {}",
+                version
+            )));
+        }
+        let agent = match reader.u32()? {
+            1 => address(reader.bytes(4)?),
+            2 => address(reader.bytes(16)?),
+            address_type => {
+                return Err(FluereError::ParseError(format!(
+                    "Unknown sFlow agent address type: {}",
+                    address_type
+                )));
+            }
+        };
+        // Sub-agent ID, sequence number and uptime
+        reader.bytes(12)?;
+
+        let mut decoded = Self {
+            agent,
+            packets: Vec::new(),
+            counters: Vec::new(),
+        };
+        for _ in 0..reader.u32()? {
+            let format = reader.u32()?;
+            let length = reader.u32()? as usize;
+            let mut sample = Reader::new(reader.opaque(length)?);
+            match format {
+                FLOW_SAMPLE | EXPANDED_FLOW_SAMPLE => {
+                    decoded.packets.extend(flow_sample(&mut sample, format)?)
+                }
+                COUNTER_SAMPLE | EXPANDED_COUNTER_SAMPLE => decoded
+                    .counters
+                    .extend(counter_sample(&mut sample, format, agent, time)?),
+                format => trace!("Skipping sFlow sample format {:#x}", format),
+            }
+        }
+
+        Ok(decoded)
+    }
+}
+
+// Packet headers of a flow sample
+fn flow_sample(sample: &mut Reader, format: u32) -> Result<Vec<PacketSample>, FluereError> {
+    // Sequence number
+    sample.u32()?;
+    let source_id = match format {
+        EXPANDED_FLOW_SAMPLE => (sample.u32()? << 24) | (sample.u32()? & 0x00ff_ffff),
+        _ => sample.u32()?,
+    };
+    let rate = sample.u32()?;
+    // Sample pool and drops
+    sample.bytes(8)?;
+    let (input, output) = match format {
+        // Interface formats precede the values
+        EXPANDED_FLOW_SAMPLE => {
+            sample.u32()?;
+            let input = sample.u32()?;
+            sample.u32()?;
+            (input, sample.u32()?)
+        }
+        _ => (sample.u32()?, sample.u32()?),
+    };
+
+    let mut packets = Vec::new();
+    for _ in 0..sample.u32()? {
+        let format = sample.u32()?;
+        let length = sample.u32()? as usize;
+        let mut record = Reader::new(sample.opaque(length)?);
+        if format != RAW_PACKET_HEADER {
+            trace!("Skipping sFlow flow record format {:#x}", format);
+            continue;
+        }
+
+        let protocol = record.u32()?;
+        let frame_length = record.u32()?;
+        // Bytes stripped from the packet, e.g. the Ethernet FCS
+        record.u32()?;
+        let length = record.u32()? as usize;
+        let Some(header) = ethernet_frame(protocol, record.opaque(length)?) else {
+            trace!("Skipping sFlow header protocol {}", protocol);
+            continue;
+        };
+        packets.push(PacketSample {
+            source_id,
+            rate,
+            input,
+            output,
+            frame_length,
+            header,
+        });
+    }
+
+    Ok(packets)
+}
+
+// Generic interface counters of a counter sample
+fn counter_sample(
+    sample: &mut Reader,
+    format: u32,
+    agent: IpAddr,
+    time: u64,
+) -> Result<Vec<InterfaceCounters>, FluereError> {
+    // Sequence number and source ID
+    sample.bytes(match format {
+        EXPANDED_COUNTER_SAMPLE => 12,
+        _ => 8,
+    })?;
+
+    let mut counters = Vec::new();
+    for _ in 0..sample.u32()? {
+        let format = sample.u32()?;
+        let length = sample.u32()? as usize;
+        let mut record = Reader::new(sample.opaque(length)?);
+        if format != GENERIC_INTERFACE_COUNTERS {
+            trace!("Skipping sFlow counter record format {:#x}", format);
+            continue;
+        }
+
+        counters.push(InterfaceCounters {
+            time,
+            agent,
+            if_index: record.u32()?,
+            if_type: record.u32()?,
+            if_speed: record.u64()?,
+            if_direction: record.u32()?,
+            if_status: record.u32()?,
+            in_octets: record.u64()?,
+            in_ucast_pkts: record.u32()?,
+            in_multicast_pkts: record.u32()?,
+            in_broadcast_pkts: record.u32()?,
+            in_discards: record.u32()?,
+            in_errors: record.u32()?,
+            in_unknown_protos: record.u32()?,
+            out_octets: record.u64()?,
+            out_ucast_pkts: record.u32()?,
+            out_multicast_pkts: record.u32()?,
+            out_broadcast_pkts: record.u32()?,
+            out_discards: record.u32()?,
+            out_errors: record.u32()?,
+            promiscuous_mode: record.u32()?,
+        });
+    }
+
+    Ok(counters)
+}
+
+// The header as an Ethernet frame, which the packet parsers start from
+fn ethernet_frame(protocol: u32, header: &[u8]) -> Option<Vec<u8>> {
+    let ethertype = match protocol {
+        HEADER_ETHERNET => return Some(header.to_vec()),
+        HEADER_IPV4 => ETHERTYPE_IPV4,
+        HEADER_IPV6 => ETHERTYPE_IPV6,
+        _ => return None,
+    };
+    let mut frame = vec![0; ETHERNET_HEADER_LENGTH - 2];
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(header);
+    Some(frame)
+}
+
+// Reads XDR fields one after the other
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], FluereError> {
+        let bytes = slice(self.data, self.offset, length)?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    // Variable-length data, padded to a multiple of 4 bytes
+    fn opaque(&mut self, length: usize) -> Result<&'a [u8], FluereError> {
+        let bytes = self.bytes(length)?;
+        self.offset += length.next_multiple_of(4) - length;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, FluereError> {
+        Ok(read_uint(self.bytes(4)?) as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, FluereError> {
+        Ok(read_uint(self.bytes(8)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect()
+    }
+
+    fn sample(format: u32, content: Vec<u8>) -> Vec<u8> {
+        let mut sample = words(&[format, content.len() as u32]);
+        sample.extend(content);
+        sample
+    }
+
+    #[test]
+    fn test_flow_and_counter_samples_are_decoded() {
+        // IPv4 UDP header, 3 bytes short of a multiple of 4
+        let header = [
+            0x45, 0x00, 0x05, 0xdc, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x30, 0x39, 0x00, 0x35, 0x05, 0xc8, 0x00,
+        ];
+        let mut raw = words(&[HEADER_IPV4, 1500, 0, header.len() as u32]);
+        raw.extend_from_slice(&header);
+        raw.push(0);
+        let mut flow = words(&[7, 3, 512, 1024, 0, 1, 2, 1]);
+        flow.extend(sample(RAW_PACKET_HEADER, raw));
+
+        let mut interface = words(&[3, 6, 0, 1_000_000_000, 1, 3, 0, 4096]);
+        interface.extend(words(&[40, 1, 2, 0, 0, 0, 0, 8192, 80, 3, 4, 5, 6, 2]));
+        let mut counters = words(&[8, 3, 1]);
+        counters.extend(sample(GENERIC_INTERFACE_COUNTERS, interface));
+
+        let mut datagram = words(&[5, 1, 0xc0a8_0001, 0, 42, 1000, 2]);
+        datagram.extend(sample(FLOW_SAMPLE, flow));
+        datagram.extend(sample(COUNTER_SAMPLE, counters));
+
+        let decoded = SflowDatagram::decode(&datagram, 1_000_000).unwrap();
+        assert_eq!(decoded.agent, IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(decoded.packets.len(), 1);
+        let packet = &decoded.packets[0];
+        assert_eq!((packet.source_id, packet.rate), (3, 512));
+        assert_eq!((packet.input, packet.output), (1, 2));
+        assert_eq!(packet.frame_length, 1500);
+        assert_eq!(packet.header.len(), ETHERNET_HEADER_LENGTH + header.len());
+        assert_eq!(packet.header[12..14], [0x08, 0x00]);
+
+        assert_eq!(decoded.counters.len(), 1);
+        let interface = decoded.counters[0];
+        assert_eq!(interface.agent, decoded.agent);
+        assert_eq!(interface.if_index, 3);
+        assert_eq!(interface.if_speed, 1_000_000_000);
+        assert_eq!(interface.in_octets, 4096);
+        assert_eq!(interface.out_octets, 8192);
+        assert_eq!(interface.out_errors, 6);
+        assert_eq!(interface.promiscuous_mode, 2);
+    }
+}
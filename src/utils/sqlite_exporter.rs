@@ -0,0 +1,283 @@
+// This file contains the SQLite exporter, which stores flow records in the `flows` table of a
+// local database. Records are inserted in a single transaction at every export interval, and
+// rows older than the retention period are pruned afterwards.
+// Like the NetFlow exporters, the clock is the packet time, so converting an old capture does
+// not prune its own flows.
+
+use std::{fs, io, path::Path};
+
+use super::{FlowExporter, fluere_csv_exporter::HEADER};
+
+use fluereflow::FluereRecord;
+use log::{debug, info};
+use rusqlite::{Connection, params};
+
+// Records held before they are inserted without waiting for the export interval
+const MAX_PENDING: usize = 65_536;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS flows (
+    id INTEGER PRIMARY KEY,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    src_port INTEGER NOT NULL,
+    dst_port INTEGER NOT NULL,
+    prot INTEGER NOT NULL,
+    d_pkts INTEGER NOT NULL,
+    d_octets INTEGER NOT NULL,
+    in_pkts INTEGER NOT NULL,
+    out_pkts INTEGER NOT NULL,
+    in_bytes INTEGER NOT NULL,
+    out_bytes INTEGER NOT NULL,
+    first INTEGER NOT NULL,
+    last INTEGER NOT NULL,
+    min_pkt INTEGER NOT NULL,
+    max_pkt INTEGER NOT NULL,
+    min_ttl INTEGER NOT NULL,
+    max_ttl INTEGER NOT NULL,
+    fin_cnt INTEGER NOT NULL,
+    syn_cnt INTEGER NOT NULL,
+    rst_cnt INTEGER NOT NULL,
+    psh_cnt INTEGER NOT NULL,
+    ack_cnt INTEGER NOT NULL,
+    urg_cnt INTEGER NOT NULL,
+    ece_cnt INTEGER NOT NULL,
+    cwr_cnt INTEGER NOT NULL,
+    ns_cnt INTEGER NOT NULL,
+    tos INTEGER NOT NULL,
+    flow_label INTEGER NOT NULL,
+    outer_vlan INTEGER NOT NULL,
+    inner_vlan INTEGER NOT NULL,
+    tunnel_type TEXT NOT NULL,
+    tunnel_src TEXT NOT NULL,
+    tunnel_dst TEXT NOT NULL,
+    tunnel_id INTEGER NOT NULL,
+    end_reason TEXT NOT NULL,
+    exporter TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS flows_first ON flows (first);
+CREATE INDEX IF NOT EXISTS flows_last ON flows (last);
+CREATE INDEX IF NOT EXISTS flows_source ON flows (source);
+CREATE INDEX IF NOT EXISTS flows_destination ON flows (destination);
+CREATE INDEX IF NOT EXISTS flows_src_port ON flows (src_port);
+CREATE INDEX IF NOT EXISTS flows_dst_port ON flows (dst_port);
+";
+
+/// Stores flow records in a SQLite database.
+pub struct SqliteExporter {
+    path: String,
+    // Rows whose last packet is older than this, in microseconds, are pruned
+    retention: Option<u64>,
+    connection: Option<Connection>,
+    insert: String,
+    pending: Vec<FluereRecord>,
+    // Last packet time of the records inserted so far
+    newest: u64,
+}
+
+impl SqliteExporter {
+    /// Exporter to the database file, keeping flows for `retention_hours` if given.
+    pub fn new(path: &str, retention_hours: Option<u64>) -> Self {
+        let placeholders: Vec<String> = (1..=HEADER.len()).map(|i| format!("?{}", i)).collect();
+        let insert = format!(
+            "INSERT INTO flows ({}) VALUES ({})",
+            HEADER.join(", "),
+            placeholders.join(", ")
+        );
+
+        Self {
+            path: path.to_string(),
+            retention: retention_hours
+                .filter(|hours| *hours > 0)
+                .map(|hours| hours * 3_600 * 1_000_000),
+            connection: None,
+            insert,
+            pending: Vec::new(),
+            newest: 0,
+        }
+    }
+
+    // Inserts the pending records in one transaction, then prunes the expired rows
+    fn commit(&mut self) -> io::Result<()> {
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "SQLite database is not open",
+            ));
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let transaction = connection.transaction().map_err(io::Error::other)?;
+        {
+            let mut statement = transaction
+                .prepare_cached(&self.insert)
+                .map_err(io::Error::other)?;
+            for record in self.pending.iter() {
+                insert_record(&mut statement, record).map_err(io::Error::other)?;
+            }
+        }
+        let newest = self.pending.iter().map(|record| record.last).max();
+        self.newest = self.newest.max(newest.unwrap_or(0));
+        let mut pruned = 0;
+        if let Some(retention) = self.retention {
+            let cutoff = self.newest.saturating_sub(retention);
+            pruned = transaction
+                .execute("DELETE FROM flows WHERE last < ?1", params![cutoff as i64])
+                .map_err(io::Error::other)?;
+        }
+        transaction.commit().map_err(io::Error::other)?;
+
+        debug!(
+            "Inserted {} flows into {}, pruned {}",
+            self.pending.len(),
+            self.path,
+            pruned
+        );
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl FlowExporter for SqliteExporter {
+    fn name(&self) -> String {
+        format!("sqlite {}", self.path)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(parent) = Path::new(&self.path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(&self.path).map_err(io::Error::other)?;
+        connection.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        add_exporter_column(&connection).map_err(io::Error::other)?;
+
+        info!("Export {} Started", self.path);
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        self.pending.extend_from_slice(records);
+        if self.pending.len() >= MAX_PENDING {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let committed = self.commit();
+        if self.connection.take().is_some() {
+            info!("Export {} Finished", self.path);
+        }
+        committed
+    }
+}
+
+fn insert_record(
+    statement: &mut rusqlite::CachedStatement,
+    record: &FluereRecord,
+) -> rusqlite::Result<usize> {
+    statement.execute(params![
+        record.source.to_string(),
+        record.destination.to_string(),
+        record.src_port,
+        record.dst_port,
+        record.prot,
+        record.d_pkts,
+        record.d_octets as i64,
+        record.in_pkts,
+        record.out_pkts,
+        record.in_bytes as i64,
+        record.out_bytes as i64,
+        record.first as i64,
+        record.last as i64,
+        record.min_pkt,
+        record.max_pkt,
+        record.min_ttl,
+        record.max_ttl,
+        record.fin_cnt,
+        record.syn_cnt,
+        record.rst_cnt,
+        record.psh_cnt,
+        record.ack_cnt,
+        record.urg_cnt,
+        record.ece_cnt,
+        record.cwr_cnt,
+        record.ns_cnt,
+        record.tos,
+        record.flow_label,
+        record.outer_vlan,
+        record.inner_vlan,
+        record.tunnel_type.as_str(),
+        record.tunnel_src.to_string(),
+        record.tunnel_dst.to_string(),
+        record.tunnel_id,
+        record.end_reason.as_str(),
+        record.exporter.to_string(),
+    ])
+}
+
+// Databases created before flows were collected from exporters lack their column, captured
+// flows have no exporter
+fn add_exporter_column(connection: &Connection) -> rusqlite::Result<()> {
+    if connection
+        .prepare("SELECT exporter FROM flows LIMIT 0")
+        .is_err()
+    {
+        connection.execute_batch(
+            "ALTER TABLE flows ADD COLUMN exporter TEXT NOT NULL DEFAULT '0.0.0.0'",
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::test_support::udp_flow;
+
+    fn record(host: u8, last: u64) -> FluereRecord {
+        udp_flow([10, 0, 0, host], [192, 168, 0, 1], last)
+    }
+
+    #[test]
+    fn test_intervals_are_inserted_and_pruned() {
+        let path = std::env::temp_dir().join(format!("fluere-{}.sqlite", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut exporter = SqliteExporter::new(path.to_str().unwrap(), Some(1));
+        exporter.open().unwrap();
+
+        let hour = 3_600_000_000;
+        let start = 1_700_000_000_000_000;
+        exporter
+            .write_batch(&[record(1, start), record(2, start + 1)])
+            .unwrap();
+        exporter.rotate().unwrap();
+        // Two hours later, the first interval is past the retention period
+        exporter
+            .write_batch(&[record(3, start + 2 * hour)])
+            .unwrap();
+        exporter.close().unwrap();
+
+        let connection = Connection::open(&path).unwrap();
+        let sources: Vec<String> = connection
+            .prepare("SELECT source FROM flows ORDER BY first")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|source| source.unwrap())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sources, vec!["10.0.0.3".to_string()]);
+    }
+}
@@ -0,0 +1,418 @@
+// This file contains the streaming exporter, which pushes every finished flow record to a
+// consumer over TCP or a Unix domain socket, as a JSON line or a length-prefixed binary frame.
+// A writer thread owns the connection, so that a consumer that is down never holds back the
+// capture: frames wait in a bounded queue, dropping the oldest when full, while the thread
+// reconnects with exponential backoff.
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, TcpStream},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{FlowExporter, JsonlFormat};
+use crate::{FluereError, types::StreamFormat};
+
+use fluereflow::{FlowEndReason, FluereRecord, TunnelType};
+use log::{debug, info, warn};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Version of the binary record layout, its first byte
+const BINARY_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Destination {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+}
+
+impl Destination {
+    fn connect(&self) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            Destination::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(BufWriter::new(stream)))
+            }
+            #[cfg(unix)]
+            Destination::Unix(path) => Ok(Box::new(BufWriter::new(
+                std::os::unix::net::UnixStream::connect(path)?,
+            ))),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Vec<u8>>,
+    // Frames dropped since the last warning, because the queue was full
+    dropped: u64,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        // A panicking writer thread leaves the queue itself consistent
+        self.queue
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Streams flow records to a consumer socket as they finish.
+pub struct StreamExporter {
+    destination: Destination,
+    format: StreamFormat,
+    json: JsonlFormat,
+    // Frames held while the consumer is unreachable
+    capacity: usize,
+    shared: Arc<Shared>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl StreamExporter {
+    /// Exporter to `tcp://host:port` or `unix:///path`, keeping at most `capacity` records
+    /// while the consumer is down.
+    pub fn new(
+        destination: &str,
+        format: StreamFormat,
+        json: JsonlFormat,
+        capacity: usize,
+    ) -> Result<Self, FluereError> {
+        let destination = if let Some(address) = destination.strip_prefix("tcp://") {
+            Destination::Tcp(address.to_string())
+        } else if let Some(path) = destination.strip_prefix("unix://") {
+            unix_destination(path)?
+        } else {
+            return Err(FluereError::ConfigError(format!(
+                "Stream destination must be tcp:// or unix://: {}",
+                destination
+            )));
+        };
+
+        Ok(Self {
+            destination,
+            format,
+            json,
+            capacity: capacity.max(1),
+            shared: Arc::new(Shared::default()),
+            writer: None,
+        })
+    }
+
+    fn encode(&self, record: &FluereRecord) -> io::Result<Vec<u8>> {
+        let mut frame = Vec::new();
+        match self.format {
+            StreamFormat::Json => self.json.write_record(&mut frame, record)?,
+            StreamFormat::Binary => {
+                frame.extend_from_slice(&[0; 4]);
+                write_binary(&mut frame, record);
+                let length = (frame.len() - 4) as u32;
+                frame[0..4].copy_from_slice(&length.to_be_bytes());
+            }
+        }
+        Ok(frame)
+    }
+}
+
+#[cfg(unix)]
+fn unix_destination(path: &str) -> Result<Destination, FluereError> {
+    Ok(Destination::Unix(path.to_string()))
+}
+
+#[cfg(not(unix))]
+fn unix_destination(_path: &str) -> Result<Destination, FluereError> {
+    Err(FluereError::ConfigError(
+        "Unix domain sockets are not available on this platform".to_string(),
+    ))
+}
+
+impl FlowExporter for StreamExporter {
+    fn name(&self) -> String {
+        match &self.destination {
+            Destination::Tcp(address) => format!("stream tcp://{}", address),
+            #[cfg(unix)]
+            Destination::Unix(path) => format!("stream unix://{}", path),
+        }
+    }
+
+    // The writer thread connects in the background, the consumer may come up later
+    fn open(&mut self) -> io::Result<()> {
+        if self.writer.is_some() {
+            return Ok(());
+        }
+        self.shared.lock().closed = false;
+        let destination = self.destination.clone();
+        let shared = self.shared.clone();
+        self.writer = Some(
+            thread::Builder::new()
+                .name("fluere-stream".to_string())
+                .spawn(move || run_writer(destination, shared))?,
+        );
+
+        info!("Export {} Started", self.name());
+        Ok(())
+    }
+
+    fn write_batch(&mut self, records: &[FluereRecord]) -> io::Result<()> {
+        let frames = records
+            .iter()
+            .map(|record| self.encode(record))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut queue = self.shared.lock();
+        for frame in frames {
+            if queue.frames.len() >= self.capacity {
+                queue.frames.pop_front();
+                queue.dropped += 1;
+            }
+            queue.frames.push_back(frame);
+        }
+        drop(queue);
+        self.shared.changed.notify_one();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Sends what the consumer can still take, without waiting for one that is down
+    fn close(&mut self) -> io::Result<()> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        self.shared.lock().closed = true;
+        self.shared.changed.notify_one();
+        if writer.join().is_err() {
+            warn!("Stream writer of {} panicked", self.name());
+        }
+
+        let unsent = self.shared.lock().frames.len();
+        if unsent > 0 {
+            warn!("{} records were not streamed to {}", unsent, self.name());
+        }
+        info!("Export {} Finished", self.name());
+        Ok(())
+    }
+}
+
+// Connects, with backoff while the consumer is down, and writes the queued frames until the
+// exporter closes
+fn run_writer(destination: Destination, shared: Arc<Shared>) {
+    while let Some(mut stream) = connect(&destination, &shared) {
+        loop {
+            let Some(frames) = next_frames(&shared) else {
+                let _ = stream.flush();
+                return;
+            };
+            let mut sent = 0;
+            let result = frames
+                .iter()
+                .try_for_each(|frame| {
+                    stream.write_all(frame)?;
+                    sent += 1;
+                    Ok(())
+                })
+                .and_then(|_: ()| stream.flush());
+            if let Err(err) = result {
+                warn!("Lost stream consumer {:?}: {}", destination, err);
+                // Frames not written wait for the next connection, ahead of newer ones
+                let mut queue = shared.lock();
+                for frame in frames.into_iter().skip(sent).rev() {
+                    queue.frames.push_front(frame);
+                }
+                break;
+            }
+        }
+    }
+}
+
+// Retries until the consumer accepts, or gives up once the exporter is closed
+fn connect(destination: &Destination, shared: &Shared) -> Option<Box<dyn Write + Send>> {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match destination.connect() {
+            Ok(stream) => {
+                debug!("Connected to stream consumer {:?}", destination);
+                return Some(stream);
+            }
+            Err(err) => debug!("Stream consumer {:?} unreachable: {}", destination, err),
+        }
+        let queue = shared.lock();
+        // One attempt is all a closing exporter waits for
+        if queue.closed {
+            return None;
+        }
+        let _ = shared.changed.wait_timeout(queue, backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Waits for queued frames, none are left once the exporter is closed and they are sent
+fn next_frames(shared: &Shared) -> Option<Vec<Vec<u8>>> {
+    let mut queue = shared.lock();
+    while queue.frames.is_empty() && !queue.closed {
+        queue = shared
+            .changed
+            .wait(queue)
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+    if queue.frames.is_empty() {
+        return None;
+    }
+    if queue.dropped > 0 {
+        warn!("Stream buffer full, dropped {} records", queue.dropped);
+        queue.dropped = 0;
+    }
+    Some(queue.frames.drain(..).collect())
+}
+
+// Fields in the order of the CSV header, integers big-endian
+fn write_binary(frame: &mut Vec<u8>, record: &FluereRecord) {
+    frame.push(BINARY_VERSION);
+    write_address(frame, record.source);
+    write_address(frame, record.destination);
+    frame.extend_from_slice(&record.src_port.to_be_bytes());
+    frame.extend_from_slice(&record.dst_port.to_be_bytes());
+    frame.push(record.prot);
+    frame.extend_from_slice(&record.d_pkts.to_be_bytes());
+    frame.extend_from_slice(&(record.d_octets as u64).to_be_bytes());
+    frame.extend_from_slice(&record.in_pkts.to_be_bytes());
+    frame.extend_from_slice(&record.out_pkts.to_be_bytes());
+    frame.extend_from_slice(&(record.in_bytes as u64).to_be_bytes());
+    frame.extend_from_slice(&(record.out_bytes as u64).to_be_bytes());
+    frame.extend_from_slice(&record.first.to_be_bytes());
+    frame.extend_from_slice(&record.last.to_be_bytes());
+    frame.extend_from_slice(&record.min_pkt.to_be_bytes());
+    frame.extend_from_slice(&record.max_pkt.to_be_bytes());
+    frame.extend_from_slice(&[record.min_ttl, record.max_ttl]);
+    for count in [
+        record.fin_cnt,
+        record.syn_cnt,
+        record.rst_cnt,
+        record.psh_cnt,
+        record.ack_cnt,
+        record.urg_cnt,
+        record.ece_cnt,
+        record.cwr_cnt,
+        record.ns_cnt,
+    ] {
+        frame.extend_from_slice(&count.to_be_bytes());
+    }
+    frame.push(record.tos);
+    frame.extend_from_slice(&record.flow_label.to_be_bytes());
+    frame.extend_from_slice(&record.outer_vlan.to_be_bytes());
+    frame.extend_from_slice(&record.inner_vlan.to_be_bytes());
+    frame.push(tunnel_type_code(record.tunnel_type));
+    write_address(frame, record.tunnel_src);
+    write_address(frame, record.tunnel_dst);
+    frame.extend_from_slice(&record.tunnel_id.to_be_bytes());
+    frame.push(end_reason_code(record.end_reason));
+    write_address(frame, record.exporter);
+}
+
+// Address length, 4 or 16, followed by the address
+fn write_address(frame: &mut Vec<u8>, address: IpAddr) {
+    match address {
+        IpAddr::V4(address) => {
+            frame.push(4);
+            frame.extend_from_slice(&address.octets());
+        }
+        IpAddr::V6(address) => {
+            frame.push(16);
+            frame.extend_from_slice(&address.octets());
+        }
+    }
+}
+
+fn tunnel_type_code(tunnel_type: TunnelType) -> u8 {
+    match tunnel_type {
+        TunnelType::None => 0,
+        TunnelType::Vxlan => 1,
+        TunnelType::Geneve => 2,
+        TunnelType::Gre => 3,
+        TunnelType::Nvgre => 4,
+        TunnelType::IpInIp => 5,
+        TunnelType::Ipv6InIp => 6,
+        TunnelType::Mpls => 7,
+    }
+}
+
+fn end_reason_code(reason: FlowEndReason) -> u8 {
+    match reason {
+        FlowEndReason::Unknown => 0,
+        FlowEndReason::IdleTimeout => 1,
+        FlowEndReason::ActiveTimeout => 2,
+        FlowEndReason::TcpFin => 3,
+        FlowEndReason::TcpReset => 4,
+        FlowEndReason::ForcedEnd => 5,
+        FlowEndReason::LackOfResources => 6,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Read, net::TcpListener};
+
+    use crate::types::{ProtocolFormat, TimestampFormat};
+    use crate::utils::test_support::udp_flow;
+
+    #[test]
+    fn test_records_wait_for_late_consumer() {
+        // Reserve a port, then free it so that the first connection attempts fail
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let json = JsonlFormat::new(
+            Some("src_port,dst_port"),
+            TimestampFormat::Micros,
+            ProtocolFormat::Number,
+        )
+        .unwrap();
+        let mut exporter = StreamExporter::new(
+            &format!("tcp://127.0.0.1:{}", port),
+            StreamFormat::Json,
+            json,
+            2,
+        )
+        .unwrap();
+        exporter.open().unwrap();
+
+        let records: Vec<FluereRecord> = (1..=3)
+            .map(|port| FluereRecord {
+                src_port: port,
+                end_reason: FlowEndReason::IdleTimeout,
+                ..udp_flow([10, 0, 0, 1], [10, 0, 0, 2], 0)
+            })
+            .collect();
+        exporter.write_batch(&records).unwrap();
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let (mut consumer, _) = listener.accept().unwrap();
+        exporter.close().unwrap();
+
+        let mut received = String::new();
+        consumer.read_to_string(&mut received).unwrap();
+        // The buffer holds two records, the oldest was dropped
+        assert_eq!(
+            received,
+            "{\"src_port\":2,\"dst_port\":53}\n{\"src_port\":3,\"dst_port\":53}\n"
+        );
+    }
+}
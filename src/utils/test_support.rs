@@ -0,0 +1,68 @@
+// This file contains the records shared by the tests of the exporters and decoders.
+// Tests override the fields they check, e.g. `FluereRecord { d_pkts: 4, ..tcp_flow(a, b, first) }`.
+
+use std::net::IpAddr;
+
+use fluereflow::FluereRecord;
+
+/// HTTPS connection of three packets, lasting a second: the initiator's SYN, and the
+/// responder's SYN-ACK and ACK.
+pub(super) fn tcp_flow(
+    source: impl Into<IpAddr>,
+    destination: impl Into<IpAddr>,
+    first: u64,
+) -> FluereRecord {
+    FluereRecord {
+        d_pkts: 3,
+        d_octets: 180,
+        first,
+        last: first + 1_000_000,
+        src_port: 40000,
+        dst_port: 443,
+        min_pkt: 60,
+        max_pkt: 60,
+        min_ttl: 64,
+        max_ttl: 64,
+        in_pkts: 1,
+        out_pkts: 2,
+        in_bytes: 60,
+        out_bytes: 120,
+        syn_cnt: 1,
+        ack_cnt: 2,
+        prot: 6,
+        ..flow(source, destination)
+    }
+}
+
+/// DNS query of a single packet.
+pub(super) fn udp_flow(
+    source: impl Into<IpAddr>,
+    destination: impl Into<IpAddr>,
+    first: u64,
+) -> FluereRecord {
+    FluereRecord {
+        d_pkts: 1,
+        d_octets: 60,
+        first,
+        last: first,
+        src_port: 40000,
+        dst_port: 53,
+        min_pkt: 60,
+        max_pkt: 60,
+        min_ttl: 64,
+        max_ttl: 64,
+        in_pkts: 1,
+        in_bytes: 60,
+        prot: 17,
+        ..flow(source, destination)
+    }
+}
+
+// Record of a flow between two addresses, with every counter at zero
+fn flow(source: impl Into<IpAddr>, destination: impl Into<IpAddr>) -> FluereRecord {
+    FluereRecord {
+        source: source.into(),
+        destination: destination.into(),
+        ..FluereRecord::default()
+    }
+}